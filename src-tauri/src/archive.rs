@@ -0,0 +1,211 @@
+use crate::migrations::CURRENT_VERSION;
+use crate::persistence::GameSession;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Identifies a JSON document as a history archive of this app.
+pub const ARCHIVE_FORMAT: &str = "nback-history";
+
+/// Every stored session, with its settings and full event history, as one JSON document for
+/// moving history between machines.
+///
+/// Sessions are written in the current storage layout, so `schema_version` follows
+/// [`CURRENT_VERSION`]. Archives from older versions can still be imported, since fields
+/// added since then have defaults. Custom auditory sets are not included: their sound files
+/// are local to each machine, and imported sessions that use one keep referring to it by ID.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryArchive {
+    pub format: String,
+    pub schema_version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub sessions: Vec<GameSession>,
+}
+
+impl HistoryArchive {
+    pub fn new(sessions: Vec<GameSession>) -> Self {
+        Self {
+            format: ARCHIVE_FORMAT.to_string(),
+            schema_version: CURRENT_VERSION,
+            exported_at: Utc::now(),
+            sessions,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String, ArchiveError> {
+        serde_json::to_string_pretty(self).map_err(ArchiveError::Json)
+    }
+
+    /// Parses and validates an archive. Nothing is imported unless every session in it is valid.
+    pub fn from_json(json: &str) -> Result<Self, ArchiveError> {
+        let archive: Self = serde_json::from_str(json).map_err(ArchiveError::Json)?;
+        if archive.format != ARCHIVE_FORMAT {
+            return Err(ArchiveError::UnknownFormat(archive.format));
+        }
+        if archive.schema_version > CURRENT_VERSION {
+            return Err(ArchiveError::UnsupportedVersion(archive.schema_version));
+        }
+        for session in &archive.sessions {
+            validate_session(session)
+                .map_err(|reason| ArchiveError::InvalidSession { id: session.id.clone(), reason })?;
+        }
+        Ok(archive)
+    }
+
+    /// Splits the archive's sessions into new ones and duplicates, by session ID. A session is a
+    /// duplicate if `is_stored` says it is already in the database, or it appeared earlier in
+    /// the archive.
+    pub fn dedup(self, is_stored: impl Fn(&str) -> bool) -> (Vec<GameSession>, usize) {
+        let mut seen = HashSet::new();
+        let mut duplicates = 0;
+        let mut sessions = Vec::new();
+        for session in self.sessions {
+            if is_stored(&session.id) || !seen.insert(session.id.clone()) {
+                duplicates += 1;
+            } else {
+                sessions.push(session);
+            }
+        }
+        (sessions, duplicates)
+    }
+}
+
+fn validate_session(session: &GameSession) -> Result<(), String> {
+    if session.id.is_empty() {
+        return Err("empty session ID".to_string());
+    }
+    session.settings.validate().map_err(|e| e.to_string())?;
+    if session.event_history.len() > session.settings.session_length {
+        return Err(format!(
+            "{} turns recorded for a session of {}",
+            session.event_history.len(),
+            session.settings.session_length
+        ));
+    }
+    if let Some((i, _)) = session.event_history.iter().enumerate().find(|(i, e)| e.turn_index != *i) {
+        return Err(format!("turn {} is out of order", i));
+    }
+    Ok(())
+}
+
+/// Outcome of merging an archive into the history.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    pub imported: usize,
+    /// Sessions skipped because their ID was already in the history.
+    pub duplicates: usize,
+}
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Json(serde_json::Error),
+    UnknownFormat(String),
+    UnsupportedVersion(u32),
+    InvalidSession { id: String, reason: String },
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::Json(e) => write!(f, "Malformed history archive: {}", e),
+            ArchiveError::UnknownFormat(format) => {
+                write!(f, "Not a history archive (format \"{}\")", format)
+            }
+            ArchiveError::UnsupportedVersion(version) => write!(
+                f,
+                "History archive has schema version {}, but this version of the app reads up to {}",
+                version, CURRENT_VERSION
+            ),
+            ArchiveError::InvalidSession { id, reason } => {
+                write!(f, "Session {} in the archive is invalid: {}", id, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameState, UserResponse};
+    use crate::persistence::UserSettings;
+
+    fn played_session(id: &str) -> GameSession {
+        let settings = UserSettings { session_length: 10, ..Default::default() };
+        let mut game_state = GameState::new(settings).unwrap();
+        game_state.start();
+        while game_state.is_active() {
+            game_state.process_turn(UserResponse::default());
+        }
+        let mut session = GameSession::new(
+            game_state.settings.clone(),
+            game_state.seed,
+            game_state.event_history,
+            game_state.stats,
+        );
+        session.id = id.to_string();
+        session
+    }
+
+    #[test]
+    fn test_archive_round_trip_keeps_events() {
+        let session = played_session("session_1");
+        let json = HistoryArchive::new(vec![session.clone()]).to_json().unwrap();
+        let archive = HistoryArchive::from_json(&json).unwrap();
+        let imported = &archive.sessions[0];
+        assert_eq!(imported.id, session.id);
+        assert_eq!(imported.seed, session.seed);
+        assert_eq!(imported.event_history.len(), 10);
+        assert_eq!(imported.event_history[9].stimulus, session.event_history[9].stimulus);
+        assert_eq!(imported.event_history[9].matches, session.event_history[9].matches);
+        assert_eq!(imported.stats, session.stats);
+
+        // Archives from before notes and tags existed are still accepted
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["schema_version"] = 2.into();
+        let session = value["sessions"][0].as_object_mut().unwrap();
+        session.remove("notes");
+        session.remove("tags");
+        let archive = HistoryArchive::from_json(&value.to_string()).unwrap();
+        assert!(archive.sessions[0].tags.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_archives_are_rejected() {
+        assert!(matches!(HistoryArchive::from_json("{"), Err(ArchiveError::Json(_))));
+
+        let mut archive = HistoryArchive::new(vec![]);
+        archive.format = "something-else".to_string();
+        let result = HistoryArchive::from_json(&archive.to_json().unwrap());
+        assert!(matches!(result, Err(ArchiveError::UnknownFormat(_))));
+
+        let mut archive = HistoryArchive::new(vec![]);
+        archive.schema_version = CURRENT_VERSION + 1;
+        let result = HistoryArchive::from_json(&archive.to_json().unwrap());
+        assert!(matches!(result, Err(ArchiveError::UnsupportedVersion(_))));
+
+        let mut session = played_session("session_1");
+        session.event_history.swap(1, 2);
+        let json = HistoryArchive::new(vec![played_session("session_0"), session]).to_json().unwrap();
+        match HistoryArchive::from_json(&json) {
+            Err(ArchiveError::InvalidSession { id, .. }) => assert_eq!(id, "session_1"),
+            other => panic!("expected an invalid session, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dedup_by_session_id() {
+        let archive = HistoryArchive::new(vec![
+            played_session("session_1"),
+            played_session("session_2"),
+            played_session("session_2"),
+            played_session("session_3"),
+        ]);
+        let (sessions, duplicates) = archive.dedup(|id| id == "session_1");
+        let ids: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["session_2", "session_3"]);
+        assert_eq!(duplicates, 2);
+    }
+}
@@ -0,0 +1,254 @@
+//! Snapshots of the whole store, kept in the `backups` directory of the app data.
+//!
+//! A scheduled backup is taken once a day, and one before every command that replaces or
+//! removes data, so a wrong click can be undone with `restore_backup`. Each kind is rotated on
+//! its own: a series of imports never pushes the daily backups out.
+
+use crate::storage::{self, CopyReport, Storage, StorageError};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// Directory of the backups inside the app-data directory.
+pub const BACKUP_DIR: &str = "backups";
+/// Backups kept of each kind; the oldest ones are deleted as new ones are taken.
+pub const KEEP_BACKUPS: usize = 7;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackupReason {
+    Scheduled,
+    BeforeReset,
+    BeforeImport,
+    BeforeRestore,
+}
+
+impl BackupReason {
+    const ALL: [BackupReason; 4] =
+        [BackupReason::Scheduled, BackupReason::BeforeReset, BackupReason::BeforeImport, BackupReason::BeforeRestore];
+
+    /// Start of the file names of this kind of backup.
+    fn prefix(self) -> &'static str {
+        match self {
+            BackupReason::Scheduled => "scheduled",
+            BackupReason::BeforeReset => "before-reset",
+            BackupReason::BeforeImport => "before-import",
+            BackupReason::BeforeRestore => "before-restore",
+        }
+    }
+}
+
+/// A backup in the backup directory. Its ID is its file name there.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BackupInfo {
+    pub id: String,
+    pub reason: BackupReason,
+    pub created_at: DateTime<Utc>,
+    pub size_bytes: u64,
+}
+
+impl BackupInfo {
+    /// Reads the reason and time from a backup's file name. Other files give `None`.
+    fn parse(id: &str) -> Option<(BackupReason, DateTime<Utc>)> {
+        let (prefix, timestamp) = id.rsplit_once('-')?;
+        let reason = BackupReason::ALL.into_iter().find(|r| r.prefix() == prefix)?;
+        let created_at = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?.and_utc();
+        Some((reason, created_at))
+    }
+}
+
+/// Snapshots the store into `dir`, then deletes the oldest backups of the same kind beyond
+/// [`KEEP_BACKUPS`]. The snapshot is written under a temporary name first, so an interrupted
+/// backup is never listed.
+pub fn create_backup(
+    db: &dyn Storage,
+    dir: &Path,
+    reason: BackupReason,
+    now: DateTime<Utc>,
+) -> Result<BackupInfo, BackupError> {
+    std::fs::create_dir_all(dir)?;
+    let id = format!("{}-{}", reason.prefix(), now.format(TIMESTAMP_FORMAT));
+    let path = dir.join(&id);
+    if path.exists() {
+        return Err(BackupError::AlreadyExists(id));
+    }
+    let partial = dir.join(format!("{}.partial", id));
+    remove_path(&partial)?;
+    db.snapshot(&partial)?;
+    std::fs::rename(&partial, &path)?;
+
+    for old in list_backups(dir)?.iter().filter(|b| b.reason == reason).skip(KEEP_BACKUPS) {
+        remove_path(&dir.join(&old.id))?;
+    }
+    Ok(BackupInfo { id, reason, created_at: now, size_bytes: disk_size(&path)? })
+}
+
+/// Takes a scheduled backup if the last one is at least a day old, or there is none yet.
+pub fn backup_if_due(db: &dyn Storage, dir: &Path, now: DateTime<Utc>) -> Result<Option<BackupInfo>, BackupError> {
+    let last = list_backups(dir)?.into_iter().find(|b| b.reason == BackupReason::Scheduled);
+    match last {
+        Some(last) if now - last.created_at < Duration::days(1) => Ok(None),
+        _ => create_backup(db, dir, BackupReason::Scheduled, now).map(Some),
+    }
+}
+
+/// Every backup in `dir`, newest first.
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>, BackupError> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let id = entry.file_name().to_string_lossy().into_owned();
+        if let Some((reason, created_at)) = BackupInfo::parse(&id) {
+            let size_bytes = disk_size(&entry.path())?;
+            backups.push(BackupInfo { id, reason, created_at, size_bytes });
+        }
+    }
+    backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+    Ok(backups)
+}
+
+/// Replaces everything in the store with the contents of a backup, after backing up the
+/// current contents. Should the copy fail halfway, that backup still holds them.
+pub fn restore_backup(
+    db: &mut dyn Storage,
+    dir: &Path,
+    id: &str,
+    now: DateTime<Utc>,
+) -> Result<CopyReport, BackupError> {
+    if !list_backups(dir)?.iter().any(|b| b.id == id) {
+        return Err(BackupError::NotFound(id.to_string()));
+    }
+    let mut backup = storage::open_snapshot(&dir.join(id))?;
+    create_backup(db, dir, BackupReason::BeforeRestore, now)?;
+    db.clear_all_data()?;
+    let report = storage::copy_storage(&mut *backup, db)?;
+    Ok(report)
+}
+
+/// Removes a backup, which is a file or a directory depending on the backend.
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
+
+fn disk_size(path: &Path) -> std::io::Result<u64> {
+    if !path.is_dir() {
+        return Ok(std::fs::metadata(path)?.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += disk_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    NotFound(String),
+    AlreadyExists(String),
+    Storage(StorageError),
+    Io(std::io::Error),
+}
+
+impl From<StorageError> for BackupError {
+    fn from(e: StorageError) -> Self {
+        BackupError::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for BackupError {
+    fn from(e: std::io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+impl fmt::Display for BackupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackupError::NotFound(id) => write!(f, "Backup {} does not exist", id),
+            BackupError::AlreadyExists(id) => write!(f, "Backup {} already exists", id),
+            BackupError::Storage(e) => write!(f, "{}", e),
+            BackupError::Io(e) => write!(f, "Backup file error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance;
+    use chrono::TimeZone;
+
+    /// A store on disk, opened with this build's backend like the backups are.
+    fn open_temporary(name: &str) -> Box<dyn Storage> {
+        let path = conformance::temp_path(name);
+        storage::open_snapshot(&path).unwrap()
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, day, hour, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_backups_are_scheduled_daily_and_rotated() {
+        let dir = conformance::temp_path("backups-rotation");
+        let db = open_temporary("backups-rotation-db");
+        conformance::save_daily_sessions(&*db, 2);
+
+        assert!(backup_if_due(&*db, &dir, at(1, 9)).unwrap().is_some());
+        assert!(backup_if_due(&*db, &dir, at(1, 20)).unwrap().is_none());
+        let backup = backup_if_due(&*db, &dir, at(2, 9)).unwrap().unwrap();
+        assert_eq!(backup.reason, BackupReason::Scheduled);
+        assert_eq!(backup.created_at, at(2, 9));
+        assert!(backup.size_bytes > 0);
+
+        for day in 3..=(KEEP_BACKUPS as u32 + 3) {
+            backup_if_due(&*db, &dir, at(day, 9)).unwrap();
+        }
+        create_backup(&*db, &dir, BackupReason::BeforeReset, at(20, 9)).unwrap();
+        let backups = list_backups(&dir).unwrap();
+        assert_eq!(backups.len(), KEEP_BACKUPS + 1);
+        assert_eq!(backups[0].reason, BackupReason::BeforeReset);
+        assert_eq!(backups[1].created_at, at(KEEP_BACKUPS as u32 + 3, 9));
+        assert_eq!(backups.last().unwrap().created_at, at(4, 9));
+
+        let taken = create_backup(&*db, &dir, BackupReason::BeforeReset, at(20, 9));
+        assert!(matches!(taken, Err(BackupError::AlreadyExists(_))));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_restore_backup_replaces_data_and_keeps_current_data() {
+        let dir = conformance::temp_path("backups-restore");
+        let mut db = open_temporary("backups-restore-db");
+        conformance::save_daily_sessions(&*db, 3);
+        let backup = create_backup(&*db, &dir, BackupReason::BeforeReset, at(1, 9)).unwrap();
+        db.clear_all_data().unwrap();
+        conformance::save_daily_sessions(&*db, 1);
+
+        let report = restore_backup(&mut *db, &dir, &backup.id, at(2, 9)).unwrap();
+        assert_eq!(report.sessions, 3);
+        assert_eq!(db.load_all_sessions().unwrap().len(), 3);
+
+        let before = list_backups(&dir).unwrap().into_iter().find(|b| b.reason == BackupReason::BeforeRestore);
+        let restored = storage::open_snapshot(&dir.join(before.unwrap().id)).unwrap();
+        assert_eq!(restored.load_all_sessions().unwrap().len(), 1);
+        drop(restored);
+
+        let missing = restore_backup(&mut *db, &dir, "../nback.db", at(3, 9));
+        assert!(matches!(missing, Err(BackupError::NotFound(_))));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
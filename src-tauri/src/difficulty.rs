@@ -0,0 +1,470 @@
+use crate::game::AccuracyStats;
+use crate::persistence::{GameSessionSummary, SessionOutcome, UserSettings};
+use crate::sequence_generator::{GeneratorError, Modality};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const MIN_N_LEVEL: usize = 1;
+pub const MAX_N_LEVEL: usize = 9;
+
+// Brain Workshop only drops a level after this many low scores in a row
+const BRAIN_WORKSHOP_STRIKES: usize = 3;
+
+/// How the next session's difficulty is chosen from the sessions played so far.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum DifficultyPolicy {
+    /// Jaeggi et al. (2008): raise N when every modality reaches 90% accuracy, lower it when
+    /// any modality falls below 75%.
+    #[default]
+    Jaeggi,
+    /// Brain Workshop: raise N when every modality scores at least 80% of
+    /// hits / (hits + misses + false alarms); lower it after three sessions in a row with a
+    /// modality below 50% at the same N.
+    BrainWorkshop,
+    /// Raise N when the mean d' across modalities reaches `raise_at`, lower it below `lower_below`.
+    DPrimeStaircase { raise_at: f64, lower_below: f64 },
+    /// Keeps N and changes the time per turn instead: `step_ms` faster at 90% accuracy,
+    /// `step_ms` slower below 75%, within `fastest_ms..=slowest_ms`.
+    SpeedRamp { step_ms: u64, fastest_ms: u64, slowest_ms: u64 },
+}
+
+/// What a policy changed compared to the current settings.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Adjustment {
+    Keep,
+    RaiseNLevel,
+    LowerNLevel,
+    SpeedUp,
+    SlowDown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recommendation {
+    pub adjustment: Adjustment,
+    pub settings: UserSettings,
+}
+
+/// Direction a score-based policy points in before it is applied to the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Harder,
+    Same,
+    Easier,
+}
+
+impl DifficultyPolicy {
+    /// Recommends the settings for the next session. `recent` is the session history, newest
+    /// first; aborted sessions and sessions played at a different N or speed are not counted.
+    /// Without a completed session at the current settings, the settings are kept.
+    pub fn recommend(&self, current: &UserSettings, recent: &[GameSessionSummary]) -> Recommendation {
+        let mut at_current = recent.iter().filter(|s| {
+            s.outcome == SessionOutcome::Completed
+                && s.settings.n_level == current.n_level
+                && s.settings.speed_ms == current.speed_ms
+        });
+        let Some(latest) = at_current.next() else {
+            return keep(current);
+        };
+
+        let verdict = match *self {
+            DifficultyPolicy::Jaeggi => threshold_verdict(latest, AccuracyStats::calculate_accuracy, 0.90, 0.75),
+            DifficultyPolicy::BrainWorkshop => {
+                match threshold_verdict(latest, brain_workshop_score, 0.80, 0.50) {
+                    Verdict::Easier => {
+                        let strikes = std::iter::once(latest)
+                            .chain(at_current)
+                            .take(BRAIN_WORKSHOP_STRIKES)
+                            .filter(|s| threshold_verdict(s, brain_workshop_score, 0.80, 0.50) == Verdict::Easier)
+                            .count();
+                        if strikes == BRAIN_WORKSHOP_STRIKES { Verdict::Easier } else { Verdict::Same }
+                    }
+                    verdict => verdict,
+                }
+            }
+            DifficultyPolicy::DPrimeStaircase { raise_at, lower_below } => match mean_d_prime(latest) {
+                Some(d) if d >= raise_at => Verdict::Harder,
+                Some(d) if d < lower_below => Verdict::Easier,
+                _ => Verdict::Same,
+            },
+            DifficultyPolicy::SpeedRamp { step_ms, fastest_ms, slowest_ms } => {
+                let verdict = threshold_verdict(latest, AccuracyStats::calculate_accuracy, 0.90, 0.75);
+                return change_speed(current, verdict, step_ms, fastest_ms, slowest_ms);
+            }
+        };
+        change_n_level(current, verdict)
+    }
+}
+
+fn keep(current: &UserSettings) -> Recommendation {
+    Recommendation { adjustment: Adjustment::Keep, settings: current.clone() }
+}
+
+/// `Harder` when every modality scores at least `raise_at`, `Easier` when any falls below
+/// `lower_below`.
+fn threshold_verdict(
+    session: &GameSessionSummary,
+    score: fn(&AccuracyStats) -> f32,
+    raise_at: f32,
+    lower_below: f32,
+) -> Verdict {
+    let scores: Vec<f32> = session.stats.values().map(score).collect();
+    if scores.is_empty() {
+        Verdict::Same
+    } else if scores.iter().any(|&s| s < lower_below) {
+        Verdict::Easier
+    } else if scores.iter().all(|&s| s >= raise_at) {
+        Verdict::Harder
+    } else {
+        Verdict::Same
+    }
+}
+
+/// Brain Workshop's percentage score, which ignores correct rejections.
+fn brain_workshop_score(stats: &AccuracyStats) -> f32 {
+    let attempts = stats.true_positives + stats.false_negatives + stats.false_positives;
+    if attempts == 0 {
+        1.0
+    } else {
+        stats.true_positives as f32 / attempts as f32
+    }
+}
+
+fn mean_d_prime(session: &GameSessionSummary) -> Option<f64> {
+    let sdt: &BTreeMap<Modality, _> = &session.sdt;
+    if sdt.is_empty() {
+        return None;
+    }
+    Some(sdt.values().map(|m| m.d_prime).sum::<f64>() / sdt.len() as f64)
+}
+
+/// Applies a verdict to N. A change the other settings can't support (e.g. a session too
+/// short for the higher N) is not recommended.
+fn change_n_level(current: &UserSettings, verdict: Verdict) -> Recommendation {
+    let (n_level, adjustment) = match verdict {
+        Verdict::Harder if current.n_level < MAX_N_LEVEL => (current.n_level + 1, Adjustment::RaiseNLevel),
+        Verdict::Easier if current.n_level > MIN_N_LEVEL => (current.n_level - 1, Adjustment::LowerNLevel),
+        _ => return keep(current),
+    };
+    let settings = UserSettings { n_level, ..current.clone() };
+    match settings.validate() {
+        Ok(()) => Recommendation { adjustment, settings },
+        Err(_) => keep(current),
+    }
+}
+
+fn change_speed(current: &UserSettings, verdict: Verdict, step_ms: u64, fastest_ms: u64, slowest_ms: u64) -> Recommendation {
+    let (speed_ms, adjustment) = match verdict {
+        Verdict::Harder => (current.speed_ms.saturating_sub(step_ms).max(fastest_ms), Adjustment::SpeedUp),
+        Verdict::Easier => ((current.speed_ms + step_ms).min(slowest_ms), Adjustment::SlowDown),
+        Verdict::Same => return keep(current),
+    };
+    if speed_ms == current.speed_ms {
+        return keep(current);
+    }
+    Recommendation { adjustment, settings: UserSettings { speed_ms, ..current.clone() } }
+}
+
+// --- Within-Session Staircase ---
+
+/// Changes the level between fixed-size blocks of a single session, e.g. 2-up/1-down: two
+/// passed blocks in a row make it harder, one failed block makes it easier.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Staircase {
+    /// Turns per block. The level can change after every block.
+    pub block_length: usize,
+    /// Passed blocks in a row before the level goes up.
+    pub up_after: u32,
+    /// Failed blocks in a row before the level goes down.
+    pub down_after: u32,
+    /// Accuracy every modality needs within a block for the block to pass.
+    pub pass_accuracy: f32,
+    pub target: StaircaseTarget,
+}
+
+impl Default for Staircase {
+    fn default() -> Self {
+        Self {
+            block_length: 20,
+            up_after: 2,
+            down_after: 1,
+            pass_accuracy: 0.8,
+            target: StaircaseTarget::NLevel,
+        }
+    }
+}
+
+/// What a staircase changes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StaircaseTarget {
+    /// N goes up or down by one, within `MIN_N_LEVEL..=MAX_N_LEVEL`. The remaining sequence
+    /// is regenerated for the new N.
+    NLevel,
+    /// The time per turn goes down (harder) or up by `step_ms`, within `fastest_ms..=slowest_ms`.
+    Speed { step_ms: u64, fastest_ms: u64, slowest_ms: u64 },
+}
+
+/// The parts of the settings a staircase moves.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DifficultyLevel {
+    pub n_level: usize,
+    pub speed_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelChange {
+    pub from: DifficultyLevel,
+    pub to: DifficultyLevel,
+}
+
+/// Blocks passed or failed in a row since the last level change.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StaircaseProgress {
+    passes: u32,
+    failures: u32,
+}
+
+impl Staircase {
+    pub fn validate(&self) -> Result<(), GeneratorError> {
+        let invalid = |reason: &str| Err(GeneratorError::InvalidStaircase(reason.to_string()));
+        if self.block_length == 0 {
+            return invalid("blocks need at least one turn");
+        }
+        if self.up_after == 0 || self.down_after == 0 {
+            return invalid("the level can only change after at least one block");
+        }
+        if !(self.pass_accuracy > 0.0 && self.pass_accuracy <= 1.0) {
+            return invalid("the pass accuracy must be above 0 and at most 1");
+        }
+        if let StaircaseTarget::Speed { step_ms, fastest_ms, slowest_ms } = self.target {
+            if step_ms == 0 || fastest_ms > slowest_ms {
+                return invalid("speed steps must be positive within a non-empty range");
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a finished block and returns the level for the next one if it changes.
+    pub fn step(&self, progress: &mut StaircaseProgress, level: DifficultyLevel, passed: bool) -> Option<DifficultyLevel> {
+        if passed {
+            progress.passes += 1;
+            progress.failures = 0;
+            if progress.passes < self.up_after {
+                return None;
+            }
+        } else {
+            progress.failures += 1;
+            progress.passes = 0;
+            if progress.failures < self.down_after {
+                return None;
+            }
+        }
+        *progress = StaircaseProgress::default();
+
+        let next = match (self.target, passed) {
+            (StaircaseTarget::NLevel, true) => DifficultyLevel { n_level: (level.n_level + 1).min(MAX_N_LEVEL), ..level },
+            (StaircaseTarget::NLevel, false) => DifficultyLevel { n_level: level.n_level.saturating_sub(1).max(MIN_N_LEVEL), ..level },
+            (StaircaseTarget::Speed { step_ms, fastest_ms, .. }, true) => {
+                DifficultyLevel { speed_ms: level.speed_ms.saturating_sub(step_ms).max(fastest_ms), ..level }
+            }
+            (StaircaseTarget::Speed { step_ms, slowest_ms, .. }, false) => {
+                DifficultyLevel { speed_ms: (level.speed_ms + step_ms).min(slowest_ms), ..level }
+            }
+        };
+        (next != level).then_some(next)
+    }
+}
+
+/// The level a staircase converged on: the mean level at its reversals, the points where it
+/// turned from going up to going down or back. In N for N staircases and in milliseconds per
+/// turn for speed staircases. `None` if it never reversed.
+pub fn staircase_threshold(target: StaircaseTarget, changes: &[LevelChange]) -> Option<f64> {
+    let value = |level: DifficultyLevel| match target {
+        StaircaseTarget::NLevel => level.n_level as f64,
+        StaircaseTarget::Speed { .. } => level.speed_ms as f64,
+    };
+    let reversals: Vec<f64> = changes
+        .windows(2)
+        .filter(|pair| {
+            let rising = |change: &LevelChange| value(change.to) > value(change.from);
+            rising(&pair[0]) != rising(&pair[1])
+        })
+        .map(|pair| value(pair[1].from))
+        .collect();
+    if reversals.is_empty() {
+        return None;
+    }
+    Some(reversals.iter().sum::<f64>() / reversals.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::GameSession;
+    use crate::sequence_generator::MatchDistribution;
+
+    /// A completed session where every modality got `hits` of 10 matches and `false_alarms`
+    /// of 10 non-matches wrong.
+    fn session(settings: &UserSettings, hits: u32, false_alarms: u32) -> GameSessionSummary {
+        let stats = AccuracyStats {
+            true_positives: hits,
+            false_negatives: 10 - hits,
+            false_positives: false_alarms,
+            true_negatives: 10 - false_alarms,
+            ..AccuracyStats::default()
+        };
+        let stats = settings.modalities.iter().map(|&m| (m, stats.clone())).collect();
+        GameSessionSummary::from(&GameSession::new(settings.clone(), 0, Vec::new(), stats))
+    }
+
+    fn settings(n_level: usize) -> UserSettings {
+        UserSettings { n_level, session_length: 30, ..UserSettings::default() }
+    }
+
+    #[test]
+    fn test_jaeggi_thresholds() {
+        let current = settings(3);
+        let raise = DifficultyPolicy::Jaeggi.recommend(&current, &[session(&current, 10, 1)]);
+        assert_eq!(raise.adjustment, Adjustment::RaiseNLevel);
+        assert_eq!(raise.settings.n_level, 4);
+
+        // (0.8 + 0.8) / 2 = 80%: between the thresholds
+        let keep = DifficultyPolicy::Jaeggi.recommend(&current, &[session(&current, 8, 2)]);
+        assert_eq!(keep.adjustment, Adjustment::Keep);
+
+        let lower = DifficultyPolicy::Jaeggi.recommend(&current, &[session(&current, 5, 2)]);
+        assert_eq!(lower.adjustment, Adjustment::LowerNLevel);
+        assert_eq!(lower.settings.n_level, 2);
+    }
+
+    #[test]
+    fn test_only_completed_sessions_at_current_settings_count() {
+        let current = settings(3);
+        assert_eq!(DifficultyPolicy::Jaeggi.recommend(&current, &[]).adjustment, Adjustment::Keep);
+
+        let mut aborted = session(&current, 10, 0);
+        aborted.outcome = SessionOutcome::Aborted;
+        let other_level = session(&settings(2), 10, 0);
+        let history = [aborted, other_level, session(&current, 5, 5)];
+        let recommendation = DifficultyPolicy::Jaeggi.recommend(&current, &history);
+        assert_eq!(recommendation.adjustment, Adjustment::LowerNLevel);
+    }
+
+    #[test]
+    fn test_n_level_stays_in_range_and_valid() {
+        let top = settings(MAX_N_LEVEL);
+        let recommendation = DifficultyPolicy::Jaeggi.recommend(&top, &[session(&top, 10, 0)]);
+        assert_eq!(recommendation.adjustment, Adjustment::Keep);
+
+        let bottom = settings(MIN_N_LEVEL);
+        let recommendation = DifficultyPolicy::Jaeggi.recommend(&bottom, &[session(&bottom, 0, 10)]);
+        assert_eq!(recommendation.adjustment, Adjustment::Keep);
+
+        // Seven fixed matches need seven scorable turns, which N = 4 doesn't leave in ten turns
+        let short = UserSettings {
+            n_level: 3,
+            session_length: 10,
+            match_distribution: MatchDistribution::Fixed { single: 3, shared: 1 },
+            ..UserSettings::default()
+        };
+        let recommendation = DifficultyPolicy::Jaeggi.recommend(&short, &[session(&short, 10, 0)]);
+        assert_eq!(recommendation.adjustment, Adjustment::Keep);
+    }
+
+    #[test]
+    fn test_brain_workshop_needs_three_strikes_to_lower() {
+        let current = settings(3);
+        let policy = DifficultyPolicy::BrainWorkshop;
+
+        // 8 / (8 + 2 + 0) = 80%
+        assert_eq!(policy.recommend(&current, &[session(&current, 8, 0)]).adjustment, Adjustment::RaiseNLevel);
+        // 8 / (8 + 2 + 2) = 67%: the 80% rule alone is stricter than Jaeggi's accuracy
+        assert_eq!(policy.recommend(&current, &[session(&current, 8, 2)]).adjustment, Adjustment::Keep);
+
+        let low = session(&current, 4, 3);
+        let ok = session(&current, 7, 1);
+        let two_strikes = [low.clone(), low.clone(), ok.clone()];
+        assert_eq!(policy.recommend(&current, &two_strikes).adjustment, Adjustment::Keep);
+        let three_strikes = [low.clone(), low.clone(), low, ok];
+        assert_eq!(policy.recommend(&current, &three_strikes).adjustment, Adjustment::LowerNLevel);
+    }
+
+    #[test]
+    fn test_d_prime_staircase() {
+        let current = settings(3);
+        let policy = DifficultyPolicy::DPrimeStaircase { raise_at: 2.0, lower_below: 1.0 };
+
+        // Log-linear d' of 9/10 hits and 1/10 false alarms is about 2.2
+        assert_eq!(policy.recommend(&current, &[session(&current, 9, 1)]).adjustment, Adjustment::RaiseNLevel);
+        // 7/10 and 3/10 give about 0.9
+        assert_eq!(policy.recommend(&current, &[session(&current, 7, 3)]).adjustment, Adjustment::LowerNLevel);
+        assert_eq!(policy.recommend(&current, &[session(&current, 8, 2)]).adjustment, Adjustment::Keep);
+    }
+
+    #[test]
+    fn test_staircase_two_up_one_down() {
+        let staircase = Staircase::default();
+        let mut progress = StaircaseProgress::default();
+        let level = DifficultyLevel { n_level: 2, speed_ms: 2000 };
+
+        assert_eq!(staircase.step(&mut progress, level, true), None);
+        let up = staircase.step(&mut progress, level, true).unwrap();
+        assert_eq!(up.n_level, 3);
+        // The count starts over after a change
+        assert_eq!(staircase.step(&mut progress, up, true), None);
+        let down = staircase.step(&mut progress, up, false).unwrap();
+        assert_eq!(down, level);
+
+        let bottom = DifficultyLevel { n_level: MIN_N_LEVEL, speed_ms: 2000 };
+        assert_eq!(staircase.step(&mut progress, bottom, false), None);
+
+        let speed = Staircase {
+            up_after: 1,
+            target: StaircaseTarget::Speed { step_ms: 500, fastest_ms: 1500, slowest_ms: 3000 },
+            ..staircase
+        };
+        let faster = speed.step(&mut progress, level, true).unwrap();
+        assert_eq!(faster, DifficultyLevel { n_level: 2, speed_ms: 1500 });
+        assert_eq!(speed.step(&mut progress, faster, true), None);
+    }
+
+    #[test]
+    fn test_staircase_validation() {
+        assert!(Staircase::default().validate().is_ok());
+        assert!(Staircase { block_length: 0, ..Staircase::default() }.validate().is_err());
+        assert!(Staircase { down_after: 0, ..Staircase::default() }.validate().is_err());
+        assert!(Staircase { pass_accuracy: 0.0, ..Staircase::default() }.validate().is_err());
+        let backwards = StaircaseTarget::Speed { step_ms: 100, fastest_ms: 3000, slowest_ms: 1500 };
+        assert!(Staircase { target: backwards, ..Staircase::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_staircase_threshold_averages_reversals() {
+        let change = |from: usize, to: usize| LevelChange {
+            from: DifficultyLevel { n_level: from, speed_ms: 2000 },
+            to: DifficultyLevel { n_level: to, speed_ms: 2000 },
+        };
+        // 2 -> 3 -> 4, reverses at 4, 4 -> 3, reverses at 3, 3 -> 4
+        let changes = [change(2, 3), change(3, 4), change(4, 3), change(3, 4)];
+        assert_eq!(staircase_threshold(StaircaseTarget::NLevel, &changes), Some(3.5));
+        assert_eq!(staircase_threshold(StaircaseTarget::NLevel, &changes[..2]), None);
+        assert_eq!(staircase_threshold(StaircaseTarget::NLevel, &[]), None);
+    }
+
+    #[test]
+    fn test_speed_ramp_changes_speed_only() {
+        let current = settings(3);
+        let policy = DifficultyPolicy::SpeedRamp { step_ms: 250, fastest_ms: 1500, slowest_ms: 2250 };
+
+        let faster = policy.recommend(&current, &[session(&current, 10, 0)]);
+        assert_eq!(faster.adjustment, Adjustment::SpeedUp);
+        assert_eq!((faster.settings.speed_ms, faster.settings.n_level), (1750, 3));
+
+        let slower = policy.recommend(&current, &[session(&current, 5, 5)]);
+        assert_eq!(slower.adjustment, Adjustment::SlowDown);
+        assert_eq!(slower.settings.speed_ms, 2250);
+
+        let fastest = UserSettings { speed_ms: 1500, ..current };
+        let recommendation = policy.recommend(&fastest, &[session(&fastest, 10, 0)]);
+        assert_eq!(recommendation.adjustment, Adjustment::Keep);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::archive::ArchiveError;
+use crate::backups::BackupError;
+use crate::profiles::ProfileError;
+use crate::sequence_generator::GeneratorError;
+use crate::stimulus_sets::StimulusSetError;
+use crate::storage::StorageError;
+use serde::Serialize;
+use std::fmt;
+
+/// The error every command returns. It reaches the frontend as `{ kind, message }`, so the
+/// frontend can word its message by kind and still show the details.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", content = "message")]
+pub enum AppError {
+    /// Reading or writing the database or another file failed.
+    Storage(String),
+    /// Data could not be encoded or decoded, e.g. an export or a malformed archive.
+    Serialization(String),
+    /// The settings cannot be saved or played with.
+    InvalidSettings(String),
+    /// The request needs a session in progress, and there is none.
+    NotRunning,
+    /// A session, profile, backup or custom auditory set that does not exist.
+    NotFound(String),
+    /// Any other request that was refused, e.g. a duplicate profile name.
+    Invalid(String),
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Storage(message)
+            | AppError::Serialization(message)
+            | AppError::InvalidSettings(message)
+            | AppError::NotFound(message)
+            | AppError::Invalid(message) => write!(f, "{}", message),
+            AppError::NotRunning => write!(f, "No session is in progress"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<StorageError> for AppError {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::Encode(_) | StorageError::Decode(_) => AppError::Serialization(e.to_string()),
+            _ => AppError::Storage(e.to_string()),
+        }
+    }
+}
+
+impl From<ProfileError> for AppError {
+    fn from(e: ProfileError) -> Self {
+        match e {
+            ProfileError::Storage(e) => e.into(),
+            ProfileError::NotFound(_) => AppError::NotFound(e.to_string()),
+            _ => AppError::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl From<BackupError> for AppError {
+    fn from(e: BackupError) -> Self {
+        match e {
+            BackupError::Storage(e) => e.into(),
+            BackupError::NotFound(_) => AppError::NotFound(e.to_string()),
+            BackupError::AlreadyExists(_) => AppError::Invalid(e.to_string()),
+            BackupError::Io(_) => AppError::Storage(e.to_string()),
+        }
+    }
+}
+
+impl From<ArchiveError> for AppError {
+    fn from(e: ArchiveError) -> Self {
+        match e {
+            ArchiveError::Json(_) => AppError::Serialization(e.to_string()),
+            _ => AppError::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl From<StimulusSetError> for AppError {
+    fn from(e: StimulusSetError) -> Self {
+        match e {
+            StimulusSetError::Io(_) => AppError::Storage(e.to_string()),
+            StimulusSetError::Parse(_) => AppError::Serialization(e.to_string()),
+            StimulusSetError::MissingSound { .. } => AppError::NotFound(e.to_string()),
+            _ => AppError::Invalid(e.to_string()),
+        }
+    }
+}
+
+impl From<GeneratorError> for AppError {
+    fn from(e: GeneratorError) -> Self {
+        AppError::InvalidSettings(e.to_string())
+    }
+}
+
+impl From<csv::Error> for AppError {
+    fn from(e: csv::Error) -> Self {
+        AppError::Serialization(e.to_string())
+    }
+}
+
+impl<W> From<csv::IntoInnerError<W>> for AppError {
+    fn from(e: csv::IntoInnerError<W>) -> Self {
+        AppError::Serialization(e.error().to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for AppError {
+    fn from(e: std::string::FromUtf8Error) -> Self {
+        AppError::Serialization(e.to_string())
+    }
+}
+
+impl From<tauri::Error> for AppError {
+    fn from(e: tauri::Error) -> Self {
+        AppError::Storage(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_serialize_with_their_kind() {
+        let error = AppError::from(ProfileError::NotFound("profile_1".to_string()));
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({ "kind": "NotFound", "message": "Profile profile_1 does not exist" })
+        );
+        assert_eq!(serde_json::to_value(AppError::NotRunning).unwrap(), serde_json::json!({ "kind": "NotRunning" }));
+        assert!(matches!(AppError::from(ProfileError::EmptyName), AppError::Invalid(_)));
+    }
+}
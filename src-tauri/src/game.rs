@@ -1,26 +1,44 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Mutex;
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+use crate::difficulty::{DifficultyLevel, LevelChange, StaircaseProgress, StaircaseTarget};
 use crate::persistence::UserSettings;
-use crate::sequence_generator;
+use crate::sequence_generator::{self, GeneratorError, Modality, NBackSequences, StreamSpec};
+use crate::signal_detection::{RateCorrection, SdtMetrics};
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub struct Stimulus {
-    pub visual: u8,
-    pub audio: String,
+/// What was presented on one turn, keyed by modality.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Stimulus(pub BTreeMap<Modality, String>);
+
+impl Stimulus {
+    pub fn get(&self, modality: Modality) -> Option<&str> {
+        self.0.get(&modality).map(String::as_str)
+    }
+
+    /// The grid cell of the position modality, if the session uses it.
+    pub fn position(&self) -> Option<u8> {
+        self.get(Modality::Position).and_then(|p| p.parse().ok())
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+/// A turn as presented to the user: the stimulus and the N it is scored against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    pub stimulus: Stimulus,
+    pub n_back: usize,
+}
+
+/// The modalities the user claimed as matches on one turn.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct UserResponse {
-    pub visual_match: bool,
-    pub audio_match: bool,
+    pub matched: BTreeSet<Modality>,
 }
 
-impl Default for UserResponse {
-    fn default() -> Self {
-        Self {
-            visual_match: false,
-            audio_match: false,
-        }
+impl UserResponse {
+    pub fn claims(&self, modality: Modality) -> bool {
+        self.matched.contains(&modality)
     }
 }
 
@@ -28,12 +46,67 @@ impl Default for UserResponse {
 pub struct GameEvent {
     pub turn_index: usize,
     pub stimulus: Stimulus,
-    pub is_visual_match: bool,
-    pub is_audio_match: bool,
+    /// The N this turn was scored against; varies per turn in variable-N mode.
+    pub n_back: usize,
+    /// Modalities whose stimulus matched the one N turns back.
+    pub matches: BTreeSet<Modality>,
+    /// Modalities whose stimulus repeated the one from N-1 or N+1 back without being a match.
+    pub lures: BTreeSet<Modality>,
     pub user_response: UserResponse,
+    /// Milliseconds from stimulus onset to the first keypress, per modality responded to.
+    pub reaction_times_ms: BTreeMap<Modality, u32>,
+    /// Pauses taken while this turn was on screen.
+    pub pauses: Vec<PauseInterval>,
+    /// Set on the last turn of a staircase block that changed the level of the turns after it.
+    pub level_change: Option<LevelChange>,
+}
+
+impl GameEvent {
+    /// How the response to `modality` on this turn was scored, or `None` if the turn did not
+    /// present it. Warm-up turns before the first N are classified too, as in the session stats.
+    pub fn outcome(&self, modality: Modality) -> Option<TrialOutcome> {
+        if !self.stimulus.0.contains_key(&modality) {
+            return None;
+        }
+        Some(match (self.user_response.claims(modality), self.matches.contains(&modality)) {
+            (true, true) => TrialOutcome::Hit,
+            (false, true) => TrialOutcome::Miss,
+            (true, false) => TrialOutcome::FalseAlarm,
+            (false, false) => TrialOutcome::CorrectRejection,
+        })
+    }
+}
+
+/// Signal-detection class of one modality's response on one turn.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TrialOutcome {
+    Hit,
+    Miss,
+    FalseAlarm,
+    CorrectRejection,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// One pause of a session, as wall-clock start and duration.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PauseInterval {
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+/// Lifecycle of a session. `Paused` sessions can be resumed; `Aborted` and `Completed` are final.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GamePhase {
+    /// No session has been started yet.
+    #[default]
+    Idle,
+    Running,
+    Paused,
+    /// Ended early by the user or by starting another session.
+    Aborted,
+    Completed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
 pub struct AccuracyStats {
     // Correctly identified a match
     pub true_positives: u32,
@@ -43,6 +116,10 @@ pub struct AccuracyStats {
     pub false_positives: u32,
     // Incorrectly missed a match
     pub false_negatives: u32,
+    // Non-match turns that repeated the N-1 or N+1 stimulus
+    pub lure_trials: u32,
+    // Claimed a match on a lure turn (also counted in false_positives)
+    pub lure_false_positives: u32,
 }
 
 impl AccuracyStats {
@@ -86,115 +163,568 @@ impl AccuracyStats {
             self.false_positives as f32 / total_non_matches as f32
         }
     }
+
+    /// Calculates the False Alarm Rate on lure turns only.
+    /// Formula: Lure False Positives / Lure Trials
+    /// Comparing it with the overall rate shows whether errors are driven by interference.
+    pub fn calculate_lure_false_alarm_rate(&self) -> f32 {
+        if self.lure_trials == 0 {
+            0.0
+        } else {
+            self.lure_false_positives as f32 / self.lure_trials as f32
+        }
+    }
+
+    /// Signal-detection measures of these counts, or `None` before there has been both a
+    /// match and a non-match turn.
+    pub fn sdt_metrics(&self, correction: RateCorrection) -> Option<SdtMetrics> {
+        SdtMetrics::from_counts(
+            self.true_positives,
+            self.false_negatives,
+            self.false_positives,
+            self.true_negatives,
+            correction,
+        )
+    }
+
+    fn add(&mut self, other: &AccuracyStats) {
+        self.true_positives += other.true_positives;
+        self.true_negatives += other.true_negatives;
+        self.false_positives += other.false_positives;
+        self.false_negatives += other.false_negatives;
+        self.lure_trials += other.lure_trials;
+        self.lure_false_positives += other.lure_false_positives;
+    }
+
+    /// Records the outcome of one turn.
+    fn record(&mut self, responded: bool, is_match: bool, is_lure: bool) {
+        match (responded, is_match) {
+            (true, true) => self.true_positives += 1,
+            (true, false) => self.false_positives += 1,
+            (false, true) => self.false_negatives += 1,
+            (false, false) => self.true_negatives += 1,
+        }
+        if is_lure {
+            self.lure_trials += 1;
+            if responded {
+                self.lure_false_positives += 1;
+            }
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct GameState {
     pub settings: UserSettings,
-    pub is_running: bool,
+    /// Seed the current sequences were generated from.
+    pub seed: u64,
+    pub phase: GamePhase,
     pub event_history: Vec<GameEvent>,
     pub current_turn_index: usize, // How many turns have been *processed*
-    pub visual_stats: AccuracyStats,
-    pub audio_stats: AccuracyStats,
+    pub stats: BTreeMap<Modality, AccuracyStats>,
     
-    // Pre-generated sequences for the entire session
+    // Pre-generated sequences for the entire session, one per modality
+    #[serde(skip_serializing)]
+    sequences: BTreeMap<Modality, Vec<String>>,
+    // The N each turn is scored against
+    #[serde(skip_serializing)]
+    lookbacks: Vec<usize>,
+    // Tokens of a custom auditory set, kept to regenerate the sequences after a level change
+    #[serde(skip_serializing)]
+    audio_stimuli: Option<Vec<String>>,
+    // Current N and time per turn; starts at the settings and moves with the staircase
+    #[serde(skip_serializing)]
+    level: DifficultyLevel,
+    #[serde(skip_serializing)]
+    staircase_progress: StaircaseProgress,
+    // When the current turn's stimulus was presented, on the monotonic clock
+    #[serde(skip_serializing)]
+    turn_onset: Option<Instant>,
+    // Keypresses received for the current turn, with their reaction times
+    #[serde(skip_serializing)]
+    pending_response: UserResponse,
     #[serde(skip_serializing)]
-    audio_sequence: Vec<String>,
+    pending_reaction_times: BTreeMap<Modality, u32>,
+    // Set while the session is paused: when the pause began, on both clocks
     #[serde(skip_serializing)]
-    visual_sequence: Vec<u8>,
+    paused_at: Option<(Instant, DateTime<Utc>)>,
+    // Pauses taken during the current turn
+    #[serde(skip_serializing)]
+    pending_pauses: Vec<PauseInterval>,
+}
+
+/// Everything needed to pick a session back up after the app was closed mid-session,
+/// including the pre-generated sequences.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionCheckpoint {
+    pub settings: UserSettings,
+    pub seed: u64,
+    pub event_history: Vec<GameEvent>,
+    pub current_turn_index: usize,
+    pub stats: BTreeMap<Modality, AccuracyStats>,
+    pub sequences: BTreeMap<Modality, Vec<String>>,
+    pub lookbacks: Vec<usize>,
+    pub audio_stimuli: Option<Vec<String>>,
+    pub level: DifficultyLevel,
+    pub staircase_progress: StaircaseProgress,
 }
 
 impl GameState {
-    pub fn new(settings: UserSettings) -> Self {
-        let (audio_sequence, visual_sequence) = sequence_generator::generate_dual_nback_sequences(
+    /// Creates a session with freshly generated sequences, ready to be started.
+    pub fn new(settings: UserSettings) -> Result<Self, GeneratorError> {
+        Self::generate(settings, None)
+    }
+
+    /// Like [`GameState::new`], but draws the audio stream from `audio_stimuli` instead of the
+    /// built-in set. Used for custom auditory sets, whose tokens are stored in the database.
+    pub fn with_audio_stimuli(
+        settings: UserSettings,
+        audio_stimuli: Vec<String>,
+    ) -> Result<Self, GeneratorError> {
+        Self::generate(settings, Some(audio_stimuli))
+    }
+
+    fn generate(
+        settings: UserSettings,
+        audio_stimuli: Option<Vec<String>>,
+    ) -> Result<Self, GeneratorError> {
+        let seed = settings.seed.unwrap_or_else(sequence_generator::random_seed);
+        let streams = stream_specs(&settings, audio_stimuli.as_deref())?;
+        let generated = sequence_generator::generate_nback_sequences(
             settings.n_level,
+            settings.n_level_mode,
             settings.session_length,
-            settings.auditory_stimulus_set,
-        );
+            &streams,
+            settings.match_distribution,
+            seed,
+        )?;
 
+        Ok(Self {
+            stats: settings.modalities.iter().map(|&m| (m, AccuracyStats::default())).collect(),
+            sequences: generated.streams,
+            lookbacks: generated.lookbacks,
+            audio_stimuli,
+            seed,
+            ..Self::idle(settings)
+        })
+    }
+
+    /// Creates a state that only holds settings, used before the first game is started.
+    pub fn idle(settings: UserSettings) -> Self {
         Self {
-            settings,
-            is_running: false,
+            seed: 0,
+            phase: GamePhase::Idle,
             event_history: Vec::new(),
             current_turn_index: 0,
-            visual_stats: AccuracyStats::default(),
-            audio_stats: AccuracyStats::default(),
-            audio_sequence,
-            visual_sequence,
+            stats: BTreeMap::new(),
+            sequences: BTreeMap::new(),
+            lookbacks: Vec::new(),
+            audio_stimuli: None,
+            level: DifficultyLevel { n_level: settings.n_level, speed_ms: settings.speed_ms },
+            staircase_progress: StaircaseProgress::default(),
+            settings,
+            turn_onset: None,
+            pending_response: UserResponse::default(),
+            pending_reaction_times: BTreeMap::new(),
+            paused_at: None,
+            pending_pauses: Vec::new(),
+        }
+    }
+
+    /// Snapshot of a running or paused session, taken between turns.
+    pub fn checkpoint(&self) -> Option<SessionCheckpoint> {
+        if !self.is_active() {
+            return None;
+        }
+        Some(SessionCheckpoint {
+            settings: self.settings.clone(),
+            seed: self.seed,
+            event_history: self.event_history.clone(),
+            current_turn_index: self.current_turn_index,
+            stats: self.stats.clone(),
+            sequences: self.sequences.clone(),
+            lookbacks: self.lookbacks.clone(),
+            audio_stimuli: self.audio_stimuli.clone(),
+            level: self.level,
+            staircase_progress: self.staircase_progress,
+        })
+    }
+
+    /// Restores a checkpointed session, paused on the turn that was on screen when it was
+    /// taken. Responses to that turn weren't checkpointed, so it is presented afresh on resume.
+    pub fn restore(checkpoint: SessionCheckpoint) -> Self {
+        Self {
+            seed: checkpoint.seed,
+            phase: GamePhase::Paused,
+            event_history: checkpoint.event_history,
+            current_turn_index: checkpoint.current_turn_index,
+            stats: checkpoint.stats,
+            sequences: checkpoint.sequences,
+            lookbacks: checkpoint.lookbacks,
+            audio_stimuli: checkpoint.audio_stimuli,
+            level: checkpoint.level,
+            staircase_progress: checkpoint.staircase_progress,
+            turn_onset: Some(Instant::now()),
+            paused_at: Some((Instant::now(), Utc::now())),
+            ..Self::idle(checkpoint.settings)
         }
     }
 
-    /// Peeks at the next stimulus without advancing the game state.
-    pub fn peek_stimulus(&self) -> Option<Stimulus> {
-        if !self.is_running || self.current_turn_index >= self.settings.session_length {
+    /// Starts the session and presents the first turn.
+    pub fn start(&mut self) {
+        self.phase = GamePhase::Running;
+        self.turn_onset = Some(Instant::now());
+    }
+
+    /// Whether a session is under way, running or paused.
+    pub fn is_active(&self) -> bool {
+        matches!(self.phase, GamePhase::Running | GamePhase::Paused)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.phase == GamePhase::Paused
+    }
+
+    /// Freezes the current turn. Keypresses are ignored until the session is resumed.
+    /// Returns `false` if there is no running session.
+    pub fn pause(&mut self) -> bool {
+        if self.phase != GamePhase::Running {
+            return false;
+        }
+        self.phase = GamePhase::Paused;
+        self.paused_at = Some((Instant::now(), Utc::now()));
+        true
+    }
+
+    /// Continues a paused session and logs the pause with the current turn. The stimulus onset
+    /// moves forward by the time spent paused, so reaction times only count time the stimulus
+    /// was actually on screen.
+    pub fn resume(&mut self) -> bool {
+        if self.phase != GamePhase::Paused {
+            return false;
+        }
+        self.phase = GamePhase::Running;
+        self.end_pause();
+        true
+    }
+
+    /// Ends a running or paused session early. Returns `false` if there was none.
+    pub fn abort(&mut self) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+        self.end_pause();
+        self.phase = GamePhase::Aborted;
+        self.turn_onset = None;
+        true
+    }
+
+    fn end_pause(&mut self) {
+        let Some((paused_at, started_at)) = self.paused_at.take() else {
+            return;
+        };
+        let paused_for = paused_at.elapsed();
+        if let Some(onset) = &mut self.turn_onset {
+            *onset += paused_for;
+        }
+        self.pending_pauses.push(PauseInterval {
+            started_at,
+            duration_ms: paused_for.as_millis() as u64,
+        });
+    }
+
+    /// Records a keypress for `modality` on the current turn and returns its reaction time in
+    /// milliseconds. Only the first press per modality and turn counts. Arithmetic is answered
+    /// with [`GameState::record_answer`] instead.
+    pub fn record_response(&mut self, modality: Modality) -> Option<u32> {
+        self.record_response_at(modality, Instant::now())
+    }
+
+    /// Like [`GameState::record_response`], with the keypress time given explicitly.
+    pub fn record_response_at(&mut self, modality: Modality, at: Instant) -> Option<u32> {
+        if self.phase != GamePhase::Running || !self.stats.contains_key(&modality) || !modality.scores_repeats() {
+            return None;
+        }
+        if let Some(&rt) = self.pending_reaction_times.get(&modality) {
+            return Some(rt);
+        }
+        self.pending_response.matched.insert(modality);
+        self.record_reaction_time(modality, at)
+    }
+
+    /// Records the answer typed for the arithmetic modality on the current turn and returns
+    /// whether it was right. A right answer counts as the arithmetic response, so a wrong one
+    /// scores like no answer, as a miss; answering a turn that asks for nothing is a false
+    /// alarm. Only the first answer per turn counts.
+    pub fn record_answer(&mut self, answer: i64) -> Option<bool> {
+        self.record_answer_at(answer, Instant::now())
+    }
+
+    /// Like [`GameState::record_answer`], with the time of the answer given explicitly.
+    pub fn record_answer_at(&mut self, answer: i64, at: Instant) -> Option<bool> {
+        let modality = Modality::Arithmetic;
+        if self.phase != GamePhase::Running || !self.stats.contains_key(&modality) {
+            return None;
+        }
+        let turn = self.current_turn_index;
+        let expected = sequence_generator::arithmetic_answer(&self.sequences[&modality], self.lookbacks[turn], turn);
+        if !self.pending_reaction_times.contains_key(&modality) {
+            if expected.is_none_or(|expected| expected == answer) {
+                self.pending_response.matched.insert(modality);
+            }
+            self.record_reaction_time(modality, at);
+        }
+        Some(expected.is_some() && self.pending_response.claims(modality))
+    }
+
+    fn record_reaction_time(&mut self, modality: Modality, at: Instant) -> Option<u32> {
+        let onset = self.turn_onset?;
+        let rt = at.saturating_duration_since(onset).as_millis().min(u32::MAX as u128) as u32;
+        self.pending_reaction_times.insert(modality, rt);
+        Some(rt)
+    }
+
+    /// Peeks at the next stimulus, and the N to compare it against, without advancing the
+    /// game state.
+    pub fn peek_stimulus(&self) -> Option<Trial> {
+        if !self.is_active() || self.current_turn_index >= self.settings.session_length {
             return None;
         }
 
-        Some(Stimulus {
-            visual: self.visual_sequence[self.current_turn_index],
-            audio: self.audio_sequence[self.current_turn_index].clone(),
+        Some(Trial {
+            stimulus: self.stimulus_at(self.current_turn_index),
+            n_back: self.lookbacks[self.current_turn_index],
         })
     }
+
+    /// The modalities that match on the upcoming turn, for immediate feedback.
+    pub fn upcoming_matches(&self) -> BTreeSet<Modality> {
+        if self.peek_stimulus().is_none() {
+            return BTreeSet::new();
+        }
+        self.matches_at(self.current_turn_index)
+    }
+
+    fn stimulus_at(&self, turn_idx: usize) -> Stimulus {
+        Stimulus(
+            self.sequences
+                .iter()
+                .map(|(&modality, sequence)| (modality, sequence[turn_idx].clone()))
+                .collect(),
+        )
+    }
+
+    fn matches_at(&self, turn_idx: usize) -> BTreeSet<Modality> {
+        let n = self.lookbacks[turn_idx];
+        self.sequences
+            .iter()
+            .filter(|(&modality, sequence)| sequence_generator::is_target(modality, sequence, n, turn_idx))
+            .map(|(&modality, _)| modality)
+            .collect()
+    }
 }
 
 impl GameState {
     /// Processes user input for the current turn, records the event, and updates stats.
-    pub fn process_turn(&mut self, user_response: UserResponse) {
-        if !self.is_running {
+    /// Keypresses recorded with [`GameState::record_response`] count as claimed matches too.
+    pub fn process_turn(&mut self, mut user_response: UserResponse) {
+        if self.phase != GamePhase::Running {
             return;
         }
+        user_response.matched.append(&mut std::mem::take(&mut self.pending_response).matched);
+        let reaction_times_ms = std::mem::take(&mut self.pending_reaction_times);
         
         let turn_idx = self.current_turn_index;
-        let n = self.settings.n_level;
-
-        let stimulus = Stimulus {
-            visual: self.visual_sequence[turn_idx],
-            audio: self.audio_sequence[turn_idx].clone(),
-        };
-
-        let mut is_visual_match = false;
-        let mut is_audio_match = false;
+        let n = self.lookbacks[turn_idx];
 
-        if turn_idx >= n {
-            let target_stimulus = Stimulus {
-                visual: self.visual_sequence[turn_idx - n],
-                audio: self.audio_sequence[turn_idx - n].clone(),
-            };
-            is_visual_match = stimulus.visual == target_stimulus.visual;
-            is_audio_match = stimulus.audio == target_stimulus.audio;
-        }
+        let stimulus = self.stimulus_at(turn_idx);
+        let matches = self.matches_at(turn_idx);
+        let lures: BTreeSet<Modality> = self
+            .sequences
+            .iter()
+            .filter(|(modality, sequence)| {
+                modality.scores_repeats() && sequence_generator::is_lure(sequence, n, turn_idx)
+            })
+            .map(|(&modality, _)| modality)
+            .collect();
 
         // --- Update Stats ---
-        // Visual
-        match (user_response.visual_match, is_visual_match) {
-            (true, true) => self.visual_stats.true_positives += 1,
-            (true, false) => self.visual_stats.false_positives += 1,
-            (false, true) => self.visual_stats.false_negatives += 1,
-            (false, false) => self.visual_stats.true_negatives += 1,
-        }
-        // Audio
-        match (user_response.audio_match, is_audio_match) {
-            (true, true) => self.audio_stats.true_positives += 1,
-            (true, false) => self.audio_stats.false_positives += 1,
-            (false, true) => self.audio_stats.false_negatives += 1,
-            (false, false) => self.audio_stats.true_negatives += 1,
+        for (modality, stats) in self.stats.iter_mut() {
+            stats.record(
+                user_response.claims(*modality),
+                matches.contains(modality),
+                lures.contains(modality),
+            );
         }
 
         // --- Record Event ---
         self.event_history.push(GameEvent {
             turn_index: turn_idx,
             stimulus,
-            is_visual_match,
-            is_audio_match,
+            n_back: n,
+            matches,
+            lures,
             user_response,
+            reaction_times_ms,
+            pauses: std::mem::take(&mut self.pending_pauses),
+            level_change: None,
         });
 
         // --- Advance Game ---
         self.current_turn_index += 1;
         if self.current_turn_index >= self.settings.session_length {
-            self.is_running = false;
+            self.phase = GamePhase::Completed;
+            self.turn_onset = None;
+        } else {
+            self.close_staircase_block();
+            self.turn_onset = Some(Instant::now());
         }
     }
+
+    /// The time the current turn stays on screen.
+    pub fn speed_ms(&self) -> u64 {
+        self.level.speed_ms
+    }
+
+    /// At the end of a staircase block, scores the block and moves to the next level if the
+    /// staircase says so. A new N regenerates the rest of the sequences; if that isn't possible
+    /// (e.g. too few turns left for it) the level stays as it is.
+    fn close_staircase_block(&mut self) {
+        let Some(staircase) = self.settings.staircase else {
+            return;
+        };
+        let turn = self.current_turn_index;
+        if !turn.is_multiple_of(staircase.block_length) {
+            return;
+        }
+
+        let block = &self.event_history[turn - staircase.block_length..];
+        let block_stats = stats_by_n_back(block).into_values().fold(
+            BTreeMap::<Modality, AccuracyStats>::new(),
+            |mut total, by_modality| {
+                for (modality, stats) in by_modality {
+                    total.entry(modality).or_default().add(&stats);
+                }
+                total
+            },
+        );
+        if block_stats.is_empty() {
+            return; // No scorable turns yet
+        }
+        let passed = block_stats.values().all(|s| s.calculate_accuracy() >= staircase.pass_accuracy);
+        // The streak is only spent once the new level is in place; a level change that can't
+        // be generated leaves it as it was.
+        let mut progress = self.staircase_progress;
+        let Some(next) = staircase.step(&mut progress, self.level, passed) else {
+            self.staircase_progress = progress;
+            return;
+        };
+
+        if let StaircaseTarget::NLevel = staircase.target {
+            let history = NBackSequences {
+                lookbacks: self.lookbacks[..turn].to_vec(),
+                streams: self.sequences.iter().map(|(&m, s)| (m, s[..turn].to_vec())).collect(),
+            };
+            let continued = stream_specs(&self.settings, self.audio_stimuli.as_deref()).and_then(|streams| {
+                sequence_generator::continue_nback_sequences(
+                    &history,
+                    next.n_level,
+                    self.settings.n_level_mode,
+                    self.settings.session_length,
+                    &streams,
+                    self.settings.match_distribution,
+                    self.seed.wrapping_add(turn as u64),
+                )
+            });
+            let Ok(continued) = continued else {
+                return;
+            };
+            self.sequences = continued.streams;
+            self.lookbacks = continued.lookbacks;
+        }
+
+        self.staircase_progress = progress;
+        if let Some(event) = self.event_history.last_mut() {
+            event.level_change = Some(LevelChange { from: self.level, to: next });
+        }
+        self.level = next;
+    }
+}
+
+/// The streams the settings ask for. `audio_stimuli` replaces the built-in auditory set.
+fn stream_specs(settings: &UserSettings, audio_stimuli: Option<&[String]>) -> Result<Vec<StreamSpec>, GeneratorError> {
+    settings
+        .modalities
+        .iter()
+        .map(|&modality| {
+            let stimuli = match (modality, audio_stimuli) {
+                (Modality::Audio, Some(tokens)) => tokens.to_vec(),
+                _ => sequence_generator::stimulus_tokens(
+                    modality,
+                    &settings.auditory_stimulus_set,
+                    settings.grid_layout,
+                )?,
+            };
+            Ok(StreamSpec {
+                modality,
+                stimuli,
+                lure_rate: settings.lure_rates.get(&modality).copied().unwrap_or(0.0),
+            })
+        })
+        .collect()
+}
+
+/// Breaks accuracy down by the N each turn asked for, for variable-N sessions.
+/// Turns before the first scorable one have nothing to compare against and are left out.
+pub fn stats_by_n_back(events: &[GameEvent]) -> BTreeMap<usize, BTreeMap<Modality, AccuracyStats>> {
+    let mut by_n: BTreeMap<usize, BTreeMap<Modality, AccuracyStats>> = BTreeMap::new();
+    for event in events.iter().filter(|e| e.turn_index >= e.n_back) {
+        let stats = by_n.entry(event.n_back).or_default();
+        for modality in event.stimulus.0.keys() {
+            stats.entry(*modality).or_default().record(
+                event.user_response.claims(*modality),
+                event.matches.contains(modality),
+                event.lures.contains(modality),
+            );
+        }
+    }
+    by_n
+}
+
+/// Mean and median reaction time of one modality's hits.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReactionTimeStats {
+    pub count: usize,
+    pub mean_ms: f32,
+    pub median_ms: f32,
+}
+
+/// Summarises reaction times per modality. Only hits count: a false alarm's latency says
+/// little about how fast the match was recognised.
+pub fn reaction_time_stats(events: &[GameEvent]) -> BTreeMap<Modality, ReactionTimeStats> {
+    let mut samples: BTreeMap<Modality, Vec<u32>> = BTreeMap::new();
+    for event in events {
+        for (modality, &rt) in &event.reaction_times_ms {
+            if event.matches.contains(modality) {
+                samples.entry(*modality).or_default().push(rt);
+            }
+        }
+    }
+
+    samples
+        .into_iter()
+        .map(|(modality, mut rts)| {
+            rts.sort_unstable();
+            let count = rts.len();
+            let mean_ms = rts.iter().map(|&rt| rt as f32).sum::<f32>() / count as f32;
+            let median_ms = if count % 2 == 0 {
+                (rts[count / 2 - 1] + rts[count / 2]) as f32 / 2.0
+            } else {
+                rts[count / 2] as f32
+            };
+            (modality, ReactionTimeStats { count, mean_ms, median_ms })
+        })
+        .collect()
 }
 
 // AppState to be managed by Tauri
@@ -203,39 +733,120 @@ pub struct AppState(pub Mutex<GameState>);
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::sequence_generator::AuditoryStimulusSet;
+    use crate::difficulty::{staircase_threshold, DifficultyPolicy, Staircase};
+    use crate::persistence::AbortedSessionPolicy;
+    use crate::sequence_generator::{AuditoryStimulusSet, GridLayout, MatchDistribution, NLevelMode};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    const VISUAL: Modality = Modality::Position;
+    const AUDIO: Modality = Modality::Audio;
+
+    fn override_sequences(game_state: &mut GameState, visual: &[u8], audio: &[&str]) {
+        game_state.sequences.insert(VISUAL, visual.iter().map(|p| p.to_string()).collect());
+        game_state.sequences.insert(AUDIO, audio.iter().map(|a| a.to_string()).collect());
+    }
+
+    fn respond(visual_match: bool, audio_match: bool) -> UserResponse {
+        let mut response = UserResponse::default();
+        if visual_match {
+            response.matched.insert(VISUAL);
+        }
+        if audio_match {
+            response.matched.insert(AUDIO);
+        }
+        response
+    }
 
     fn default_settings() -> UserSettings {
         UserSettings {
             n_level: 2,
+            n_level_mode: NLevelMode::Fixed,
             speed_ms: 1000,
             session_length: 5,
             auditory_stimulus_set: AuditoryStimulusSet::AllLetters,
+            modalities: vec![VISUAL, AUDIO],
+            grid_layout: GridLayout::default(),
+            match_distribution: MatchDistribution::default(),
+            lure_rates: BTreeMap::new(),
+            seed: Some(2024),
+            aborted_sessions: AbortedSessionPolicy::Discard,
+            difficulty_policy: DifficultyPolicy::Jaeggi,
+            staircase: None,
         }
     }
 
     #[test]
     fn test_new_game_state() {
         let settings = default_settings();
-        let game_state = GameState::new(settings.clone());
+        let game_state = GameState::new(settings.clone()).unwrap();
         assert_eq!(game_state.settings.n_level, 2);
-        assert_eq!(game_state.is_running, false);
+        assert_eq!(game_state.phase, GamePhase::Idle);
         assert_eq!(game_state.event_history.len(), 0);
         assert_eq!(game_state.current_turn_index, 0);
-        assert_eq!(game_state.visual_stats.true_positives, 0);
+        assert_eq!(game_state.stats[&VISUAL].true_positives, 0);
+    }
+
+    #[test]
+    fn test_custom_audio_stimuli() {
+        let mut settings = default_settings();
+        settings.session_length = 30;
+        settings.auditory_stimulus_set = AuditoryStimulusSet::Custom("digits".to_string());
+        assert_eq!(
+            GameState::new(settings.clone()).unwrap_err(),
+            GeneratorError::UnresolvedStimulusSet("digits".to_string())
+        );
+
+        let digits: Vec<String> = ["1", "2", "3"].iter().map(|d| d.to_string()).collect();
+        let game_state = GameState::with_audio_stimuli(settings, digits.clone()).unwrap();
+        assert!(game_state.sequences[&AUDIO].iter().all(|token| digits.contains(token)));
+    }
+
+    #[test]
+    fn test_seeded_game_state_is_reproducible() {
+        let first = GameState::new(default_settings()).unwrap();
+        let second = GameState::new(default_settings()).unwrap();
+        assert_eq!(first.seed, 2024);
+        assert_eq!(first.sequences, second.sequences);
+
+        let mut settings = default_settings();
+        settings.seed = None;
+        let unseeded = GameState::new(settings).unwrap();
+        let replay =
+            GameState::new(UserSettings { seed: Some(unseeded.seed), ..unseeded.settings.clone() }).unwrap();
+        assert_eq!(unseeded.sequences, replay.sequences);
+    }
+
+    #[test]
+    fn test_positions_follow_grid_layout() {
+        for (layout, count) in [
+            (GridLayout::Square { size: 5 }, 25),
+            (GridLayout::Rect { rows: 2, cols: 4 }, 8),
+            (GridLayout::Ring { positions: 6 }, 6),
+        ] {
+            let mut settings = default_settings();
+            settings.session_length = 60;
+            settings.grid_layout = layout;
+            let game_state = GameState::new(settings).unwrap();
+            let positions: HashSet<&String> = game_state.sequences[&VISUAL].iter().collect();
+            assert!(positions.iter().all(|p| p.parse::<usize>().unwrap() < count));
+            assert!(positions.len() > 3);
+        }
     }
 
     #[test]
     fn test_peek_and_process_turn() {
         let mut settings = default_settings();
         settings.session_length = 5;
-        let mut game_state = GameState::new(settings);
-        game_state.is_running = true;
+        let mut game_state = GameState::new(settings).unwrap();
+        game_state.start();
 
         for i in 0..5 {
             assert_eq!(game_state.current_turn_index, i);
-            let stimulus = game_state.peek_stimulus().unwrap();
-            assert!(stimulus.visual > 0); // Check that we got a real stimulus
+            let trial = game_state.peek_stimulus().unwrap();
+            assert!(trial.stimulus.position().unwrap() < 9); // Check that we got a real grid position
+            assert!(trial.stimulus.get(AUDIO).is_some());
+            assert_eq!(trial.n_back, 2);
             
             game_state.process_turn(UserResponse::default()); // Process turn i with default (no match) input
             assert_eq!(game_state.event_history.len(), i + 1);
@@ -243,7 +854,7 @@ mod tests {
 
         // After 5 turns are processed, index is 5.
         assert_eq!(game_state.current_turn_index, 5);
-        assert_eq!(game_state.is_running, false);
+        assert_eq!(game_state.phase, GamePhase::Completed);
         
         // The next peek should return None
         assert!(game_state.peek_stimulus().is_none());
@@ -253,64 +864,63 @@ mod tests {
     fn test_process_turn_with_pregen_sequence() {
         let mut settings = default_settings();
         settings.n_level = 2;
-        let mut game_state = GameState::new(settings);
+        let mut game_state = GameState::new(settings).unwrap();
         
         // Manually override the pre-generated sequences for a predictable test
-        game_state.audio_sequence = vec!["A".to_string(), "B".to_string(), "C".to_string(), "B".to_string(), "D".to_string()];
-        game_state.visual_sequence = vec![1, 2, 1, 4, 1];
+        override_sequences(&mut game_state, &[1, 2, 1, 4, 1], &["A", "B", "C", "B", "D"]);
         // Expected matches:
         // Turn 2: Visual (1 == 1)
         // Turn 3: Audio ('B' == 'B')
         // Turn 4: Visual (1 == 1)
 
-        game_state.is_running = true;
+        game_state.start();
 
         // --- Turn 0 ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: false, audio_match: false });
-        assert_eq!(game_state.visual_stats.true_negatives, 1);
-        assert_eq!(game_state.audio_stats.true_negatives, 1);
+        game_state.process_turn(respond(false, false));
+        assert_eq!(game_state.stats[&VISUAL].true_negatives, 1);
+        assert_eq!(game_state.stats[&AUDIO].true_negatives, 1);
         assert_eq!(game_state.current_turn_index, 1);
         let event0 = &game_state.event_history[0];
-        assert_eq!(event0.is_visual_match, false);
-        assert_eq!(event0.user_response.visual_match, false);
+        assert_eq!(event0.matches.contains(&VISUAL), false);
+        assert_eq!(event0.user_response.claims(VISUAL), false);
 
         // --- Turn 1 ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: false, audio_match: false });
-        assert_eq!(game_state.visual_stats.true_negatives, 2);
-        assert_eq!(game_state.audio_stats.true_negatives, 2);
+        game_state.process_turn(respond(false, false));
+        assert_eq!(game_state.stats[&VISUAL].true_negatives, 2);
+        assert_eq!(game_state.stats[&AUDIO].true_negatives, 2);
         assert_eq!(game_state.current_turn_index, 2);
 
         // --- Turn 2 (Visual Match) ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: true, audio_match: false });
-        assert_eq!(game_state.visual_stats.true_positives, 1);
-        assert_eq!(game_state.audio_stats.true_negatives, 3);
+        game_state.process_turn(respond(true, false));
+        assert_eq!(game_state.stats[&VISUAL].true_positives, 1);
+        assert_eq!(game_state.stats[&AUDIO].true_negatives, 3);
         assert_eq!(game_state.current_turn_index, 3);
         let event2 = &game_state.event_history[2];
-        assert_eq!(event2.is_visual_match, true);
-        assert_eq!(event2.user_response.visual_match, true);
+        assert_eq!(event2.matches.contains(&VISUAL), true);
+        assert_eq!(event2.user_response.claims(VISUAL), true);
 
         // --- Turn 3 (Audio Match) ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: false, audio_match: true });
-        assert_eq!(game_state.visual_stats.true_negatives, 3);
-        assert_eq!(game_state.audio_stats.true_positives, 1);
+        game_state.process_turn(respond(false, true));
+        assert_eq!(game_state.stats[&VISUAL].true_negatives, 3);
+        assert_eq!(game_state.stats[&AUDIO].true_positives, 1);
         assert_eq!(game_state.current_turn_index, 4);
         let event3 = &game_state.event_history[3];
-        assert_eq!(event3.is_audio_match, true);
-        assert_eq!(event3.user_response.audio_match, true);
+        assert_eq!(event3.matches.contains(&AUDIO), true);
+        assert_eq!(event3.user_response.claims(AUDIO), true);
 
         // --- Turn 4 (Visual Match, user misses it) ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: false, audio_match: false });
-        assert_eq!(game_state.visual_stats.false_negatives, 1);
-        assert_eq!(game_state.audio_stats.true_negatives, 4);
+        game_state.process_turn(respond(false, false));
+        assert_eq!(game_state.stats[&VISUAL].false_negatives, 1);
+        assert_eq!(game_state.stats[&AUDIO].true_negatives, 4);
         assert_eq!(game_state.current_turn_index, 5);
         let event4 = &game_state.event_history[4];
-        assert_eq!(event4.is_visual_match, true);
-        assert_eq!(event4.user_response.visual_match, false);
+        assert_eq!(event4.matches.contains(&VISUAL), true);
+        assert_eq!(event4.user_response.claims(VISUAL), false);
     }
 
     #[test]
@@ -318,16 +928,15 @@ mod tests {
         let mut settings = default_settings();
         settings.n_level = 3; // Test with N=3
         settings.session_length = 6;
-        let mut game_state = GameState::new(settings);
+        let mut game_state = GameState::new(settings).unwrap();
         
-        game_state.audio_sequence = vec!["A".to_string(), "B".to_string(), "C".to_string(), "A".to_string(), "D".to_string(), "C".to_string()];
-        game_state.visual_sequence = vec![1, 2, 3, 4, 2, 6];
+        override_sequences(&mut game_state, &[1, 2, 3, 4, 2, 6], &["A", "B", "C", "A", "D", "C"]);
         // Expected matches:
         // Turn 3: Audio ('A' == 'A')
         // Turn 4: Visual (2 == 2)
         // Turn 5: Audio ('C' == 'C')
 
-        game_state.is_running = true;
+        game_state.start();
 
         // Process turns 0, 1, 2 (no matches possible)
         for _ in 0..3 {
@@ -335,28 +944,386 @@ mod tests {
             game_state.process_turn(UserResponse::default());
         }
         assert_eq!(game_state.current_turn_index, 3);
-        assert_eq!(game_state.visual_stats.true_negatives, 3);
-        assert_eq!(game_state.audio_stats.true_negatives, 3);
+        assert_eq!(game_state.stats[&VISUAL].true_negatives, 3);
+        assert_eq!(game_state.stats[&AUDIO].true_negatives, 3);
 
         // --- Turn 3 (Audio Match) ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: false, audio_match: true });
-        assert_eq!(game_state.audio_stats.true_positives, 1);
-        assert_eq!(game_state.visual_stats.true_negatives, 4); // Correctly said no visual match
+        game_state.process_turn(respond(false, true));
+        assert_eq!(game_state.stats[&AUDIO].true_positives, 1);
+        assert_eq!(game_state.stats[&VISUAL].true_negatives, 4); // Correctly said no visual match
         assert_eq!(game_state.current_turn_index, 4);
 
         // --- Turn 4 (Visual Match) ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: true, audio_match: false });
-        assert_eq!(game_state.visual_stats.true_positives, 1);
-        assert_eq!(game_state.audio_stats.true_negatives, 4); // Correctly said no audio match
+        game_state.process_turn(respond(true, false));
+        assert_eq!(game_state.stats[&VISUAL].true_positives, 1);
+        assert_eq!(game_state.stats[&AUDIO].true_negatives, 4); // Correctly said no audio match
         assert_eq!(game_state.current_turn_index, 5);
 
         // --- Turn 5 (Audio Match, user misses) ---
         game_state.peek_stimulus();
-        game_state.process_turn(UserResponse { visual_match: false, audio_match: false });
-        assert_eq!(game_state.audio_stats.false_negatives, 1);
-        assert_eq!(game_state.visual_stats.true_negatives, 5);
+        game_state.process_turn(respond(false, false));
+        assert_eq!(game_state.stats[&AUDIO].false_negatives, 1);
+        assert_eq!(game_state.stats[&VISUAL].true_negatives, 5);
         assert_eq!(game_state.current_turn_index, 6);
     }
+
+    #[test]
+    fn test_invalid_settings_are_rejected() {
+        let mut settings = default_settings();
+        settings.n_level = 5; // Equal to the session length
+        assert_eq!(
+            GameState::new(settings).err(),
+            Some(GeneratorError::InvalidNLevel { n: 5, length: 5 })
+        );
+    }
+
+    #[test]
+    fn test_lure_turns_are_recorded() {
+        let mut settings = default_settings();
+        settings.session_length = 6;
+        let mut game_state = GameState::new(settings).unwrap();
+
+        override_sequences(&mut game_state, &[1, 2, 3, 3, 2, 3], &["A", "B", "C", "D", "E", "F"]);
+        // Visual (N = 2):
+        // Turn 3: lure, repeats turn 2 (N-1)
+        // Turn 4: lure, repeats turn 1 (N+1)
+        // Turn 5: match with turn 3
+
+        game_state.start();
+        for _ in 0..3 {
+            game_state.process_turn(UserResponse::default());
+        }
+        game_state.process_turn(respond(true, false));
+        game_state.process_turn(UserResponse::default());
+        game_state.process_turn(respond(true, false));
+
+        assert!(game_state.event_history[3].lures.contains(&VISUAL));
+        assert!(game_state.event_history[4].lures.contains(&VISUAL));
+        assert!(!game_state.event_history[5].lures.contains(&VISUAL));
+        assert!(game_state.event_history.iter().all(|e| !e.lures.contains(&AUDIO)));
+
+        assert_eq!(game_state.stats[&VISUAL].lure_trials, 2);
+        assert_eq!(game_state.stats[&VISUAL].lure_false_positives, 1);
+        assert_eq!(game_state.stats[&VISUAL].false_positives, 1);
+        assert_eq!(game_state.stats[&VISUAL].true_positives, 1);
+        assert_eq!(game_state.stats[&VISUAL].calculate_lure_false_alarm_rate(), 0.5);
+        assert_eq!(game_state.stats[&AUDIO].lure_trials, 0);
+    }
+
+    #[test]
+    fn test_variable_n_scores_against_each_turns_n() {
+        let mut settings = default_settings();
+        settings.n_level = 3;
+        settings.n_level_mode = NLevelMode::Variable;
+        settings.session_length = 7;
+        let mut game_state = GameState::new(settings).unwrap();
+
+        override_sequences(&mut game_state, &[1, 2, 3, 3, 2, 5, 3], &["A", "B", "C", "D", "E", "F", "G"]);
+        game_state.lookbacks = vec![3, 3, 3, 1, 3, 2, 2];
+        // Visual: turn 3 matches 1 back, turn 4 matches 3 back, turn 6 misses 2 back (5 vs 2).
+
+        game_state.start();
+        for _ in 0..3 {
+            game_state.process_turn(UserResponse::default());
+        }
+        assert_eq!(game_state.peek_stimulus().unwrap().n_back, 1);
+        assert_eq!(game_state.upcoming_matches(), BTreeSet::from([VISUAL]));
+        game_state.process_turn(respond(true, false));
+        game_state.process_turn(respond(false, false));
+        game_state.process_turn(respond(true, false));
+        game_state.process_turn(respond(true, false));
+
+        assert_eq!(game_state.event_history[5].n_back, 2);
+        let outcomes: Vec<_> = game_state.event_history[3..].iter().map(|e| e.outcome(VISUAL)).collect();
+        assert_eq!(
+            outcomes,
+            [TrialOutcome::Hit, TrialOutcome::Miss, TrialOutcome::FalseAlarm, TrialOutcome::FalseAlarm].map(Some)
+        );
+        assert_eq!(game_state.event_history[3].outcome(AUDIO), Some(TrialOutcome::CorrectRejection));
+        assert_eq!(game_state.event_history[3].outcome(Modality::Color), None);
+        assert_eq!(game_state.stats[&VISUAL].true_positives, 1);
+        assert_eq!(game_state.stats[&VISUAL].false_negatives, 1);
+        assert_eq!(game_state.stats[&VISUAL].false_positives, 2);
+
+        let by_n = stats_by_n_back(&game_state.event_history);
+        assert_eq!(by_n.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(by_n[&1][&VISUAL].true_positives, 1);
+        assert_eq!(by_n[&2][&VISUAL].false_positives, 2);
+        // Only turn 4 counts towards N = 3; the warm-up turns are left out.
+        assert_eq!(by_n[&3][&VISUAL].false_negatives, 1);
+        assert_eq!(by_n[&3][&AUDIO].true_negatives, 1);
+    }
+
+    #[test]
+    fn test_reaction_times_are_recorded_per_modality() {
+        let mut settings = default_settings();
+        settings.session_length = 4;
+        let mut game_state = GameState::new(settings).unwrap();
+        override_sequences(&mut game_state, &[1, 2, 1, 2], &["A", "B", "C", "B"]);
+        assert_eq!(game_state.record_response(VISUAL), None); // Not started yet
+
+        game_state.start();
+        game_state.process_turn(UserResponse::default());
+        game_state.process_turn(UserResponse::default());
+
+        // Turn 2: visual match, answered after 400 ms; the second press doesn't count.
+        let onset = game_state.turn_onset.unwrap();
+        assert_eq!(game_state.record_response_at(VISUAL, onset + Duration::from_millis(400)), Some(400));
+        assert_eq!(game_state.record_response_at(VISUAL, onset + Duration::from_millis(900)), Some(400));
+        game_state.process_turn(UserResponse::default());
+
+        // Turn 3: both match; audio is answered through the legacy flag, without a timestamp.
+        let onset = game_state.turn_onset.unwrap();
+        game_state.record_response_at(VISUAL, onset + Duration::from_millis(600));
+        game_state.process_turn(respond(false, true));
+
+        let event2 = &game_state.event_history[2];
+        assert!(event2.user_response.claims(VISUAL));
+        assert_eq!(event2.reaction_times_ms, BTreeMap::from([(VISUAL, 400)]));
+        assert_eq!(game_state.stats[&VISUAL].true_positives, 2);
+        assert_eq!(game_state.stats[&AUDIO].true_positives, 1);
+        assert!(!game_state.event_history[3].reaction_times_ms.contains_key(&AUDIO));
+
+        let rts = reaction_time_stats(&game_state.event_history);
+        assert_eq!(rts[&VISUAL], ReactionTimeStats { count: 2, mean_ms: 500.0, median_ms: 500.0 });
+        assert!(!rts.contains_key(&AUDIO));
+        assert!(game_state.turn_onset.is_none());
+    }
+
+    #[test]
+    fn test_pause_freezes_responses_and_shifts_onset() {
+        let mut game_state = GameState::new(default_settings()).unwrap();
+        assert!(!game_state.pause()); // Nothing to pause yet
+
+        game_state.start();
+        let onset = game_state.turn_onset.unwrap();
+        assert!(game_state.pause());
+        assert!(!game_state.pause());
+        assert_eq!(game_state.record_response(VISUAL), None);
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(game_state.resume());
+        assert!(!game_state.resume());
+        let shifted = game_state.turn_onset.unwrap();
+        assert!(shifted >= onset + Duration::from_millis(20));
+        // Reaction times count from the shifted onset, leaving the pause out
+        assert_eq!(game_state.record_response_at(VISUAL, shifted + Duration::from_millis(150)), Some(150));
+    }
+
+    #[test]
+    fn test_phase_transitions_and_pause_log() {
+        let mut game_state = GameState::new(default_settings()).unwrap();
+        assert_eq!(game_state.phase, GamePhase::Idle);
+        game_state.process_turn(UserResponse::default()); // Ignored until started
+        assert!(game_state.event_history.is_empty());
+
+        game_state.start();
+        game_state.process_turn(UserResponse::default());
+        assert!(game_state.pause());
+        game_state.process_turn(UserResponse::default()); // Paused sessions don't advance
+        assert_eq!(game_state.current_turn_index, 1);
+        assert!(game_state.peek_stimulus().is_some());
+        assert!(game_state.resume());
+        assert!(game_state.pause());
+        assert!(game_state.resume());
+        game_state.process_turn(UserResponse::default());
+
+        assert!(game_state.event_history[0].pauses.is_empty());
+        assert_eq!(game_state.event_history[1].pauses.len(), 2);
+
+        assert!(game_state.pause());
+        assert!(game_state.abort());
+        assert_eq!(game_state.phase, GamePhase::Aborted);
+        assert!(!game_state.abort());
+        assert!(!game_state.resume());
+        assert!(game_state.peek_stimulus().is_none());
+        game_state.process_turn(UserResponse::default());
+        assert_eq!(game_state.event_history.len(), 2);
+    }
+
+    #[test]
+    fn test_checkpoint_restores_paused_at_current_turn() {
+        let mut game_state = GameState::new(default_settings()).unwrap();
+        assert!(game_state.checkpoint().is_none());
+        game_state.start();
+        for _ in 0..2 {
+            let matched = game_state.upcoming_matches();
+            game_state.process_turn(UserResponse { matched });
+        }
+
+        let bytes = bincode::serialize(&game_state.checkpoint().unwrap()).unwrap();
+        let mut restored = GameState::restore(bincode::deserialize(&bytes).unwrap());
+        assert_eq!(restored.phase, GamePhase::Paused);
+        assert_eq!(restored.current_turn_index, 2);
+        assert_eq!(restored.event_history.len(), 2);
+        assert_eq!(restored.stats, game_state.stats);
+        assert_eq!(restored.peek_stimulus(), game_state.peek_stimulus());
+        assert_eq!(restored.record_response(VISUAL), None); // Still paused
+
+        assert!(restored.resume());
+        while restored.is_active() {
+            let matched = restored.upcoming_matches();
+            game_state.process_turn(UserResponse { matched: matched.clone() });
+            restored.process_turn(UserResponse { matched });
+        }
+        assert_eq!(restored.phase, GamePhase::Completed);
+        assert_eq!(restored.stats, game_state.stats);
+        assert_eq!(restored.event_history[2].pauses.len(), 1);
+        assert!(restored.checkpoint().is_none());
+    }
+
+    #[test]
+    fn test_staircase_changes_n_between_blocks() {
+        let staircase = Staircase { block_length: 10, up_after: 1, down_after: 1, ..Staircase::default() };
+        let settings = UserSettings { session_length: 40, staircase: Some(staircase), ..default_settings() };
+        let mut game_state = GameState::new(settings).unwrap();
+        game_state.start();
+
+        let play_block = |game_state: &mut GameState, correctly: bool| {
+            for _ in 0..10 {
+                let matched = match correctly {
+                    true => game_state.upcoming_matches(),
+                    false => BTreeSet::from([VISUAL, AUDIO]),
+                };
+                game_state.process_turn(UserResponse { matched });
+            }
+        };
+
+        play_block(&mut game_state, true);
+        let presented = game_state.sequences[&VISUAL][..10].to_vec();
+        let change = game_state.event_history[9].level_change.unwrap();
+        assert_eq!((change.from.n_level, change.to.n_level), (2, 3));
+        assert!(game_state.event_history[..9].iter().all(|e| e.level_change.is_none()));
+        assert!(game_state.lookbacks[10..].iter().all(|&n| n == 3));
+        assert_eq!(game_state.sequences[&VISUAL][..10], presented[..]);
+        assert_eq!(game_state.peek_stimulus().unwrap().n_back, 3);
+
+        play_block(&mut game_state, false);
+        assert_eq!(game_state.event_history[19].level_change.unwrap().to.n_level, 2);
+        play_block(&mut game_state, true);
+        assert_eq!(game_state.event_history[29].level_change.unwrap().to.n_level, 3);
+        play_block(&mut game_state, true);
+        assert_eq!(game_state.phase, GamePhase::Completed);
+        assert!(game_state.event_history[39].level_change.is_none());
+
+        let changes: Vec<LevelChange> = game_state.event_history.iter().filter_map(|e| e.level_change).collect();
+        assert_eq!(staircase_threshold(StaircaseTarget::NLevel, &changes), Some(2.5));
+    }
+
+    #[test]
+    fn test_staircase_keeps_progress_when_level_change_fails() {
+        let staircase = Staircase { block_length: 5, up_after: 2, ..Staircase::default() };
+        let settings = UserSettings { session_length: 30, staircase: Some(staircase), ..default_settings() };
+        let mut game_state = GameState::new(settings).unwrap();
+        game_state.start();
+        let play_block = |game_state: &mut GameState| {
+            for _ in 0..5 {
+                let matched = game_state.upcoming_matches();
+                game_state.process_turn(UserResponse { matched });
+            }
+        };
+
+        play_block(&mut game_state);
+        let progress = game_state.staircase_progress;
+        assert_ne!(progress, StaircaseProgress::default());
+
+        // The continuation can't be generated from a single sound
+        game_state.audio_stimuli = Some(vec!["a".to_string()]);
+        play_block(&mut game_state);
+        assert_eq!(game_state.level.n_level, 2);
+        assert_eq!(game_state.staircase_progress, progress);
+
+        game_state.audio_stimuli = None;
+        play_block(&mut game_state);
+        assert_eq!(game_state.level.n_level, 3);
+        assert_eq!(game_state.staircase_progress, StaircaseProgress::default());
+    }
+
+    #[test]
+    fn test_speed_staircase_keeps_sequences() {
+        let staircase = Staircase {
+            block_length: 5,
+            up_after: 1,
+            target: StaircaseTarget::Speed { step_ms: 250, fastest_ms: 500, slowest_ms: 2000 },
+            ..Staircase::default()
+        };
+        let settings = UserSettings { session_length: 20, staircase: Some(staircase), ..default_settings() };
+        let mut game_state = GameState::new(settings).unwrap();
+        let sequences = game_state.sequences.clone();
+        game_state.start();
+        for _ in 0..5 {
+            let matched = game_state.upcoming_matches();
+            game_state.process_turn(UserResponse { matched });
+        }
+        assert_eq!(game_state.speed_ms(), 750);
+        assert_eq!(game_state.sequences, sequences);
+
+        // Checkpoints carry the level over
+        let restored = GameState::restore(game_state.checkpoint().unwrap());
+        assert_eq!(restored.speed_ms(), 750);
+    }
+
+    #[test]
+    fn test_triple_nback_scores_each_modality() {
+        let mut settings = default_settings();
+        settings.modalities = vec![Modality::Position, Modality::Color, Modality::Shape];
+        settings.session_length = 4;
+        let mut game_state = GameState::new(settings).unwrap();
+        assert_eq!(game_state.stats.len(), 3);
+
+        game_state.sequences.insert(Modality::Position, vec!["0".into(), "1".into(), "0".into(), "2".into()]);
+        game_state.sequences.insert(Modality::Color, vec!["red".into(), "blue".into(), "green".into(), "blue".into()]);
+        game_state.sequences.insert(Modality::Shape, vec!["star".into(), "circle".into(), "cross".into(), "star".into()]);
+        // Turn 2: position match. Turn 3: colour match.
+
+        game_state.start();
+        game_state.process_turn(UserResponse::default());
+        game_state.process_turn(UserResponse::default());
+        assert_eq!(game_state.upcoming_matches(), BTreeSet::from([Modality::Position]));
+        game_state.process_turn(UserResponse { matched: BTreeSet::from([Modality::Position, Modality::Shape]) });
+        assert_eq!(game_state.upcoming_matches(), BTreeSet::from([Modality::Color]));
+        game_state.process_turn(UserResponse::default());
+
+        assert_eq!(game_state.stats[&Modality::Position].true_positives, 1);
+        assert_eq!(game_state.stats[&Modality::Shape].false_positives, 1);
+        assert_eq!(game_state.stats[&Modality::Color].false_negatives, 1);
+        assert_eq!(game_state.event_history[3].stimulus.get(Modality::Color), Some("blue"));
+        assert!(game_state.event_history[3].stimulus.get(Modality::Audio).is_none());
+        assert_eq!(game_state.phase, GamePhase::Completed);
+    }
+
+    #[test]
+    fn test_arithmetic_scores_answers() {
+        let mut settings = default_settings();
+        settings.modalities = vec![Modality::Position, Modality::Arithmetic];
+        let mut game_state = GameState::new(settings).unwrap();
+        let arithmetic = ["7", "2", "-3", "*4", "+1"];
+        game_state.sequences.insert(Modality::Arithmetic, arithmetic.iter().map(|t| t.to_string()).collect());
+
+        game_state.start();
+        // An answer before the first N is a false alarm; a key press is no answer
+        assert_eq!(game_state.record_answer(5), Some(false));
+        assert_eq!(game_state.record_response(Modality::Arithmetic), None);
+        game_state.process_turn(UserResponse::default());
+        game_state.process_turn(UserResponse::default());
+        // 7 - 3, then 2 * 4; only the first answer of a turn counts
+        assert_eq!(game_state.upcoming_matches(), BTreeSet::from([Modality::Arithmetic]));
+        assert_eq!(game_state.record_answer(4), Some(true));
+        assert_eq!(game_state.record_answer(0), Some(true));
+        game_state.process_turn(UserResponse::default());
+        assert_eq!(game_state.record_answer(6), Some(false));
+        assert_eq!(game_state.record_answer(8), Some(false));
+        game_state.process_turn(UserResponse::default());
+        // 3 + 1, left unanswered
+        game_state.process_turn(UserResponse::default());
+
+        let stats = &game_state.stats[&Modality::Arithmetic];
+        assert_eq!((stats.true_positives, stats.false_negatives), (1, 2));
+        assert_eq!((stats.false_positives, stats.true_negatives), (1, 1));
+        assert!(game_state.event_history.iter().all(|e| !e.lures.contains(&Modality::Arithmetic)));
+        assert!(game_state.event_history[2].reaction_times_ms.contains_key(&Modality::Arithmetic));
+        assert_eq!(game_state.event_history[2].stimulus.get(Modality::Arithmetic), Some("-3"));
+    }
 }
@@ -0,0 +1,41 @@
+//! Filters, sort order and pagination of history listings. Every storage backend answers
+//! them from session summaries alone, without reading any event history.
+
+use crate::persistence::GameSessionSummary;
+use crate::sequence_generator::AuditoryStimulusSet;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    NewestFirst,
+    OldestFirst,
+}
+
+/// Which sessions `Storage::query_history` returns. Every filter is optional; the default
+/// lists the whole history, newest first.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HistoryQuery {
+    /// Sessions started at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Sessions started at or before this time.
+    pub to: Option<DateTime<Utc>>,
+    pub n_level: Option<usize>,
+    pub stimulus_set: Option<AuditoryStimulusSet>,
+    pub tag: Option<String>,
+    pub order: SortOrder,
+    /// Matching sessions to skip, for pagination.
+    pub offset: usize,
+    /// Most sessions to return. `None` returns all remaining matches.
+    pub limit: Option<usize>,
+}
+
+/// One page of matching sessions.
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryQueryResult {
+    pub summaries: Vec<GameSessionSummary>,
+    /// Matching sessions across all pages.
+    pub total: usize,
+}
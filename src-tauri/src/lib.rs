@@ -1,17 +1,40 @@
+mod archive;
+mod backups;
+mod difficulty;
+mod error;
 mod game;
+mod history_query;
+mod migrations;
 mod persistence;
+mod profiles;
+mod recovery;
 pub mod sequence_generator;
-
-use crate::sequence_generator::AuditoryStimulusSet;
-use chrono::Duration;
-use game::{AppState, GameState, Stimulus, UserResponse};
-use persistence::{
-    clear_all_data, load_all_sessions, load_session_by_id, load_settings, save_session,
-    save_settings, DbState, GameSession, GameSessionSummary, UserSettings,
-};
+mod session_clock;
+mod signal_detection;
+mod stimulus_sets;
+mod storage;
+
+use crate::sequence_generator::{AuditoryStimulusSet, GridLayout, MatchDistribution, Modality, NLevelMode};
+use chrono::{DateTime, Duration, Utc};
+use archive::{HistoryArchive, ImportReport};
+use backups::{BackupInfo, BackupReason};
+use difficulty::{DifficultyPolicy, Recommendation};
+use error::AppError;
+use game::{AccuracyStats, AppState, GamePhase, GameState, Trial, TrialOutcome, UserResponse};
+use history_query::{HistoryQuery, HistoryQueryResult};
+use persistence::{AbortedSessionPolicy, GameSession, GameSessionSummary, SessionOutcome, UserSettings};
+use profiles::Profile;
+use recovery::{RecoveryReport, RecoveryState, StartupErrorState};
 use rand::prelude::*;
 use serde::Serialize;
-use tauri::{Manager, State};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::time::Instant;
+use session_clock::SessionClock;
+use signal_detection::{RateCorrection, SdtMetrics};
+use stimulus_sets::CustomStimulusSet;
+use storage::{Backend, Backends, CopyReport, DbState, Storage, StorageError};
+use tauri::{AppHandle, Manager, State};
 
 // --- Frontend-Specific Data Structures ---
 // This ensures that the data sent to the frontend matches what it expects,
@@ -19,8 +42,12 @@ use tauri::{Manager, State};
 
 #[derive(Serialize, Clone)]
 struct FrontendStimulus {
-    visual_stimulus: VisualStimulus,
-    audio_stimulus: AudioStimulus,
+    visual_stimulus: Option<VisualStimulus>,
+    audio_stimulus: Option<AudioStimulus>,
+    // Every modality's stimulus, including the ones without a dedicated field
+    stimuli: BTreeMap<Modality, String>,
+    // The N to compare against, announced to the user in variable-N mode
+    n_back: usize,
 }
 
 #[derive(Serialize, Clone)]
@@ -33,94 +60,161 @@ struct AudioStimulus {
     letter: String,
 }
 
-impl From<&Stimulus> for FrontendStimulus {
-    fn from(stimulus: &Stimulus) -> Self {
+impl From<&Trial> for FrontendStimulus {
+    fn from(trial: &Trial) -> Self {
+        let stimulus = &trial.stimulus;
         Self {
-            visual_stimulus: VisualStimulus {
-                position: stimulus.visual,
-            },
-            audio_stimulus: AudioStimulus {
-                letter: stimulus.audio.clone(),
-            },
+            visual_stimulus: stimulus.position().map(|position| VisualStimulus { position }),
+            audio_stimulus: stimulus.get(Modality::Audio).map(|letter| AudioStimulus {
+                letter: letter.to_string(),
+            }),
+            stimuli: stimulus.0.clone(),
+            n_back: trial.n_back,
         }
     }
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct FrontendModalityStats {
+    accuracy: f32,
+    false_alarm_rate: f32,
+    lure_false_alarm_rate: f32,
+    sdt: Option<SdtMetrics>,
+}
+
+impl From<&AccuracyStats> for FrontendModalityStats {
+    fn from(stats: &AccuracyStats) -> Self {
+        Self {
+            accuracy: stats.calculate_accuracy() * 100.0,
+            false_alarm_rate: stats.calculate_false_alarm_rate() * 100.0,
+            lure_false_alarm_rate: stats.calculate_lure_false_alarm_rate() * 100.0,
+            sdt: stats.sdt_metrics(RateCorrection::LogLinear),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct FrontendGameState {
+    phase: GamePhase,
     is_running: bool,
+    is_paused: bool,
     settings: UserSettings,
     current_turn_index: usize,
     current_stimulus: Option<FrontendStimulus>,
+    // Position and audio figures, kept for the dual N-back screen
     visual_accuracy: f32,
     visual_false_alarm_rate: f32,
+    visual_lure_false_alarm_rate: f32,
     audio_accuracy: f32,
     audio_false_alarm_rate: f32,
+    audio_lure_false_alarm_rate: f32,
+    modality_stats: BTreeMap<Modality, FrontendModalityStats>,
     // Add correct answers for the current turn for immediate feedback
     is_visual_match: bool,
     is_audio_match: bool,
+    upcoming_matches: BTreeSet<Modality>,
 }
 
 impl From<&GameState> for FrontendGameState {
     fn from(state: &GameState) -> Self {
-        let n = state.settings.n_level;
-        let idx = state.current_turn_index;
-
-        let mut is_visual_match = false;
-        let mut is_audio_match = false;
-
-        // Determine if the upcoming turn is a match
-        if state.is_running && idx >= n {
-            if let Some(current_stimulus) = state.peek_stimulus() {
-                // The event history contains the processed turns. The last event is idx-1.
-                // We need to compare the upcoming stimulus (at idx) with the stimulus from n turns ago (at idx-n).
-                // The stimulus at idx-n is stored in the event at index idx-n.
-                if let Some(target_event) = state.event_history.get(idx - n) {
-                    is_visual_match = current_stimulus.visual == target_event.stimulus.visual;
-                    is_audio_match = current_stimulus.audio == target_event.stimulus.audio;
-                }
-            }
-        }
+        let modality_stats: BTreeMap<Modality, FrontendModalityStats> = state
+            .stats
+            .iter()
+            .map(|(&modality, stats)| (modality, stats.into()))
+            .collect();
+        let empty = FrontendModalityStats::from(&AccuracyStats::default());
+        let visual = modality_stats.get(&Modality::Position).unwrap_or(&empty);
+        let audio = modality_stats.get(&Modality::Audio).unwrap_or(&empty);
+        let upcoming_matches = state.upcoming_matches();
 
         Self {
-            is_running: state.is_running,
+            phase: state.phase,
+            is_running: state.is_active(),
+            is_paused: state.is_paused(),
             settings: state.settings.clone(),
             current_turn_index: state.current_turn_index,
             current_stimulus: state.peek_stimulus().as_ref().map(FrontendStimulus::from),
-            visual_accuracy: state.visual_stats.calculate_accuracy() * 100.0,
-            visual_false_alarm_rate: state.visual_stats.calculate_false_alarm_rate() * 100.0,
-            audio_accuracy: state.audio_stats.calculate_accuracy() * 100.0,
-            audio_false_alarm_rate: state.audio_stats.calculate_false_alarm_rate() * 100.0,
-            is_visual_match,
-            is_audio_match,
+            visual_accuracy: visual.accuracy,
+            visual_false_alarm_rate: visual.false_alarm_rate,
+            visual_lure_false_alarm_rate: visual.lure_false_alarm_rate,
+            audio_accuracy: audio.accuracy,
+            audio_false_alarm_rate: audio.false_alarm_rate,
+            audio_lure_false_alarm_rate: audio.lure_false_alarm_rate,
+            is_visual_match: upcoming_matches.contains(&Modality::Position),
+            is_audio_match: upcoming_matches.contains(&Modality::Audio),
+            modality_stats,
+            upcoming_matches,
         }
     }
 }
 
+// --- CSV Export ---
+const CSV_STAT_COLUMNS: [&str; 5] = [
+    "true_positives",
+    "true_negatives",
+    "false_positives",
+    "false_negatives",
+    "lure_false_positives",
+];
+
+/// Column prefix for a modality's stats. Position keeps the `visual` prefix of earlier exports.
+fn csv_prefix(modality: Modality) -> &'static str {
+    match modality {
+        Modality::Position => "visual",
+        Modality::Audio => "audio",
+        Modality::Color => "color",
+        Modality::Shape => "shape",
+        Modality::Image => "image",
+        Modality::Arithmetic => "arithmetic",
+    }
+}
 
-// --- CSV Export Struct ---
-#[derive(Serialize)]
-struct CsvRecord {
-    timestamp: String,
-    n_level: usize,
-    speed_ms: u64,
-    session_length: usize,
-    visual_true_positives: u32,
-    visual_true_negatives: u32,
-    visual_false_positives: u32,
-    visual_false_negatives: u32,
-    audio_true_positives: u32,
-    audio_true_negatives: u32,
-    audio_false_positives: u32,
-    audio_false_negatives: u32,
+// Signal-detection columns, left empty when a modality had no match or no non-match turns
+const CSV_SDT_COLUMNS: [&str; 5] = ["d_prime", "criterion", "beta", "a_prime", "b_double_prime"];
+
+fn csv_stat_values(stats: Option<&AccuracyStats>) -> Vec<String> {
+    match stats {
+        Some(stats) => [
+            stats.true_positives,
+            stats.true_negatives,
+            stats.false_positives,
+            stats.false_negatives,
+            stats.lure_false_positives,
+        ]
+        .iter()
+        .map(u32::to_string)
+        .collect(),
+        None => vec![String::new(); CSV_STAT_COLUMNS.len()],
+    }
+}
+
+fn csv_sdt_values(metrics: Option<&SdtMetrics>) -> Vec<String> {
+    match metrics {
+        Some(m) => [m.d_prime, m.criterion, m.beta, m.a_prime, m.b_double_prime]
+            .iter()
+            .map(|value| format!("{:.4}", value))
+            .collect(),
+        None => vec![String::new(); CSV_SDT_COLUMNS.len()],
+    }
 }
 
 // --- Settings Commands ---
 #[tauri::command]
-fn load_user_settings(db_state: State<DbState>) -> Result<UserSettings, String> {
+fn load_user_settings(db_state: State<DbState>) -> Result<UserSettings, AppError> {
+    let db = db_state.0.lock().unwrap();
+    db.load_settings().map_err(AppError::from)
+}
+
+/// Recommends the next session's settings from the history, using the policy chosen in the
+/// settings. The recommendation is not saved; the frontend offers it to the user.
+#[tauri::command]
+fn recommend_next_settings(db_state: State<DbState>) -> Result<Recommendation, AppError> {
     let db = db_state.0.lock().unwrap();
-    load_settings(&db).map_err(|e| e.to_string())
+    let settings = db.load_settings()?;
+    let history = db.load_all_sessions()?;
+    Ok(settings.difficulty_policy.recommend(&settings, &history))
 }
 
 #[tauri::command]
@@ -128,164 +222,691 @@ fn save_user_settings(
     app_state: State<AppState>,
     db_state: State<DbState>,
     settings: UserSettings,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
+    settings.validate()?;
+    let mut game_state = app_state.0.lock().unwrap();
+
     // First, save the settings to the persistent database
     let db = db_state.0.lock().unwrap();
-    save_settings(&db, &settings).map_err(|e| e.to_string())?;
+    resolve_audio_stimuli(&**db, &settings)?;
+    db.save_settings(&settings)?;
 
-    // Then, update the in-memory game state with the new settings
-    let mut game_state = app_state.0.lock().unwrap();
-    game_state.settings = settings;
+    // Then, update the in-memory game state with the new settings. A session in progress keeps
+    // the settings it was started with; its sequences were generated for them.
+    if !game_state.is_active() {
+        game_state.settings = settings;
+    }
 
     Ok(())
 }
 
+/// Removes all data of every profile, after taking a backup it can be restored from. A
+/// session in progress is aborted first, and the default profile's settings are loaded.
 #[tauri::command]
-fn reset_all_data(db_state: State<DbState>) -> Result<(), String> {
-    let db = db_state.0.lock().unwrap();
-    clear_all_data(&db).map_err(|e| e.to_string())
+fn reset_all_data(
+    app: AppHandle,
+    app_state: State<AppState>,
+    db_state: State<DbState>,
+    clock: State<SessionClock>,
+) -> Result<(), AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    let mut db = db_state.0.lock().unwrap();
+    abort_session(&**db, &mut game_state, &clock)?;
+    backups::create_backup(&**db, &backup_dir(&app)?, BackupReason::BeforeReset, Utc::now())?;
+    db.clear_all_data()?;
+    *game_state = GameState::idle(db.load_settings()?);
+    Ok(())
 }
 
 #[tauri::command]
-async fn generate_fake_history(db_state: State<'_, DbState>) -> Result<(), String> {
-    let db = db_state.0.lock().unwrap().clone();
-    tauri::async_runtime::spawn(async move {
+async fn generate_fake_history(db_state: State<'_, DbState>) -> Result<(), AppError> {
+    let sessions = tauri::async_runtime::spawn(async move {
         let mut rng = thread_rng();
+        let mut sessions = Vec::new();
         let stimulus_sets = [
             AuditoryStimulusSet::AllLetters,
             AuditoryStimulusSet::NonConfusingLetters,
             AuditoryStimulusSet::TianGanDiZhi,
         ];
+        let grid_layouts = [
+            GridLayout::Square { size: 3 },
+            GridLayout::Square { size: 4 },
+            GridLayout::Ring { positions: 8 },
+        ];
 
         for i in 0..15 {
             let settings = UserSettings {
                 n_level: rng.gen_range(2..=4),
+                n_level_mode: if rng.gen_bool(0.2) { NLevelMode::Variable } else { NLevelMode::Fixed },
                 speed_ms: rng.gen_range(2000..=3000),
                 session_length: rng.gen_range(20..=30),
-                auditory_stimulus_set: *stimulus_sets.choose(&mut rng).unwrap(),
+                auditory_stimulus_set: stimulus_sets.choose(&mut rng).unwrap().clone(),
+                modalities: vec![Modality::Position, Modality::Audio],
+                grid_layout: *grid_layouts.choose(&mut rng).unwrap(),
+                match_distribution: MatchDistribution::default(),
+                lure_rates: BTreeMap::new(),
+                seed: None,
+                aborted_sessions: AbortedSessionPolicy::Discard,
+                difficulty_policy: DifficultyPolicy::default(),
+                staircase: None,
             };
 
             // Create a temporary game state to generate a valid session
-            let mut temp_game = GameState::new(settings.clone());
-            temp_game.is_running = true;
-
-            while temp_game.is_running {
-                // Simulate user input with some randomness
-                let user_response = UserResponse {
-                    visual_match: rng.gen_bool(0.2), // 20% chance of pressing the button
-                    audio_match: rng.gen_bool(0.2),
-                };
-                temp_game.process_turn(user_response);
+            let mut temp_game = match GameState::new(settings.clone()) {
+                Ok(game) => game,
+                Err(e) => {
+                    eprintln!("Failed to generate sequences for fake session: {}", e);
+                    continue;
+                }
+            };
+            temp_game.start();
+
+            while temp_game.is_active() {
+                // Simulate keypresses with some randomness: a 20% chance of pressing each
+                // button, 300-1200 ms after the stimulus
+                let onset = Instant::now();
+                for &modality in &settings.modalities {
+                    if rng.gen_bool(0.2) {
+                        let delay = std::time::Duration::from_millis(rng.gen_range(300..=1200));
+                        temp_game.record_response_at(modality, onset + delay);
+                    }
+                }
+                temp_game.process_turn(UserResponse::default());
             }
 
             let mut session = GameSession::new(
                 settings,
+                temp_game.seed,
                 temp_game.event_history,
-                temp_game.visual_stats,
-                temp_game.audio_stats,
+                temp_game.stats,
             );
             
             // Backdate the session
             session.timestamp = session.timestamp - Duration::days(i);
 
-            if let Err(e) = save_session(&db, &session) {
-                eprintln!("Failed to save generated session: {}", e);
-            }
+            sessions.push(session);
         }
+        sessions
     })
-    .await
-    .map_err(|e| e.to_string())
+    .await?;
+
+    let db = db_state.0.lock().unwrap();
+    for session in &sessions {
+        db.save_session(session)?;
+    }
+    Ok(())
 }
 
 // --- Game History Commands ---
+/// Lists the history, newest first. With a `tag`, only sessions carrying it are listed.
+#[tauri::command]
+fn get_game_history(db_state: State<DbState>, tag: Option<String>) -> Result<Vec<GameSessionSummary>, AppError> {
+    let db = db_state.0.lock().unwrap();
+    let query = HistoryQuery { tag, ..Default::default() };
+    Ok(db.query_history(&query)?.summaries)
+}
+
+/// Lists one page of the history matching `query`, without loading any event history.
+#[tauri::command]
+fn query_history(db_state: State<DbState>, query: HistoryQuery) -> Result<HistoryQueryResult, AppError> {
+    let db = db_state.0.lock().unwrap();
+    db.query_history(&query).map_err(AppError::from)
+}
+
 #[tauri::command]
-fn get_game_history(db_state: State<DbState>) -> Result<Vec<GameSessionSummary>, String> {
+fn list_session_tags(db_state: State<DbState>) -> Result<BTreeSet<String>, AppError> {
     let db = db_state.0.lock().unwrap();
-    load_all_sessions(&db).map_err(|e| e.to_string())
+    db.load_all_tags().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn delete_game_session(db_state: State<DbState>, session_id: String) -> Result<(), AppError> {
+    let db = db_state.0.lock().unwrap();
+    match db.delete_session(&session_id)? {
+        true => Ok(()),
+        false => Err(AppError::NotFound(format!("Session '{}' not found", session_id))),
+    }
+}
+
+/// Replaces a session's notes and tags.
+#[tauri::command]
+fn annotate_session(
+    db_state: State<DbState>,
+    session_id: String,
+    notes: String,
+    tags: Vec<String>,
+) -> Result<GameSessionSummary, AppError> {
+    let db = db_state.0.lock().unwrap();
+    let mut session = db
+        .load_session(&session_id)?
+        .ok_or_else(|| AppError::NotFound(format!("Session '{}' not found", session_id)))?;
+    session.annotate(notes, tags);
+    db.save_session(&session)?;
+    Ok(GameSessionSummary::from(&session))
 }
 
 #[tauri::command]
 fn get_session_details(
     db_state: State<DbState>,
     session_id: String,
-) -> Result<Option<GameSession>, String> {
+) -> Result<Option<GameSession>, AppError> {
     let db = db_state.0.lock().unwrap();
-    load_session_by_id(&db, &session_id).map_err(|e| e.to_string())
+    db.load_session(&session_id).map_err(AppError::from)
 }
 
 #[tauri::command]
-fn export_history_as_csv(db_state: State<DbState>) -> Result<String, String> {
+fn export_history_as_csv(db_state: State<DbState>) -> Result<String, AppError> {
     let db = db_state.0.lock().unwrap();
-    let summaries = load_all_sessions(&db).map_err(|e| e.to_string())?;
-
-    let records: Vec<CsvRecord> = summaries.into_iter().map(|s| CsvRecord {
-        timestamp: s.timestamp.to_rfc3339(),
-        n_level: s.settings.n_level,
-        speed_ms: s.settings.speed_ms,
-        session_length: s.settings.session_length,
-        visual_true_positives: s.visual_stats.true_positives,
-        visual_true_negatives: s.visual_stats.true_negatives,
-        visual_false_positives: s.visual_stats.false_positives,
-        visual_false_negatives: s.visual_stats.false_negatives,
-        audio_true_positives: s.audio_stats.true_positives,
-        audio_true_negatives: s.audio_stats.true_negatives,
-        audio_false_positives: s.audio_stats.false_positives,
-        audio_false_negatives: s.audio_stats.false_negatives,
-    }).collect();
+    let summaries = db.load_all_sessions()?;
+
+    let modalities: BTreeSet<Modality> = summaries.iter().flat_map(|s| s.stats.keys().copied()).collect();
 
     let mut wtr = csv::Writer::from_writer(vec![]);
-    for record in records {
-        wtr.serialize(record).map_err(|e| e.to_string())?;
+    let mut header: Vec<String> = [
+        "timestamp",
+        "n_level",
+        "n_level_mode",
+        "speed_ms",
+        "session_length",
+        "position_count",
+    ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    for modality in &modalities {
+        header.extend(
+            CSV_STAT_COLUMNS
+                .iter()
+                .chain(&CSV_SDT_COLUMNS)
+                .map(|c| format!("{}_{}", csv_prefix(*modality), c)),
+        );
+    }
+    wtr.write_record(&header)?;
+
+    for s in summaries {
+        let mut record = vec![
+            s.timestamp.to_rfc3339(),
+            s.settings.n_level.to_string(),
+            format!("{:?}", s.settings.n_level_mode),
+            s.settings.speed_ms.to_string(),
+            s.settings.session_length.to_string(),
+            s.settings.grid_layout.position_count().to_string(),
+        ];
+        for modality in &modalities {
+            record.extend(csv_stat_values(s.stats.get(modality)));
+            record.extend(csv_sdt_values(s.sdt.get(modality)));
+        }
+        wtr.write_record(&record)?;
     }
 
-    let csv_string = String::from_utf8(wtr.into_inner().map_err(|e| e.to_string())?).map_err(|e| e.to_string())?;
+    let csv_string = String::from_utf8(wtr.into_inner()?)?;
     Ok(csv_string)
 }
 
+// Per-modality columns of the trial export
+const CSV_TRIAL_COLUMNS: [&str; 6] = ["stimulus", "match", "lure", "response", "outcome", "rt_ms"];
 
-// --- Game Logic Commands ---
+fn csv_trial_outcome(outcome: TrialOutcome) -> &'static str {
+    match outcome {
+        TrialOutcome::Hit => "hit",
+        TrialOutcome::Miss => "miss",
+        TrialOutcome::FalseAlarm => "false_alarm",
+        TrialOutcome::CorrectRejection => "correct_rejection",
+    }
+}
+
+fn csv_stimulus_set(set: &AuditoryStimulusSet) -> String {
+    match set {
+        AuditoryStimulusSet::Custom(id) => id.clone(),
+        builtin => format!("{:?}", builtin),
+    }
+}
+
+/// Exports one row per turn, oldest session first, for analysis outside the app. `from` and
+/// `to` optionally restrict the export to sessions started within that range (inclusive).
+#[tauri::command]
+fn export_trials_as_csv(
+    db_state: State<DbState>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<String, AppError> {
+    let db = db_state.0.lock().unwrap();
+    let mut sessions = db.load_full_sessions()?;
+    sessions.retain(|s| from.is_none_or(|from| s.timestamp >= from) && to.is_none_or(|to| s.timestamp <= to));
+    sessions.reverse();
+
+    let modalities: BTreeSet<Modality> = sessions.iter().flat_map(|s| s.stats.keys().copied()).collect();
+
+    let mut wtr = csv::Writer::from_writer(vec![]);
+    let mut header: Vec<String> = [
+        "session_id",
+        "session_timestamp",
+        "session_outcome",
+        "n_level",
+        "n_back",
+        "speed_ms",
+        "stimulus_set",
+        "turn_index",
+    ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    for modality in &modalities {
+        header.extend(CSV_TRIAL_COLUMNS.iter().map(|c| format!("{}_{}", csv_prefix(*modality), c)));
+    }
+    wtr.write_record(&header)?;
+
+    for s in &sessions {
+        // A speed staircase changes the speed after some turns; events only record the change
+        let mut speed_ms = s.settings.speed_ms;
+        for event in &s.event_history {
+            let mut record = vec![
+                s.id.clone(),
+                s.timestamp.to_rfc3339(),
+                format!("{:?}", s.outcome),
+                s.settings.n_level.to_string(),
+                event.n_back.to_string(),
+                speed_ms.to_string(),
+                csv_stimulus_set(&s.settings.auditory_stimulus_set),
+                event.turn_index.to_string(),
+            ];
+            for modality in &modalities {
+                match event.stimulus.get(*modality) {
+                    Some(stimulus) => record.extend([
+                        stimulus.to_string(),
+                        event.matches.contains(modality).to_string(),
+                        event.lures.contains(modality).to_string(),
+                        event.user_response.claims(*modality).to_string(),
+                        event.outcome(*modality).map(csv_trial_outcome).unwrap_or_default().to_string(),
+                        event.reaction_times_ms.get(modality).map(u32::to_string).unwrap_or_default(),
+                    ]),
+                    None => record.extend(vec![String::new(); CSV_TRIAL_COLUMNS.len()]),
+                }
+            }
+            wtr.write_record(&record)?;
+            if let Some(change) = event.level_change {
+                speed_ms = change.to.speed_ms;
+            }
+        }
+    }
+
+    let csv_string = String::from_utf8(wtr.into_inner()?)?;
+    Ok(csv_string)
+}
+
+/// Exports every session with its settings and full event history as a JSON archive.
+#[tauri::command]
+fn export_history_as_json(db_state: State<DbState>) -> Result<String, AppError> {
+    let db = db_state.0.lock().unwrap();
+    let sessions = db.load_full_sessions()?;
+    HistoryArchive::new(sessions).to_json().map_err(AppError::from)
+}
+
+/// Merges a JSON archive into the history. Sessions already in the history are skipped by ID;
+/// if any session in the archive is invalid, nothing is imported. A backup is taken before
+/// anything is.
+#[tauri::command]
+fn import_history(app: AppHandle, db_state: State<DbState>, archive: String) -> Result<ImportReport, AppError> {
+    let archive = HistoryArchive::from_json(&archive)?;
+    let db = db_state.0.lock().unwrap();
+    let mut stored = HashSet::new();
+    for session in &archive.sessions {
+        if db.session_exists(&session.id)? {
+            stored.insert(session.id.clone());
+        }
+    }
+    let (sessions, duplicates) = archive.dedup(|id| stored.contains(id));
+    if !sessions.is_empty() {
+        backups::create_backup(&**db, &backup_dir(&app)?, BackupReason::BeforeImport, Utc::now())?;
+    }
+    for session in &sessions {
+        db.save_session(session)?;
+    }
+    db.flush()?;
+    Ok(ImportReport { imported: sessions.len(), duplicates })
+}
+
+// --- Backup and Recovery Commands ---
+
+fn backup_dir(app: &AppHandle) -> Result<PathBuf, AppError> {
+    let app_data_dir = app.path().app_data_dir()?;
+    Ok(app_data_dir.join(backups::BACKUP_DIR))
+}
+
+/// Takes the daily backup whenever it is due, checking once an hour for as long as the app runs.
+fn run_scheduled_backups(app: AppHandle) {
+    let Ok(dir) = backup_dir(&app) else {
+        return;
+    };
+    loop {
+        {
+            let db_state = app.state::<DbState>();
+            let db = db_state.0.lock().unwrap();
+            if let Err(e) = backups::backup_if_due(&**db, &dir, Utc::now()) {
+                eprintln!("Failed to back up the database: {}", e);
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs(60 * 60));
+    }
+}
+
+/// Lists the backups, newest first.
+#[tauri::command]
+fn list_backups(app: AppHandle) -> Result<Vec<BackupInfo>, AppError> {
+    backups::list_backups(&backup_dir(&app)?).map_err(AppError::from)
+}
+
+/// Replaces all data with a backup and loads the restored settings. A session in progress is
+/// aborted first, and the data being replaced is backed up itself.
 #[tauri::command]
-fn start_game(app_state: State<AppState>) {
+fn restore_backup(
+    app: AppHandle,
+    app_state: State<AppState>,
+    db_state: State<DbState>,
+    clock: State<SessionClock>,
+    id: String,
+) -> Result<UserSettings, AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    let mut db = db_state.0.lock().unwrap();
+    abort_session(&**db, &mut game_state, &clock)?;
+    backups::restore_backup(&mut **db, &backup_dir(&app)?, &id, Utc::now())?;
+    let settings = db.load_settings()?;
+    *game_state = GameState::idle(settings.clone());
+    Ok(settings)
+}
+
+/// Returns the report of the recovery from a damaged database done at startup, if there was
+/// one. It is returned only once, so the frontend shows it once.
+#[tauri::command]
+fn take_recovery_report(recovery_state: State<RecoveryState>) -> Option<RecoveryReport> {
+    recovery_state.0.lock().unwrap().take()
+}
+
+/// Returns the error the app started despite, such as settings that could not be read, if
+/// there was one. Like the recovery report, it is returned only once.
+#[tauri::command]
+fn take_startup_error(startup_error_state: State<StartupErrorState>) -> Option<AppError> {
+    startup_error_state.0.lock().unwrap().take()
+}
+
+/// The storage backend in use and the ones this build can move the data to.
+#[tauri::command]
+fn get_storage_backends(app: AppHandle) -> Result<Backends, AppError> {
+    Ok(storage::backends(&app.path().app_data_dir()?))
+}
+
+/// Moves all data to another storage backend and switches to it. The old database is kept
+/// next to the new one, renamed to `<name>.migrated`. A session in progress is aborted first.
+#[tauri::command]
+fn migrate_storage(
+    app: AppHandle,
+    app_state: State<AppState>,
+    db_state: State<DbState>,
+    clock: State<SessionClock>,
+    to: Backend,
+) -> Result<CopyReport, AppError> {
+    let app_data_dir = app.path().app_data_dir()?;
     let mut game_state = app_state.0.lock().unwrap();
-    
-    let mut settings = game_state.settings.clone();
-    if settings.session_length < 10 {
-        settings.session_length = 10; // Enforce minimum length
+    let mut db = db_state.0.lock().unwrap();
+    abort_session(&**db, &mut game_state, &clock)?;
+    let report = storage::migrate(&mut db, &app_data_dir, to)?;
+    Ok(report)
+}
+
+// --- Auditory Stimulus Set Commands ---
+
+/// Directories custom sound files are looked up in: the bundled sounds first, then the
+/// user's own sounds in the app-data directory.
+fn sound_dirs(app: &AppHandle) -> Vec<PathBuf> {
+    [app.path().resource_dir(), app.path().app_data_dir()]
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|dir| dir.join("sounds"))
+        .collect()
+}
+
+/// Looks up the tokens of a custom auditory set. Built-in sets resolve to `None`.
+fn resolve_audio_stimuli(db: &dyn Storage, settings: &UserSettings) -> Result<Option<Vec<String>>, AppError> {
+    match &settings.auditory_stimulus_set {
+        AuditoryStimulusSet::Custom(id) => match db.load_stimulus_set(id)? {
+            Some(set) => Ok(Some(set.tokens())),
+            None => Err(AppError::NotFound(format!("Custom auditory stimulus set '{}' is not registered", id))),
+        },
+        _ => Ok(None),
+    }
+}
+
+#[tauri::command]
+fn register_stimulus_set(
+    app: AppHandle,
+    db_state: State<DbState>,
+    manifest_path: String,
+) -> Result<CustomStimulusSet, AppError> {
+    let manifest = stimulus_sets::load_manifest(manifest_path.as_ref())?;
+    let set = manifest.resolve(&sound_dirs(&app))?;
+
+    let db = db_state.0.lock().unwrap();
+    db.save_stimulus_set(&set)?;
+    Ok(set)
+}
+
+#[tauri::command]
+fn list_stimulus_sets(db_state: State<DbState>) -> Result<Vec<CustomStimulusSet>, AppError> {
+    let db = db_state.0.lock().unwrap();
+    db.load_all_stimulus_sets().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn remove_stimulus_set(app_state: State<AppState>, db_state: State<DbState>, id: String) -> Result<(), AppError> {
+    let in_use = AuditoryStimulusSet::Custom(id.clone());
+    if app_state.0.lock().unwrap().settings.auditory_stimulus_set == in_use {
+        return Err(AppError::Invalid(format!("Stimulus set '{}' is selected in the current settings", id)));
+    }
+
+    let db = db_state.0.lock().unwrap();
+    match db.delete_stimulus_set(&id)? {
+        true => Ok(()),
+        false => Err(AppError::NotFound(format!("Stimulus set '{}' not found", id))),
     }
-    if settings.session_length > 100 {
-        settings.session_length = 100; // Enforce maximum length
+}
+
+// --- Profile Commands ---
+
+#[tauri::command]
+fn list_profiles(db_state: State<DbState>) -> Result<Vec<Profile>, AppError> {
+    let db = db_state.0.lock().unwrap();
+    db.list_profiles().map_err(AppError::from)
+}
+
+#[tauri::command]
+fn get_active_profile(db_state: State<DbState>) -> Result<Profile, AppError> {
+    let db = db_state.0.lock().unwrap();
+    profiles::load_profile(&**db, db.profile_id()).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn create_profile(db_state: State<DbState>, name: String) -> Result<Profile, AppError> {
+    let db = db_state.0.lock().unwrap();
+    profiles::create_profile(&**db, &name).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn rename_profile(db_state: State<DbState>, id: String, name: String) -> Result<Profile, AppError> {
+    let db = db_state.0.lock().unwrap();
+    profiles::rename_profile(&**db, &id, &name).map_err(AppError::from)
+}
+
+#[tauri::command]
+fn delete_profile(db_state: State<DbState>, id: String) -> Result<(), AppError> {
+    let db = db_state.0.lock().unwrap();
+    profiles::delete_profile(&**db, &id).map_err(AppError::from)
+}
+
+/// Makes another profile active and loads its settings. A session in progress is aborted
+/// first, under the abort policy of the profile it belongs to.
+#[tauri::command]
+fn switch_profile(
+    app_state: State<AppState>,
+    db_state: State<DbState>,
+    clock: State<SessionClock>,
+    id: String,
+) -> Result<UserSettings, AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    let mut db = db_state.0.lock().unwrap();
+    // An unknown profile leaves the session in progress alone
+    profiles::load_profile(&**db, &id)?;
+    abort_session(&**db, &mut game_state, &clock)?;
+    profiles::switch_profile(&mut **db, &id)?;
+    let settings = db.load_settings()?;
+    *game_state = GameState::idle(settings.clone());
+    Ok(settings)
+}
+
+// --- Game Logic Commands ---
+
+/// Saves a completed or aborted session and returns its summary.
+fn save_finished_session(db: &dyn Storage, game_state: &GameState) -> Result<GameSessionSummary, StorageError> {
+    let mut session = GameSession::new(
+        game_state.settings.clone(),
+        game_state.seed,
+        game_state.event_history.clone(),
+        game_state.stats.clone(),
+    );
+    if game_state.phase == GamePhase::Aborted {
+        session.outcome = SessionOutcome::Aborted;
     }
+    db.save_session(&session)?;
+    Ok(GameSessionSummary::from(&session))
+}
+
+/// Starts a new session. From here on the session clock advances the turns and reports them
+/// through the `stimulus_onset`, `turn_closed` and `session_finished` events.
+#[tauri::command]
+fn start_game(
+    app: AppHandle,
+    app_state: State<AppState>,
+    db_state: State<DbState>,
+    clock: State<SessionClock>,
+) -> Result<(), AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    let db = db_state.0.lock().unwrap();
 
-    *game_state = GameState::new(settings);
-    game_state.is_running = true;
+    // The stored settings, which include any saved while the previous session was running
+    let settings = db.load_settings()?;
+    settings.validate()?;
+
+    // Generated before anything else changes, so a session that can't be generated leaves the
+    // one in flight alone
+    let mut new_game = match resolve_audio_stimuli(&**db, &settings)? {
+        Some(tokens) => GameState::with_audio_stimuli(settings, tokens),
+        None => GameState::new(settings),
+    }?;
+    // A session still in flight is aborted like any other, so the abort policy applies to it
+    abort_session(&**db, &mut game_state, &clock)?;
+    new_game.start();
+    *game_state = new_game;
+    if let Some(checkpoint) = game_state.checkpoint() {
+        db.save_checkpoint(&checkpoint)?;
+    }
+    clock.start(app);
+    Ok(())
 }
 
+/// Restores the session that was in progress when the app was last closed, paused on the
+/// turn that was on screen. Returns `None` if there is nothing to resume. Call it at startup,
+/// before a new session is started.
 #[tauri::command]
-fn submit_user_input(
+fn resume_session(
+    app: AppHandle,
     app_state: State<AppState>,
     db_state: State<DbState>,
-    user_response: UserResponse,
-) {
+    clock: State<SessionClock>,
+) -> Result<Option<FrontendGameState>, AppError> {
     let mut game_state = app_state.0.lock().unwrap();
-    if !game_state.is_running {
-        return;
+    if game_state.is_active() {
+        return Ok(Some(FrontendGameState::from(&*game_state)));
     }
 
-    game_state.process_turn(user_response);
+    let db = db_state.0.lock().unwrap();
+    let Some(checkpoint) = db.load_checkpoint()? else {
+        return Ok(None);
+    };
+    *game_state = GameState::restore(checkpoint);
+    clock.start(app);
+    clock.pause();
+    Ok(Some(FrontendGameState::from(&*game_state)))
+}
 
-    // If the game has just stopped, save the session.
-    if !game_state.is_running {
-        let session = GameSession::new(
-            game_state.settings.clone(),
-            game_state.event_history.clone(),
-            game_state.visual_stats.clone(),
-            game_state.audio_stats.clone(),
-        );
-        let db = db_state.0.lock().unwrap();
-        if let Err(e) = save_session(&db, &session) {
-            eprintln!("Failed to save game session: {}", e);
-        }
+#[tauri::command]
+fn pause_game(app_state: State<AppState>, clock: State<SessionClock>) -> Result<(), AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    if !game_state.is_active() {
+        return Err(AppError::NotRunning);
+    }
+    if game_state.pause() {
+        clock.pause();
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn resume_game(app_state: State<AppState>, clock: State<SessionClock>) -> Result<(), AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    if !game_state.is_active() {
+        return Err(AppError::NotRunning);
+    }
+    if game_state.resume() {
+        clock.resume();
+    }
+    Ok(())
+}
+
+/// Aborts the running or paused session, saving the turns played so far if the
+/// `aborted_sessions` policy keeps partial sessions. Returns the summary of a saved session.
+fn abort_session(
+    db: &dyn Storage,
+    game_state: &mut GameState,
+    clock: &SessionClock,
+) -> Result<Option<GameSessionSummary>, StorageError> {
+    clock.stop();
+    if !game_state.abort() {
+        return Ok(None);
     }
+    let keep = game_state.settings.aborted_sessions == AbortedSessionPolicy::KeepPartial;
+    let summary = match keep && !game_state.event_history.is_empty() {
+        true => Some(save_finished_session(db, game_state)?),
+        false => None,
+    };
+    // Only once the partial session is saved; should saving fail, it can still be resumed
+    db.clear_checkpoint()?;
+    Ok(summary)
+}
+
+/// Ends the running or paused session early.
+#[tauri::command]
+fn abort_game(
+    app_state: State<AppState>,
+    db_state: State<DbState>,
+    clock: State<SessionClock>,
+) -> Result<Option<GameSessionSummary>, AppError> {
+    let mut game_state = app_state.0.lock().unwrap();
+    let db = db_state.0.lock().unwrap();
+    abort_session(&**db, &mut game_state, &clock).map_err(AppError::from)
+}
+
+/// Timestamps a keypress against the current stimulus onset and returns the reaction time
+/// in milliseconds. Fails with `NotRunning` if no turn is being presented.
+#[tauri::command]
+fn register_response(app_state: State<AppState>, modality: Modality) -> Result<u32, AppError> {
+    app_state.0.lock().unwrap().record_response(modality).ok_or(AppError::NotRunning)
+}
+
+/// Records the answer typed for the arithmetic modality on the current turn and returns whether
+/// it was right. Fails with `NotRunning` if no turn is being presented or the session has no
+/// arithmetic.
+#[tauri::command]
+fn register_answer(app_state: State<AppState>, answer: i64) -> Result<bool, AppError> {
+    app_state.0.lock().unwrap().record_answer(answer).ok_or(AppError::NotRunning)
 }
 
 #[tauri::command]
@@ -299,22 +920,23 @@ pub fn run() {
     tauri::Builder::default()
         .setup(|app| {
             let handle = app.handle().clone();
-            let app_data_dir = handle
-                .path()
-                .app_data_dir()
-                .expect("Failed to get app data directory");
+            let app_data_dir = handle.path().app_data_dir()?;
             
             if !app_data_dir.exists() {
-                std::fs::create_dir_all(&app_data_dir).expect("Failed to create app data directory");
+                std::fs::create_dir_all(&app_data_dir)?;
             }
 
-            let db_path = app_data_dir.join("nback.db");
-            let db = sled::open(db_path).expect("Failed to open database");
-
-            let initial_settings = load_settings(&db).unwrap_or_default();
+            // A damaged database is recovered rather than crashing the app; any other error
+            // opening it ends startup with that error
+            let (db, recovery) = recovery::open_or_recover(&app_data_dir, Utc::now())?;
+            let (initial_settings, startup_error) = recovery::startup_settings(&*db);
             
             handle.manage(DbState(db.into()));
-            handle.manage(AppState(GameState::new(initial_settings).into()));
+            handle.manage(RecoveryState(recovery.into()));
+            handle.manage(StartupErrorState(startup_error.into()));
+            handle.manage(AppState(GameState::idle(initial_settings).into()));
+            handle.manage(SessionClock::default());
+            std::thread::spawn(move || run_scheduled_backups(handle));
             
             Ok(())
         })
@@ -323,15 +945,43 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             start_game,
-            submit_user_input,
+            pause_game,
+            resume_game,
+            abort_game,
+            resume_session,
+            register_response,
+            register_answer,
             get_game_state,
             load_user_settings,
             save_user_settings,
             get_game_history,
+            query_history,
             get_session_details,
+            list_session_tags,
+            delete_game_session,
+            annotate_session,
             export_history_as_csv,
+            export_trials_as_csv,
+            export_history_as_json,
+            import_history,
+            recommend_next_settings,
             reset_all_data,
-            generate_fake_history
+            generate_fake_history,
+            list_backups,
+            restore_backup,
+            take_recovery_report,
+            take_startup_error,
+            get_storage_backends,
+            migrate_storage,
+            register_stimulus_set,
+            list_stimulus_sets,
+            remove_stimulus_set,
+            list_profiles,
+            get_active_profile,
+            create_profile,
+            rename_profile,
+            delete_profile,
+            switch_profile
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,526 @@
+//! Schema versions of the values stored in the database, and the chain that upgrades every
+//! older layout to the current one.
+//!
+//! Bincode is not self-describing: a record written before a field was added cannot be read
+//! with the new struct, and `#[serde(default)]` does not help. Each stored value is therefore
+//! wrapped in an envelope carrying its schema version, and decoded with the layout of that
+//! version before being upgraded one version at a time.
+//!
+//! | Version | Layout                                                              |
+//! |---------|---------------------------------------------------------------------|
+//! | 0       | Dual N-back without a choice of auditory set. Stored without an envelope. |
+//! | 1       | Dual N-back with `auditory_stimulus_set`. Stored without an envelope. |
+//! | 2       | Configurable modalities, sequences and difficulty; first enveloped. |
+//! | 3       | Notes and tags on sessions.                                         |
+//!
+//! When a stored struct changes, bump [`CURRENT_VERSION`], copy the old layout into a
+//! `vN` module below and add the step from it to the next version. A `vN` module only copies
+//! the structs that changed afterwards and reuses the current types for the rest; when one of
+//! those changes too, its old layout has to be copied into every module that uses it.
+
+use crate::game::SessionCheckpoint;
+use crate::persistence::{GameSession, GameSessionSummary, UserSettings};
+use crate::profiles::Profile;
+use crate::stimulus_sets::CustomStimulusSet;
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The schema version new records are written with.
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Prefix of every enveloped record, followed by the version as a little-endian `u32`.
+/// Unversioned records start with a `u64` length or N-level, which never has these bytes.
+const ENVELOPE_MAGIC: [u8; 4] = *b"NBKv";
+
+/// A value stored in the database, readable at any schema version it was ever written with.
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Decodes a payload written at `version` and upgrades it to the current layout.
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self>;
+}
+
+/// Serializes `value` at the current version, inside an envelope.
+pub fn encode<T: Versioned>(value: &T) -> bincode::Result<Vec<u8>> {
+    let mut bytes = ENVELOPE_MAGIC.to_vec();
+    bytes.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, value)?;
+    Ok(bytes)
+}
+
+/// Decodes a stored value of any version, returning it with the version it was stored at.
+/// Unversioned records are tried as version 1, then as version 0.
+pub fn decode<T: Versioned>(bytes: &[u8]) -> bincode::Result<(T, u32)> {
+    match split_envelope(bytes) {
+        Some((version, payload)) => Ok((T::decode_version(version, payload)?, version)),
+        None => T::decode_version(1, bytes)
+            .map(|value| (value, 1))
+            .or_else(|_| T::decode_version(0, bytes).map(|value| (value, 0))),
+    }
+}
+
+fn split_envelope(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let rest = bytes.strip_prefix(&ENVELOPE_MAGIC[..])?;
+    let version = u32::from_le_bytes(rest.get(..4)?.try_into().ok()?);
+    Some((version, &rest[4..]))
+}
+
+/// Deserializes one exact layout. Unlike `bincode::deserialize`, trailing bytes are an error,
+/// so a record is never mistaken for a shorter older layout that happens to be its prefix.
+fn strict<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+}
+
+fn unsupported(version: u32) -> bincode::Error {
+    Box::new(bincode::ErrorKind::Custom(format!("unsupported schema version {}", version)))
+}
+
+impl Versioned for UserSettings {
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self> {
+        match version {
+            0 => Ok(v1::UserSettings::from(strict::<v0::UserSettings>(bytes)?).into()),
+            1 => Ok(strict::<v1::UserSettings>(bytes)?.into()),
+            2..=CURRENT_VERSION => strict(bytes),
+            _ => Err(unsupported(version)),
+        }
+    }
+}
+
+impl Versioned for GameSession {
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self> {
+        match version {
+            0 => Ok(v2::GameSession::from(v1::GameSession::from(strict::<v0::GameSession>(bytes)?)).into()),
+            1 => Ok(v2::GameSession::from(strict::<v1::GameSession>(bytes)?).into()),
+            2 => Ok(strict::<v2::GameSession>(bytes)?.into()),
+            CURRENT_VERSION => strict(bytes),
+            _ => Err(unsupported(version)),
+        }
+    }
+}
+
+/// Custom sets were added in version 2, so older versions never stored one.
+impl Versioned for CustomStimulusSet {
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self> {
+        match version {
+            2..=CURRENT_VERSION => strict(bytes),
+            _ => Err(unsupported(version)),
+        }
+    }
+}
+
+/// Profiles were added in version 2, like custom sets.
+impl Versioned for Profile {
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self> {
+        match version {
+            2..=CURRENT_VERSION => strict(bytes),
+            _ => Err(unsupported(version)),
+        }
+    }
+}
+
+/// Checkpoints were added in version 2. An older one only stands for a session in progress,
+/// so it is dropped rather than upgraded.
+impl Versioned for SessionCheckpoint {
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self> {
+        match version {
+            2..=CURRENT_VERSION => strict(bytes),
+            _ => Err(unsupported(version)),
+        }
+    }
+}
+
+/// Summaries only live in the history index, which is rebuilt from the sessions whenever the
+/// schema changes, so only the current layout is ever read.
+impl Versioned for GameSessionSummary {
+    fn decode_version(version: u32, bytes: &[u8]) -> bincode::Result<Self> {
+        match version {
+            CURRENT_VERSION => strict(bytes),
+            _ => Err(unsupported(version)),
+        }
+    }
+}
+
+/// Layouts of the first release, before the auditory set could be chosen.
+mod v0 {
+    use super::v1;
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct UserSettings {
+        pub n_level: usize,
+        pub speed_ms: u64,
+        pub session_length: usize,
+    }
+
+    #[derive(Deserialize)]
+    pub struct GameSession {
+        pub id: String,
+        pub timestamp: DateTime<Utc>,
+        pub settings: UserSettings,
+        pub event_history: Vec<v1::GameEvent>,
+        pub visual_stats: v1::AccuracyStats,
+        pub audio_stats: v1::AccuracyStats,
+    }
+
+    impl From<UserSettings> for v1::UserSettings {
+        fn from(settings: UserSettings) -> Self {
+            Self {
+                n_level: settings.n_level,
+                speed_ms: settings.speed_ms,
+                session_length: settings.session_length,
+                // The only set there was
+                auditory_stimulus_set: v1::AuditoryStimulusSet::AllLetters,
+            }
+        }
+    }
+
+    impl From<GameSession> for v1::GameSession {
+        fn from(session: GameSession) -> Self {
+            Self {
+                id: session.id,
+                timestamp: session.timestamp,
+                settings: session.settings.into(),
+                event_history: session.event_history,
+                visual_stats: session.visual_stats,
+                audio_stats: session.audio_stats,
+            }
+        }
+    }
+}
+
+/// Layouts of dual N-back with a choice of built-in auditory sets.
+mod v1 {
+    use super::v2;
+    use crate::game;
+    use crate::persistence::{self, SessionOutcome};
+    use crate::sequence_generator::{self, Modality};
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[derive(Deserialize)]
+    pub enum AuditoryStimulusSet {
+        AllLetters,
+        NonConfusingLetters,
+        TianGanDiZhi,
+    }
+
+    #[derive(Deserialize)]
+    pub struct UserSettings {
+        pub n_level: usize,
+        pub speed_ms: u64,
+        pub session_length: usize,
+        pub auditory_stimulus_set: AuditoryStimulusSet,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Stimulus {
+        pub visual: u8,
+        pub audio: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct UserResponse {
+        pub visual_match: bool,
+        pub audio_match: bool,
+    }
+
+    #[derive(Deserialize)]
+    pub struct GameEvent {
+        pub turn_index: usize,
+        pub stimulus: Stimulus,
+        pub is_visual_match: bool,
+        pub is_audio_match: bool,
+        pub user_response: UserResponse,
+    }
+
+    #[derive(Deserialize)]
+    pub struct AccuracyStats {
+        pub true_positives: u32,
+        pub true_negatives: u32,
+        pub false_positives: u32,
+        pub false_negatives: u32,
+    }
+
+    #[derive(Deserialize)]
+    pub struct GameSession {
+        pub id: String,
+        pub timestamp: DateTime<Utc>,
+        pub settings: UserSettings,
+        pub event_history: Vec<GameEvent>,
+        pub visual_stats: AccuracyStats,
+        pub audio_stats: AccuracyStats,
+    }
+
+    impl From<AuditoryStimulusSet> for sequence_generator::AuditoryStimulusSet {
+        fn from(set: AuditoryStimulusSet) -> Self {
+            match set {
+                AuditoryStimulusSet::AllLetters => Self::AllLetters,
+                AuditoryStimulusSet::NonConfusingLetters => Self::NonConfusingLetters,
+                AuditoryStimulusSet::TianGanDiZhi => Self::TianGanDiZhi,
+            }
+        }
+    }
+
+    impl From<UserSettings> for persistence::UserSettings {
+        fn from(settings: UserSettings) -> Self {
+            Self {
+                n_level: settings.n_level,
+                speed_ms: settings.speed_ms,
+                session_length: settings.session_length,
+                auditory_stimulus_set: settings.auditory_stimulus_set.into(),
+                ..Default::default()
+            }
+        }
+    }
+
+    fn modalities(visual: bool, audio: bool) -> BTreeSet<Modality> {
+        [(Modality::Position, visual), (Modality::Audio, audio)]
+            .into_iter()
+            .filter_map(|(modality, present)| present.then_some(modality))
+            .collect()
+    }
+
+    impl GameEvent {
+        fn upgrade(self, n_back: usize) -> game::GameEvent {
+            game::GameEvent {
+                turn_index: self.turn_index,
+                stimulus: game::Stimulus(BTreeMap::from([
+                    (Modality::Position, self.stimulus.visual.to_string()),
+                    (Modality::Audio, self.stimulus.audio),
+                ])),
+                n_back,
+                matches: modalities(self.is_visual_match, self.is_audio_match),
+                lures: BTreeSet::new(),
+                user_response: game::UserResponse {
+                    matched: modalities(self.user_response.visual_match, self.user_response.audio_match),
+                },
+                reaction_times_ms: BTreeMap::new(),
+                pauses: Vec::new(),
+                level_change: None,
+            }
+        }
+    }
+
+    impl From<AccuracyStats> for game::AccuracyStats {
+        fn from(stats: AccuracyStats) -> Self {
+            Self {
+                true_positives: stats.true_positives,
+                true_negatives: stats.true_negatives,
+                false_positives: stats.false_positives,
+                false_negatives: stats.false_negatives,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl From<GameSession> for v2::GameSession {
+        fn from(session: GameSession) -> Self {
+            let n_level = session.settings.n_level;
+            Self {
+                id: session.id,
+                timestamp: session.timestamp,
+                settings: session.settings.into(),
+                // Sequences were not seeded yet, so these sessions cannot be replayed
+                seed: 0,
+                event_history: session.event_history.into_iter().map(|e| e.upgrade(n_level)).collect(),
+                stats: BTreeMap::from([
+                    (Modality::Position, session.visual_stats.into()),
+                    (Modality::Audio, session.audio_stats.into()),
+                ]),
+                outcome: SessionOutcome::Completed,
+            }
+        }
+    }
+}
+
+/// Layouts from before sessions could be annotated.
+mod v2 {
+    use crate::game::{AccuracyStats, GameEvent};
+    use crate::persistence::{self, SessionOutcome, UserSettings};
+    use crate::sequence_generator::Modality;
+    use chrono::{DateTime, Utc};
+    use serde::Deserialize;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[derive(Deserialize)]
+    pub struct GameSession {
+        pub id: String,
+        pub timestamp: DateTime<Utc>,
+        pub settings: UserSettings,
+        pub seed: u64,
+        pub event_history: Vec<GameEvent>,
+        pub stats: BTreeMap<Modality, AccuracyStats>,
+        pub outcome: SessionOutcome,
+    }
+
+    impl From<GameSession> for persistence::GameSession {
+        fn from(session: GameSession) -> Self {
+            Self {
+                id: session.id,
+                timestamp: session.timestamp,
+                settings: session.settings,
+                seed: session.seed,
+                event_history: session.event_history,
+                stats: session.stats,
+                outcome: session.outcome,
+                notes: String::new(),
+                tags: BTreeSet::new(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence_generator::{AuditoryStimulusSet, Modality};
+    use chrono::{TimeZone, Utc};
+    use serde::Serialize;
+    use std::collections::BTreeMap;
+
+    // Writers for the old layouts, mirroring the structs as they were serialized back then
+
+    #[derive(Serialize)]
+    struct V0Settings {
+        n_level: usize,
+        speed_ms: u64,
+        session_length: usize,
+    }
+
+    #[derive(Serialize)]
+    struct V1Settings {
+        n_level: usize,
+        speed_ms: u64,
+        session_length: usize,
+        auditory_stimulus_set: u32,
+    }
+
+    #[derive(Serialize)]
+    struct V1Event {
+        turn_index: usize,
+        visual: u8,
+        audio: String,
+        is_visual_match: bool,
+        is_audio_match: bool,
+        visual_match: bool,
+        audio_match: bool,
+    }
+
+    #[derive(Serialize)]
+    struct V1Session<S> {
+        id: String,
+        timestamp: chrono::DateTime<Utc>,
+        settings: S,
+        event_history: Vec<V1Event>,
+        visual_stats: [u32; 4],
+        audio_stats: [u32; 4],
+    }
+
+    fn v1_session<S: Serialize>(settings: S) -> Vec<u8> {
+        let event = |turn_index, visual_match| V1Event {
+            turn_index,
+            visual: 4,
+            audio: "C".to_string(),
+            is_visual_match: visual_match,
+            is_audio_match: false,
+            visual_match,
+            audio_match: true,
+        };
+        bincode::serialize(&V1Session {
+            id: "session_1".to_string(),
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            settings,
+            event_history: vec![event(0, false), event(1, false), event(2, true)],
+            visual_stats: [1, 0, 0, 0],
+            audio_stats: [0, 0, 1, 0],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_at_current_version() {
+        let settings = UserSettings { n_level: 4, ..Default::default() };
+        let bytes = encode(&settings).unwrap();
+        assert!(bytes.starts_with(&ENVELOPE_MAGIC));
+        let (decoded, version): (UserSettings, u32) = decode(&bytes).unwrap();
+        assert_eq!(version, CURRENT_VERSION);
+        assert_eq!(decoded.n_level, 4);
+    }
+
+    #[test]
+    fn test_unversioned_settings_are_upgraded() {
+        let v1 = V1Settings { n_level: 3, speed_ms: 1500, session_length: 25, auditory_stimulus_set: 2 };
+        let (settings, version): (UserSettings, u32) = decode(&bincode::serialize(&v1).unwrap()).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!((settings.n_level, settings.speed_ms, settings.session_length), (3, 1500, 25));
+        assert_eq!(settings.auditory_stimulus_set, AuditoryStimulusSet::TianGanDiZhi);
+        assert_eq!(settings.modalities, vec![Modality::Position, Modality::Audio]);
+
+        let v0 = V0Settings { n_level: 5, speed_ms: 2500, session_length: 30 };
+        let (settings, version): (UserSettings, u32) = decode(&bincode::serialize(&v0).unwrap()).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(settings.n_level, 5);
+        assert_eq!(settings.auditory_stimulus_set, AuditoryStimulusSet::AllLetters);
+    }
+
+    #[test]
+    fn test_unversioned_sessions_are_upgraded() {
+        let settings = V1Settings { n_level: 2, speed_ms: 2000, session_length: 3, auditory_stimulus_set: 1 };
+        let (session, version): (GameSession, u32) = decode(&v1_session(settings)).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(session.id, "session_1");
+        assert_eq!(session.settings.auditory_stimulus_set, AuditoryStimulusSet::NonConfusingLetters);
+        assert_eq!(session.event_history.len(), 3);
+        let last = &session.event_history[2];
+        assert_eq!(last.n_back, 2);
+        assert_eq!(last.stimulus.position(), Some(4));
+        assert_eq!(last.stimulus.get(Modality::Audio), Some("C"));
+        assert!(last.matches.contains(&Modality::Position));
+        assert!(last.user_response.claims(Modality::Audio));
+        assert_eq!(session.stats[&Modality::Position].true_positives, 1);
+        assert_eq!(session.stats[&Modality::Audio].false_positives, 1);
+
+        let settings = V0Settings { n_level: 2, speed_ms: 2000, session_length: 3 };
+        let (session, version): (GameSession, u32) = decode(&v1_session(settings)).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(session.settings.auditory_stimulus_set, AuditoryStimulusSet::AllLetters);
+        assert_eq!(session.event_history.len(), 3);
+    }
+
+    #[test]
+    fn test_version_2_sessions_get_empty_annotations() {
+        let session = GameSession::new(UserSettings::default(), 7, vec![], BTreeMap::new());
+        // Version 2 had every field up to the outcome
+        let v2 = (
+            &session.id,
+            session.timestamp,
+            &session.settings,
+            session.seed,
+            &session.event_history,
+            &session.stats,
+            session.outcome,
+        );
+        let mut bytes = ENVELOPE_MAGIC.to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend(bincode::serialize(&v2).unwrap());
+
+        let (upgraded, version): (GameSession, u32) = decode(&bytes).unwrap();
+        assert_eq!(version, 2);
+        assert_eq!((upgraded.id, upgraded.seed), (session.id, 7));
+        assert!(upgraded.notes.is_empty() && upgraded.tags.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_and_garbled_records_are_errors() {
+        let mut bytes = ENVELOPE_MAGIC.to_vec();
+        bytes.extend_from_slice(&(CURRENT_VERSION + 1).to_le_bytes());
+        bytes.extend(bincode::serialize(&UserSettings::default()).unwrap());
+        assert!(decode::<UserSettings>(&bytes).is_err());
+        assert!(decode::<UserSettings>(b"not a record").is_err());
+        assert!(decode::<SessionCheckpoint>(&bincode::serialize(&0u64).unwrap()).is_err());
+    }
+}
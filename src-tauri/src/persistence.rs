@@ -1,60 +1,166 @@
 use serde::{Deserialize, Serialize};
-use sled::Db;
-use std::sync::Mutex;
-use crate::game::{AccuracyStats, GameEvent};
-use crate::sequence_generator::AuditoryStimulusSet;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::difficulty::{self, DifficultyPolicy, LevelChange, Staircase};
+use crate::game::{self, AccuracyStats, GameEvent, ReactionTimeStats};
+use crate::signal_detection::{RateCorrection, SdtMetrics};
+use crate::sequence_generator::{
+    self, AuditoryStimulusSet, GeneratorError, GridLayout, MatchDistribution, Modality, NLevelMode,
+};
 use chrono::{DateTime, Utc};
 
 // --- User Settings ---
 
+/// Turns a session may have.
+pub const SESSION_LENGTH_RANGE: std::ops::RangeInclusive<usize> = 10..=100;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserSettings {
     pub n_level: usize,
+    /// Whether every turn uses `n_level`, or draws its own N up to it.
+    #[serde(default)]
+    pub n_level_mode: NLevelMode,
     pub speed_ms: u64,
     pub session_length: usize,
     pub auditory_stimulus_set: AuditoryStimulusSet,
+    /// The modalities tracked in a session. Older settings fall back to dual position + audio.
+    #[serde(default = "default_modalities")]
+    pub modalities: Vec<Modality>,
+    /// Arrangement of the position modality's cells. Older settings fall back to a 3x3 grid.
+    #[serde(default)]
+    pub grid_layout: GridLayout,
+    /// How many turns should be matches. Older settings fall back to the default 20% ratio.
+    #[serde(default)]
+    pub match_distribution: MatchDistribution,
+    /// How many N-1 / N+1 lures each modality should contain. Missing modalities get none.
+    #[serde(default)]
+    pub lure_rates: BTreeMap<Modality, f32>,
+    /// Fixed seed for the stimulus sequence. `None` draws a fresh seed for every session.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Whether sessions ended early are kept in the history.
+    #[serde(default)]
+    pub aborted_sessions: AbortedSessionPolicy,
+    /// How `recommend_next_settings` picks the next session's difficulty.
+    #[serde(default)]
+    pub difficulty_policy: DifficultyPolicy,
+    /// Adapts the level block by block within a session. `None` keeps it fixed.
+    #[serde(default)]
+    pub staircase: Option<Staircase>,
 }
 
 impl Default for UserSettings {
     fn default() -> Self {
         Self {
             n_level: 2,
+            n_level_mode: NLevelMode::Fixed,
             speed_ms: 2000,
             session_length: 20,
             auditory_stimulus_set: AuditoryStimulusSet::AllLetters,
+            modalities: default_modalities(),
+            grid_layout: GridLayout::default(),
+            match_distribution: MatchDistribution::default(),
+            lure_rates: BTreeMap::new(),
+            seed: None,
+            aborted_sessions: AbortedSessionPolicy::default(),
+            difficulty_policy: DifficultyPolicy::default(),
+            staircase: None,
         }
     }
 }
 
+fn default_modalities() -> Vec<Modality> {
+    vec![Modality::Position, Modality::Audio]
+}
+
+impl UserSettings {
+    /// Checks the settings the sequence generator depends on before they are stored or used.
+    pub fn validate(&self) -> Result<(), GeneratorError> {
+        if !SESSION_LENGTH_RANGE.contains(&self.session_length) {
+            return Err(GeneratorError::InvalidSessionLength(self.session_length));
+        }
+        sequence_generator::validate_modalities(&self.modalities)?;
+        self.grid_layout.validate()?;
+        let repeat_streams = self.modalities.iter().filter(|m| m.scores_repeats()).count();
+        self.match_distribution.validate(self.n_level, self.session_length, repeat_streams)?;
+        for rate in self.lure_rates.values() {
+            sequence_generator::validate_lure_rate(*rate)?;
+        }
+        if let Some(staircase) = &self.staircase {
+            staircase.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// What happens to a session that is aborted before its last turn.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AbortedSessionPolicy {
+    #[default]
+    Discard,
+    /// Save the turns played so far as a partial session.
+    KeepPartial,
+}
+
 // --- Game Session History ---
 
+/// How a saved session ended.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SessionOutcome {
+    #[default]
+    Completed,
+    /// Aborted early; the session only holds the turns played before that.
+    Aborted,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GameSession {
     pub id: String,
     pub timestamp: DateTime<Utc>,
     pub settings: UserSettings,
+    /// The seed the stimulus sequence was generated from, so the session can be replayed.
+    pub seed: u64,
     pub event_history: Vec<GameEvent>,
-    pub visual_stats: AccuracyStats,
-    pub audio_stats: AccuracyStats,
+    pub stats: BTreeMap<Modality, AccuracyStats>,
+    #[serde(default)]
+    pub outcome: SessionOutcome,
+    /// Free-text context, e.g. how the user felt.
+    #[serde(default)]
+    pub notes: String,
+    /// Labels to group and filter sessions by, e.g. "tired" or "experiment-A".
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
 }
 
 impl GameSession {
     pub fn new(
         settings: UserSettings,
+        seed: u64,
         event_history: Vec<GameEvent>,
-        visual_stats: AccuracyStats,
-        audio_stats: AccuracyStats,
+        stats: BTreeMap<Modality, AccuracyStats>,
     ) -> Self {
         let now = Utc::now();
         Self {
             id: format!("session_{}", now.timestamp_nanos_opt().unwrap_or_default()),
             timestamp: now,
             settings,
+            seed,
             event_history,
-            visual_stats,
-            audio_stats,
+            stats,
+            outcome: SessionOutcome::Completed,
+            notes: String::new(),
+            tags: BTreeSet::new(),
         }
     }
+
+    /// Replaces the notes and tags. Tags are trimmed, and empty ones are dropped.
+    pub fn annotate(&mut self, notes: String, tags: impl IntoIterator<Item = String>) {
+        self.notes = notes;
+        self.tags = tags
+            .into_iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+    }
 }
 
 /// A lighter version of GameSession for list views, omitting the heavy event history.
@@ -63,8 +169,22 @@ pub struct GameSessionSummary {
     pub id: String,
     pub timestamp: DateTime<Utc>,
     pub settings: UserSettings,
-    pub visual_stats: AccuracyStats,
-    pub audio_stats: AccuracyStats,
+    pub seed: u64,
+    pub outcome: SessionOutcome,
+    /// Turns actually played; shorter than the session length for aborted sessions.
+    pub turns_played: usize,
+    pub stats: BTreeMap<Modality, AccuracyStats>,
+    /// Accuracy per N asked for, so variable-N sessions can be broken down by level.
+    pub stats_by_n_back: BTreeMap<usize, BTreeMap<Modality, AccuracyStats>>,
+    /// Mean and median reaction time of each modality's hits.
+    pub reaction_times: BTreeMap<Modality, ReactionTimeStats>,
+    /// d', c, β, A′ and B″ of each modality, with log-linear corrected rates. Modalities
+    /// without both match and non-match turns are left out.
+    pub sdt: BTreeMap<Modality, SdtMetrics>,
+    /// The level a within-session staircase converged on, if it reversed at least once.
+    pub staircase_threshold: Option<f64>,
+    pub notes: String,
+    pub tags: BTreeSet<String>,
 }
 
 impl From<&GameSession> for GameSessionSummary {
@@ -73,161 +193,54 @@ impl From<&GameSession> for GameSessionSummary {
             id: session.id.clone(),
             timestamp: session.timestamp,
             settings: session.settings.clone(),
-            visual_stats: session.visual_stats.clone(),
-            audio_stats: session.audio_stats.clone(),
-        }
-    }
-}
-
-// --- Database Interaction ---
-
-pub struct DbState(pub Mutex<Db>);
-
-const SETTINGS_KEY: &str = "user_settings";
-const SESSIONS_TREE: &str = "game_sessions";
-
-pub fn save_settings(db: &Db, settings: &UserSettings) -> Result<(), sled::Error> {
-    let bytes = bincode::serialize(settings).unwrap();
-    db.insert(SETTINGS_KEY, bytes)?;
-    Ok(())
-}
-
-pub fn load_settings(db: &Db) -> Result<UserSettings, sled::Error> {
-    match db.get(SETTINGS_KEY)? {
-        Some(bytes) => {
-            let settings = bincode::deserialize(&bytes).unwrap_or_else(|_| UserSettings::default());
-            Ok(settings)
-        }
-        None => Ok(UserSettings::default()),
-    }
-}
-
-pub fn save_session(db: &Db, session: &GameSession) -> Result<(), sled::Error> {
-    let tree = db.open_tree(SESSIONS_TREE)?;
-    let bytes = bincode::serialize(session).unwrap();
-    tree.insert(session.id.as_bytes(), bytes)?;
-    Ok(())
-}
-
-pub fn load_all_sessions(db: &Db) -> Result<Vec<GameSessionSummary>, sled::Error> {
-    let tree = db.open_tree(SESSIONS_TREE)?;
-    let mut summaries: Vec<GameSessionSummary> = Vec::new();
-    for item in tree.iter() {
-        let (_, bytes) = item?;
-        // We still deserialize the full session, but immediately convert it to a summary
-        match bincode::deserialize::<GameSession>(&bytes) {
-            Ok(session) => summaries.push((&session).into()),
-            Err(e) => {
-                eprintln!("Skipping session due to deserialization error: {}", e);
-            }
-        }
-    }
-    // Sort by timestamp, newest first
-    summaries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
-    Ok(summaries)
-}
-
-pub fn load_session_by_id(db: &Db, session_id: &str) -> Result<Option<GameSession>, sled::Error> {
-    let tree = db.open_tree(SESSIONS_TREE)?;
-    match tree.get(session_id.as_bytes())? {
-        Some(bytes) => {
-            match bincode::deserialize::<GameSession>(&bytes) {
-                Ok(session) => Ok(Some(session)),
-                Err(e) => {
-                    eprintln!("Failed to deserialize session {}: {}", session_id, e);
-                    Ok(None)
-                }
-            }
+            seed: session.seed,
+            outcome: session.outcome,
+            turns_played: session.event_history.len(),
+            stats: session.stats.clone(),
+            stats_by_n_back: game::stats_by_n_back(&session.event_history),
+            reaction_times: game::reaction_time_stats(&session.event_history),
+            sdt: session
+                .stats
+                .iter()
+                .filter_map(|(&modality, stats)| {
+                    stats.sdt_metrics(RateCorrection::LogLinear).map(|metrics| (modality, metrics))
+                })
+                .collect(),
+            staircase_threshold: session.settings.staircase.and_then(|staircase| {
+                let changes: Vec<LevelChange> =
+                    session.event_history.iter().filter_map(|e| e.level_change).collect();
+                difficulty::staircase_threshold(staircase.target, &changes)
+            }),
+            notes: session.notes.clone(),
+            tags: session.tags.clone(),
         }
-        None => Ok(None),
     }
 }
 
-pub fn clear_all_data(db: &Db) -> Result<(), sled::Error> {
-    db.drop_tree(SESSIONS_TREE)?;
-    db.remove(SETTINGS_KEY)?;
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::game::{GameEvent, Stimulus, UserResponse};
-
-    fn get_temp_db() -> Db {
-        sled::Config::new().temporary(true).open().unwrap()
-    }
 
     #[test]
-    fn test_save_and_load_settings() {
-        let db = get_temp_db();
-        
-        // Test loading default settings
-        let loaded_settings = load_settings(&db).unwrap();
-        assert_eq!(loaded_settings.n_level, 2);
-        assert_eq!(loaded_settings.auditory_stimulus_set, AuditoryStimulusSet::AllLetters);
-
-
-        // Test saving and loading custom settings
-        let custom_settings = UserSettings {
-            n_level: 3,
-            speed_ms: 1500,
-            session_length: 25,
-            auditory_stimulus_set: AuditoryStimulusSet::NonConfusingLetters,
-        };
-        save_settings(&db, &custom_settings).unwrap();
-        let loaded_settings = load_settings(&db).unwrap();
-        assert_eq!(loaded_settings.n_level, 3);
-        assert_eq!(loaded_settings.session_length, 25);
-        assert_eq!(loaded_settings.auditory_stimulus_set, AuditoryStimulusSet::NonConfusingLetters);
-    }
+    fn test_settings_validation() {
+        assert!(UserSettings::default().validate().is_ok());
 
-    #[test]
-    fn test_save_and_load_sessions() {
-        let db = get_temp_db();
-        
-        // Initially, there should be no sessions
-        let summaries = load_all_sessions(&db).unwrap();
-        assert!(summaries.is_empty());
-
-        // Create and save two sessions
-        let settings = UserSettings::default();
-        let event_history1 = vec![GameEvent {
-            turn_index: 0,
-            stimulus: Stimulus { visual: 1, audio: "A".to_string() },
-            is_visual_match: false,
-            is_audio_match: false,
-            user_response: UserResponse::default(),
-        }];
-        let stats1 = AccuracyStats { true_positives: 1, ..Default::default() };
-        let session1 = GameSession::new(settings.clone(), event_history1, stats1.clone(), stats1.clone());
-        
-        std::thread::sleep(std::time::Duration::from_millis(10));
-
-        let event_history2 = vec![]; // Empty for simplicity
-        let stats2 = AccuracyStats { true_positives: 2, ..Default::default() };
-        let session2 = GameSession::new(settings.clone(), event_history2, stats2.clone(), stats2.clone());
-
-        save_session(&db, &session1).unwrap();
-        save_session(&db, &session2).unwrap();
-
-        // Load session summaries and check
-        let summaries = load_all_sessions(&db).unwrap();
-        assert_eq!(summaries.len(), 2);
-        
-        // Check if they are sorted correctly (newest first) and data is intact
-        assert_eq!(summaries[0].id, session2.id);
-        assert_eq!(summaries[0].visual_stats.true_positives, 2);
-        assert_eq!(summaries[1].visual_stats.true_positives, 1);
-
-        // Load a single full session by ID and check its details
-        let loaded_session1 = load_session_by_id(&db, &session1.id).unwrap().unwrap();
-        assert_eq!(loaded_session1.id, session1.id);
-        assert_eq!(loaded_session1.event_history.len(), 1);
-        assert_eq!(loaded_session1.event_history[0].stimulus.visual, 1);
-
-        // Test loading a non-existent session
-        let non_existent = load_session_by_id(&db, "non-existent-id").unwrap();
-        assert!(non_existent.is_none());
+        let mut settings = UserSettings::default();
+        settings.modalities.clear();
+        assert_eq!(settings.validate(), Err(GeneratorError::NoModalities));
+
+        let mut settings = UserSettings::default();
+        settings.lure_rates.insert(Modality::Audio, 1.5);
+        assert_eq!(settings.validate(), Err(GeneratorError::InvalidLureRate(1.5)));
+
+        let mut settings = UserSettings { grid_layout: GridLayout::Ring { positions: 1 }, ..Default::default() };
+        assert_eq!(settings.validate(), Err(GeneratorError::InvalidGridLayout(settings.grid_layout)));
+        settings.grid_layout = GridLayout::Square { size: 9 };
+        assert!(settings.validate().is_err());
+
+        for session_length in [9, 101] {
+            let settings = UserSettings { session_length, ..Default::default() };
+            assert_eq!(settings.validate(), Err(GeneratorError::InvalidSessionLength(session_length)));
+        }
     }
 }
@@ -0,0 +1,123 @@
+use crate::storage::{Storage, StorageError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The profile that owns the data stored before profiles existed. It cannot be deleted.
+pub const DEFAULT_PROFILE_ID: &str = "default";
+const DEFAULT_PROFILE_NAME: &str = "Default";
+
+/// A person training on this installation, with their own settings and history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Creates the default profile if the store does not hold it yet, as on first use or after
+/// all data was cleared. Every backend calls this when it opens.
+pub(crate) fn ensure_default_profile(db: &dyn Storage) -> Result<(), StorageError> {
+    if db.load_profile(DEFAULT_PROFILE_ID)?.is_none() {
+        db.save_profile(&Profile {
+            id: DEFAULT_PROFILE_ID.to_string(),
+            name: DEFAULT_PROFILE_NAME.to_string(),
+            created_at: Utc::now(),
+        })?;
+    }
+    Ok(())
+}
+
+pub fn load_profile(db: &dyn Storage, profile_id: &str) -> Result<Profile, ProfileError> {
+    db.load_profile(profile_id)?
+        .ok_or_else(|| ProfileError::NotFound(profile_id.to_string()))
+}
+
+/// Makes `profile_id` the active profile, also for later startups.
+pub fn switch_profile(db: &mut dyn Storage, profile_id: &str) -> Result<(), ProfileError> {
+    load_profile(db, profile_id)?;
+    db.set_active_profile(profile_id)?;
+    Ok(())
+}
+
+pub fn create_profile(db: &dyn Storage, name: &str) -> Result<Profile, ProfileError> {
+    let name = validate_name(db, name, None)?;
+    let now = Utc::now();
+    let profile = Profile {
+        id: format!("profile_{}", now.timestamp_nanos_opt().unwrap_or_default()),
+        name,
+        created_at: now,
+    };
+    db.save_profile(&profile)?;
+    Ok(profile)
+}
+
+pub fn rename_profile(db: &dyn Storage, profile_id: &str, name: &str) -> Result<Profile, ProfileError> {
+    let mut profile = load_profile(db, profile_id)?;
+    profile.name = validate_name(db, name, Some(profile_id))?;
+    db.save_profile(&profile)?;
+    Ok(profile)
+}
+
+/// Deletes a profile with its settings and history. The default and the active profile
+/// cannot be deleted.
+pub fn delete_profile(db: &dyn Storage, profile_id: &str) -> Result<(), ProfileError> {
+    if profile_id == DEFAULT_PROFILE_ID {
+        return Err(ProfileError::DeleteDefault);
+    }
+    if profile_id == db.profile_id() {
+        return Err(ProfileError::DeleteActive);
+    }
+    load_profile(db, profile_id)?;
+    db.remove_profile(profile_id)?;
+    Ok(())
+}
+
+/// Trims `name` and checks it is non-empty and not taken by another profile.
+fn validate_name(db: &dyn Storage, name: &str, renaming: Option<&str>) -> Result<String, ProfileError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(ProfileError::EmptyName);
+    }
+    let taken = db
+        .list_profiles()?
+        .iter()
+        .any(|p| Some(p.id.as_str()) != renaming && p.name.eq_ignore_ascii_case(name));
+    if taken {
+        return Err(ProfileError::DuplicateName(name.to_string()));
+    }
+    Ok(name.to_string())
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    EmptyName,
+    DuplicateName(String),
+    NotFound(String),
+    DeleteDefault,
+    DeleteActive,
+    Storage(StorageError),
+}
+
+impl From<StorageError> for ProfileError {
+    fn from(e: StorageError) -> Self {
+        ProfileError::Storage(e)
+    }
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::EmptyName => write!(f, "Profile name must not be empty"),
+            ProfileError::DuplicateName(name) => write!(f, "A profile named \"{}\" already exists", name),
+            ProfileError::NotFound(id) => write!(f, "Profile {} does not exist", id),
+            ProfileError::DeleteDefault => write!(f, "The default profile cannot be deleted"),
+            ProfileError::DeleteActive => {
+                write!(f, "The active profile cannot be deleted; switch to another profile first")
+            }
+            ProfileError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
@@ -0,0 +1,302 @@
+//! Startup recovery from a damaged database.
+//!
+//! When the store cannot be opened, or fails its integrity check, because it is damaged, the
+//! broken database is moved into the `quarantine` directory of the app data and a new one is
+//! started from the newest backup. Every record that can still be read from the broken
+//! database is then added back, since it is newer than any backup. The quarantined files are
+//! kept for a closer look.
+
+use crate::backups;
+use crate::error::AppError;
+use crate::persistence::UserSettings;
+use crate::profiles::Profile;
+use crate::storage::{self, Salvage, Storage, StorageError};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Directory of the quarantined databases inside the app-data directory.
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+/// The report of the recovery done at startup, until the frontend has shown it.
+pub struct RecoveryState(pub Mutex<Option<RecoveryReport>>);
+
+/// An error found at startup that the app started despite, until the frontend has shown it.
+pub struct StartupErrorState(pub Mutex<Option<AppError>>);
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RecoveryReport {
+    /// The damage that was found.
+    pub problem: String,
+    /// Where the damaged database was moved.
+    pub quarantine_dir: PathBuf,
+    /// The backup the new database was started from, if there was a usable one.
+    pub restored_backup: Option<String>,
+    /// Profiles and sessions read back from the damaged database.
+    pub recovered_profiles: usize,
+    pub recovered_sessions: usize,
+    /// Records, or runs of records, in the damaged database that could not be read.
+    pub unreadable_records: usize,
+}
+
+/// Opens the store in the app-data directory, recovering it first if it is damaged. Errors
+/// other than damage are returned as they are, leaving the database untouched.
+pub fn open_or_recover(
+    app_data_dir: &Path,
+    now: DateTime<Utc>,
+) -> Result<(Box<dyn Storage>, Option<RecoveryReport>), StorageError> {
+    let problem = match storage::open(app_data_dir).and_then(|db| db.check_integrity().map(|()| db)) {
+        Ok(db) => return Ok((db, None)),
+        Err(e) if e.is_damage() => e.to_string(),
+        Err(e) => return Err(e),
+    };
+    eprintln!("Recovering the damaged database: {}", problem);
+    // The new store keeps the backend of the damaged one, rather than that for new stores
+    let backend = storage::active_backend(app_data_dir);
+    let quarantine_dir = quarantine(app_data_dir, now)?;
+    let mut db = storage::open_as(backend, app_data_dir)?;
+    let restored_backup = restore_newest_backup(&mut *db, &app_data_dir.join(backups::BACKUP_DIR));
+
+    let salvage = match storage::database_files(&quarantine_dir).first() {
+        Some(path) => storage::salvage(path).unwrap_or_else(|e| {
+            eprintln!("Nothing could be read from the damaged database: {}", e);
+            Salvage::default()
+        }),
+        None => Salvage::default(),
+    };
+    merge(&mut *db, &salvage)?;
+    db.flush()?;
+
+    let report = RecoveryReport {
+        problem,
+        quarantine_dir,
+        restored_backup,
+        recovered_profiles: salvage.profiles.len(),
+        recovered_sessions: salvage.sessions.len(),
+        unreadable_records: salvage.unreadable,
+    };
+    Ok((db, Some(report)))
+}
+
+/// The settings to start with, and the error if the stored ones cannot be read. In that case
+/// the app starts with the defaults, held in memory only: the stored record is left as it is
+/// until the user saves new settings.
+pub fn startup_settings(db: &dyn Storage) -> (UserSettings, Option<AppError>) {
+    match db.load_settings() {
+        Ok(settings) => (settings, None),
+        Err(e) => {
+            eprintln!("Cannot read the settings: {}", e);
+            (UserSettings::default(), Some(e.into()))
+        }
+    }
+}
+
+/// Moves the database files into a new directory under `quarantine`, named after `now`.
+fn quarantine(app_data_dir: &Path, now: DateTime<Utc>) -> Result<PathBuf, StorageError> {
+    let dir = app_data_dir.join(QUARANTINE_DIR).join(now.format("%Y%m%dT%H%M%S%.3fZ").to_string());
+    std::fs::create_dir_all(&dir)?;
+    for path in storage::database_files(app_data_dir) {
+        if let Some(name) = path.file_name() {
+            std::fs::rename(&path, dir.join(name))?;
+        }
+    }
+    Ok(dir)
+}
+
+/// Fills the new store from the newest backup that can be read. Returns its ID.
+fn restore_newest_backup(db: &mut dyn Storage, dir: &Path) -> Option<String> {
+    for backup in backups::list_backups(dir).unwrap_or_default() {
+        let restored = db.clear_all_data().and_then(|()| {
+            let mut from = storage::open_snapshot(&dir.join(&backup.id))?;
+            storage::copy_storage(&mut *from, db)
+        });
+        match restored {
+            Ok(_) => return Some(backup.id),
+            Err(e) => eprintln!("Skipping backup {}: {}", backup.id, e),
+        }
+    }
+    None
+}
+
+/// Adds the salvaged records, replacing the ones with the same IDs. Settings and sessions of
+/// a profile whose own record was lost are kept under a new profile with the same ID.
+fn merge(db: &mut dyn Storage, salvage: &Salvage) -> Result<(), StorageError> {
+    let active = db.profile_id().to_string();
+    for profile in &salvage.profiles {
+        db.save_profile(profile)?;
+    }
+    let owners: BTreeSet<&str> = salvage
+        .settings
+        .iter()
+        .map(|(id, _)| id.as_str())
+        .chain(salvage.sessions.iter().map(|(id, _)| id.as_str()))
+        .collect();
+    for id in owners {
+        if db.load_profile(id)?.is_none() {
+            let name = format!("Recovered {}", id);
+            db.save_profile(&Profile { id: id.to_string(), name, created_at: Utc::now() })?;
+        }
+    }
+
+    for (id, settings) in &salvage.settings {
+        db.set_active_profile(id)?;
+        db.save_settings(settings)?;
+    }
+    for (id, session) in &salvage.sessions {
+        if db.profile_id() != id {
+            db.set_active_profile(id)?;
+        }
+        db.save_session(session)?;
+    }
+    for set in &salvage.stimulus_sets {
+        db.save_stimulus_set(set)?;
+    }
+    db.set_active_profile(&active)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, 1, 9, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_healthy_database_is_opened_as_is() {
+        let dir = conformance::temp_path("recovery-healthy");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (db, report) = open_or_recover(&dir, now()).unwrap();
+        assert!(report.is_none());
+        conformance::save_daily_sessions(&*db, 2);
+        drop(db);
+
+        let (db, report) = open_or_recover(&dir, now()).unwrap();
+        assert!(report.is_none());
+        assert_eq!(db.load_all_sessions().unwrap().len(), 2);
+        assert!(!dir.join(QUARANTINE_DIR).exists());
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Fills a store in `dir` with sessions, three of them also in a backup.
+    #[cfg(feature = "sqlite-store")]
+    fn save_sessions_with_backup(dir: &Path) -> std::path::PathBuf {
+        let (db, _) = open_or_recover(dir, now()).unwrap();
+        conformance::save_daily_sessions(&*db, 3);
+        let backup_dir = dir.join(backups::BACKUP_DIR);
+        backups::create_backup(&*db, &backup_dir, backups::BackupReason::Scheduled, now()).unwrap();
+        conformance::save_daily_sessions(&*db, 5);
+        drop(db);
+        dir.join(storage::Backend::Sqlite.db_name())
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[test]
+    fn test_unreadable_database_is_replaced_by_the_newest_backup() {
+        let dir = conformance::temp_path("recovery-unreadable");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = save_sessions_with_backup(&dir);
+        // Overwrite the file header, so SQLite no longer recognizes the file
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[..100].fill(0xFF);
+        std::fs::write(&path, bytes).unwrap();
+
+        let (db, report) = open_or_recover(&dir, now()).unwrap();
+        let report = report.unwrap();
+        assert!(report.restored_backup.is_some());
+        assert_eq!(report.recovered_sessions, 0);
+        assert!(report.quarantine_dir.join(storage::Backend::Sqlite.db_name()).exists());
+        assert_eq!(db.load_all_sessions().unwrap().len(), 3);
+        db.check_integrity().unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(all(feature = "sled-store", feature = "sqlite-store"))]
+    #[test]
+    fn test_damaged_sled_store_is_recovered_as_sled() {
+        let dir = conformance::temp_path("recovery-sled");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(storage::Backend::Sled.db_name());
+        let db = storage::open_as(storage::Backend::Sled, &dir).unwrap();
+        conformance::save_daily_sessions(&*db, 2);
+        drop(db);
+        // A configuration sled cannot parse
+        std::fs::write(path.join("conf"), [b'#'; 64]).unwrap();
+
+        let (db, report) = open_or_recover(&dir, now()).unwrap();
+        assert!(report.is_some());
+        assert_eq!(storage::active_backend(&dir), storage::Backend::Sled);
+        assert!(!dir.join(storage::Backend::Sqlite.db_name()).exists());
+        db.check_integrity().unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[test]
+    fn test_unreadable_settings_are_reported_and_kept() {
+        let dir = conformance::temp_path("recovery-settings");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(storage::Backend::Sqlite.db_name());
+        let (db, _) = open_or_recover(&dir, now()).unwrap();
+        db.save_settings(&UserSettings { n_level: 4, ..Default::default() }).unwrap();
+        let (settings, error) = startup_settings(&*db);
+        assert_eq!((settings.n_level, error), (4, None));
+        drop(db);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute("UPDATE settings SET record = x'00'", []).unwrap();
+        drop(conn);
+
+        let (db, report) = open_or_recover(&dir, now()).unwrap();
+        assert!(report.is_none());
+        let (settings, error) = startup_settings(&*db);
+        assert_eq!(settings.n_level, UserSettings::default().n_level);
+        assert!(matches!(error, Some(AppError::Serialization(_))), "{:?}", error);
+        // Nothing was written over the stored record
+        assert!(db.load_settings().is_err());
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "sqlite-store")]
+    #[test]
+    fn test_readable_sessions_are_recovered_from_a_damaged_database() {
+        let dir = conformance::temp_path("recovery-damaged");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = save_sessions_with_backup(&dir);
+        // Break the timestamp index; the sessions themselves stay readable
+        let (root_page, page_size): (u64, u64) = {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            let root_page = conn
+                .query_row("SELECT rootpage FROM sqlite_master WHERE name = 'sessions_by_timestamp'", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+            (root_page, conn.pragma_query_value(None, "page_size", |row| row.get(0)).unwrap())
+        };
+        let mut bytes = std::fs::read(&path).unwrap();
+        let start = ((root_page - 1) * page_size) as usize;
+        bytes[start..start + page_size as usize].fill(0xFF);
+        std::fs::write(&path, bytes).unwrap();
+
+        let (db, report) = open_or_recover(&dir, now()).unwrap();
+        let report = report.unwrap();
+        assert!(report.problem.contains("damaged"), "{}", report.problem);
+        assert_eq!(report.recovered_sessions, 5);
+        assert_eq!(report.recovered_profiles, 1);
+        assert_eq!(db.load_all_sessions().unwrap().len(), 5);
+        drop(db);
+
+        // The recovered database is healthy again
+        let (_, report) = open_or_recover(&dir, now()).unwrap();
+        assert!(report.is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
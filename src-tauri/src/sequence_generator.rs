@@ -1,133 +1,683 @@
 use serde::{Deserialize, Serialize};
 use rand::seq::SliceRandom;
-use rand::thread_rng;
-use std::collections::HashSet;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::{BTreeMap, HashSet};
 use std::hash::Hash;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Hash)]
 pub enum AuditoryStimulusSet {
     AllLetters,
     NonConfusingLetters,
     TianGanDiZhi,
+    /// A user-registered set, referenced by its ID. Its tokens live in the database.
+    Custom(String),
 }
 
-const LOWER_BOUND_RATIO: f32 = 1.0 / 6.0;
-const UPPER_BOUND_RATIO: f32 = 1.0 / 4.0;
-const TARGET_RATIO: f32 = 1.0 / 5.0;
+/// A stimulus channel that can be tracked independently. A session uses one or more of them:
+/// single, dual, triple or quad N-back.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum Modality {
+    Position,
+    Audio,
+    Color,
+    Shape,
+    Image,
+    /// A digit on every turn, with an operation from the first turn that has an N-back
+    /// predecessor. The user answers the digit N turns back combined with the current one by
+    /// the operation, e.g. `7` then `-3` is `4`; see [`arithmetic_answer`].
+    Arithmetic,
+}
 
-/// Generates a single N-Back sequence with a controlled number of matches.
-fn generate_single_nback_sequence<T>(
-    n: usize,
-    length: usize,
-    stimulus_set: &[T],
-    forbidden_match_indices: &HashSet<usize>,
-) -> Vec<T>
-where
-    T: Clone + Eq + Hash + Debug,
-{
-    let mut rng = thread_rng();
+impl Modality {
+    pub const ALL: [Modality; 6] = [
+        Modality::Position,
+        Modality::Audio,
+        Modality::Color,
+        Modality::Shape,
+        Modality::Image,
+        Modality::Arithmetic,
+    ];
 
-    loop {
-        // Step 1: Plan match positions
-        let num_target_matches = ((length - n) as f32 * TARGET_RATIO).ceil() as usize;
+    /// Whether the modality is scored on repeats of the stimulus N turns back. Arithmetic asks
+    /// for an answer on every turn instead, so it has no matches or lures to plan.
+    pub fn scores_repeats(self) -> bool {
+        self != Modality::Arithmetic
+    }
+}
 
-        let all_possible_slots: Vec<usize> = (n..length).collect();
-        let mut non_overlapping_slots: Vec<&usize> = all_possible_slots
-            .iter()
-            .filter(|p| !forbidden_match_indices.contains(p))
-            .collect();
-        let mut overlapping_slots: Vec<&usize> = all_possible_slots
-            .iter()
-            .filter(|p| forbidden_match_indices.contains(p))
-            .collect();
+/// Whether every turn is scored against the same N, or each one draws its own.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum NLevelMode {
+    #[default]
+    Fixed,
+    /// Brain Workshop's variable N-back: every scorable turn draws its N from `1..=n_level`
+    /// and the frontend announces it with the stimulus.
+    Variable,
+}
+
+/// How the cells of the position modality are arranged. Cells are numbered row by row, or
+/// clockwise from the top for a ring.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum GridLayout {
+    Square { size: u8 },
+    Rect { rows: u8, cols: u8 },
+    Ring { positions: u8 },
+}
+
+impl Default for GridLayout {
+    fn default() -> Self {
+        GridLayout::Square { size: 3 }
+    }
+}
 
-        non_overlapping_slots.shuffle(&mut rng);
-        overlapping_slots.shuffle(&mut rng);
+/// Largest layout the position modality supports (an 8x8 grid).
+const MAX_GRID_POSITIONS: usize = 64;
+
+impl GridLayout {
+    pub fn position_count(&self) -> usize {
+        match *self {
+            GridLayout::Square { size } => size as usize * size as usize,
+            GridLayout::Rect { rows, cols } => rows as usize * cols as usize,
+            GridLayout::Ring { positions } => positions as usize,
+        }
+    }
+
+    /// Checks that the layout has at least two cells and fits within the supported maximum.
+    pub fn validate(&self) -> Result<(), GeneratorError> {
+        let count = self.position_count();
+        if !(2..=MAX_GRID_POSITIONS).contains(&count) {
+            return Err(GeneratorError::InvalidGridLayout(*self));
+        }
+        Ok(())
+    }
+}
 
-        let mut match_indices = HashSet::new();
-        match_indices.extend(non_overlapping_slots.iter().take(num_target_matches).map(|&&i| i));
+const ALL_LETTERS: &[&str] = &[
+    "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R",
+    "S", "T", "U", "V", "W", "X", "Y", "Z",
+];
+const NON_CONFUSING_LETTERS: &[&str] = &["A", "K", "Q", "R", "U", "W", "H", "L", "O"];
+const TIAN_GAN_DI_ZHI: &[&str] = &[
+    "jia", "yi", "bing", "ding", "wu", "ji", "geng", "xin", "ren", "gui", "zi", "chou",
+    "yin", "mao", "chen", "si", "wu_branch", "wei", "shen", "you", "xu", "hai",
+];
+const COLORS: &[&str] = &["red", "green", "blue", "yellow", "purple", "orange", "cyan", "white"];
+const SHAPES: &[&str] = &["circle", "square", "triangle", "diamond", "star", "pentagon", "hexagon", "cross"];
+const IMAGES: &[&str] = &[
+    "image_1", "image_2", "image_3", "image_4", "image_5", "image_6", "image_7", "image_8",
+];
+const DIGITS: &[&str] = &["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+/// Operations of the arithmetic modality, written before the digit of a turn.
+const ARITHMETIC_OPERATIONS: [char; 3] = ['+', '-', '*'];
 
-        let remaining_needed = num_target_matches.saturating_sub(match_indices.len());
-        if remaining_needed > 0 {
-            match_indices.extend(overlapping_slots.iter().take(remaining_needed).map(|&&i| i));
+/// Returns the tokens a modality draws its stimuli from. Positions are the cell indices of
+/// `grid_layout`. Custom auditory sets can't be resolved here; their tokens come from the
+/// database.
+pub fn stimulus_tokens(
+    modality: Modality,
+    auditory_stimulus_set: &AuditoryStimulusSet,
+    grid_layout: GridLayout,
+) -> Result<Vec<String>, GeneratorError> {
+    let tokens: &[&str] = match modality {
+        Modality::Position => {
+            return Ok((0..grid_layout.position_count()).map(|p| p.to_string()).collect())
         }
+        Modality::Audio => match auditory_stimulus_set {
+            AuditoryStimulusSet::AllLetters => ALL_LETTERS,
+            AuditoryStimulusSet::NonConfusingLetters => NON_CONFUSING_LETTERS,
+            AuditoryStimulusSet::TianGanDiZhi => TIAN_GAN_DI_ZHI,
+            AuditoryStimulusSet::Custom(id) => {
+                return Err(GeneratorError::UnresolvedStimulusSet(id.clone()))
+            }
+        },
+        Modality::Color => COLORS,
+        Modality::Shape => SHAPES,
+        Modality::Image => IMAGES,
+        Modality::Arithmetic => DIGITS,
+    };
+    Ok(tokens.iter().map(|t| t.to_string()).collect())
+}
+
+/// One stimulus stream of a session.
+#[derive(Debug, Clone)]
+pub struct StreamSpec {
+    pub modality: Modality,
+    pub stimuli: Vec<String>,
+    /// Fraction of the scorable turns that should be lures: non-match turns that repeat the
+    /// stimulus from N-1 or N+1 turns back. A rate of 0 leaves lures to chance.
+    pub lure_rate: f32,
+}
+
+/// Upper bound on build-and-verify rounds before the generator gives up.
+const MAX_ATTEMPTS: usize = 100;
+
+/// Reasons a sequence cannot be generated for the requested parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratorError {
+    /// `n` must be at least 1 and leave at least one turn with an N-back predecessor.
+    InvalidNLevel { n: usize, length: usize },
+    /// Sessions have between 10 and 100 turns.
+    InvalidSessionLength(usize),
+    /// A stimulus set needs two distinct values, otherwise every turn would be a match.
+    StimulusSetTooSmall { size: usize },
+    /// Match ratios are fractions between 0 and 1.
+    InvalidMatchRatio(f32),
+    /// Overlap shares are fractions between 0 and 1.
+    InvalidOverlap(f32),
+    /// Lure rates are fractions between 0 and 1.
+    InvalidLureRate(f32),
+    /// A session needs at least one modality.
+    NoModalities,
+    /// Each modality may only appear once per session.
+    DuplicateModality(Modality),
+    /// A grid layout needs between 2 and 64 cells.
+    InvalidGridLayout(GridLayout),
+    /// A custom auditory set was selected but its tokens were not supplied.
+    UnresolvedStimulusSet(String),
+    /// A within-session staircase has parameters it can't run with.
+    InvalidStaircase(String),
+    /// More matches were requested than there are turns that can hold one.
+    TooManyMatches { requested: usize, available: usize },
+    /// No attempt produced a sequence satisfying every constraint.
+    ExhaustedAttempts { attempts: usize },
+}
 
-        // Step 2: Build the sequence
-        let mut sequence = Vec::with_capacity(length);
-        for i in 0..length {
-            if i < n {
-                sequence.push(stimulus_set.choose(&mut rng).unwrap().clone());
-                continue;
+impl fmt::Display for GeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeneratorError::InvalidNLevel { n, length } => write!(
+                f,
+                "N-level {} is invalid for a session of {} turns; it must be at least 1 and less than the session length",
+                n, length
+            ),
+            GeneratorError::InvalidSessionLength(length) => write!(
+                f,
+                "Session length {} is not supported; it must be between {} and {} turns",
+                length,
+                crate::persistence::SESSION_LENGTH_RANGE.start(),
+                crate::persistence::SESSION_LENGTH_RANGE.end()
+            ),
+            GeneratorError::StimulusSetTooSmall { size } => write!(
+                f,
+                "Stimulus set has {} distinct value(s), at least 2 are required",
+                size
+            ),
+            GeneratorError::InvalidMatchRatio(ratio) => {
+                write!(f, "Match ratio must be between 0 and 1, got {}", ratio)
             }
+            GeneratorError::InvalidOverlap(overlap) => {
+                write!(f, "Match overlap must be between 0 and 1, got {}", overlap)
+            }
+            GeneratorError::InvalidLureRate(rate) => {
+                write!(f, "Lure rate must be between 0 and 1, got {}", rate)
+            }
+            GeneratorError::NoModalities => write!(f, "At least one modality must be selected"),
+            GeneratorError::DuplicateModality(modality) => {
+                write!(f, "Modality {:?} is selected more than once", modality)
+            }
+            GeneratorError::InvalidGridLayout(layout) => write!(
+                f,
+                "Grid layout {:?} has {} cells, between 2 and {} are supported",
+                layout,
+                layout.position_count(),
+                MAX_GRID_POSITIONS
+            ),
+            GeneratorError::UnresolvedStimulusSet(id) => {
+                write!(f, "Custom auditory stimulus set '{}' is not available", id)
+            }
+            GeneratorError::InvalidStaircase(reason) => write!(f, "Invalid staircase: {}", reason),
+            GeneratorError::TooManyMatches { requested, available } => write!(
+                f,
+                "{} matches requested but only {} turns can be matches",
+                requested, available
+            ),
+            GeneratorError::ExhaustedAttempts { attempts } => write!(
+                f,
+                "Failed to generate a valid sequence after {} attempts",
+                attempts
+            ),
+        }
+    }
+}
 
-            let previous_stimulus = &sequence[i - n];
-            if match_indices.contains(&i) {
-                sequence.push(previous_stimulus.clone());
-            } else {
-                let mut new_stimulus = stimulus_set.choose(&mut rng).unwrap();
-                while new_stimulus == previous_stimulus {
-                    new_stimulus = stimulus_set.choose(&mut rng).unwrap();
+impl std::error::Error for GeneratorError {}
+
+/// How often the stimulus streams of a session repeat their N-back predecessor.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum MatchDistribution {
+    /// Each modality matches on `ratio` of the turns that have an N-back predecessor.
+    /// `overlap` is the share of a modality's matches placed on turns where an earlier modality
+    /// already matches: 0 keeps matches apart where possible, 1 stacks them together.
+    Ratio { ratio: f32, overlap: f32 },
+    /// Exact per-session counts: `single` matches for each modality on its own, plus `shared`
+    /// turns on which every modality matches at once. Jaeggi's 6 + 6 + 2 block of 20 + N turns
+    /// is `single: 6, shared: 2`.
+    Fixed { single: usize, shared: usize },
+}
+
+impl Default for MatchDistribution {
+    fn default() -> Self {
+        MatchDistribution::Ratio { ratio: 0.2, overlap: 0.0 }
+    }
+}
+
+impl MatchDistribution {
+    /// Checks that the distribution can be realised for `streams` modalities in a session of
+    /// `length` turns at level `n`.
+    pub fn validate(&self, n: usize, length: usize, streams: usize) -> Result<(), GeneratorError> {
+        if n == 0 || n >= length {
+            return Err(GeneratorError::InvalidNLevel { n, length });
+        }
+        let scorable_turns = length - n;
+        match *self {
+            MatchDistribution::Ratio { ratio, overlap } => {
+                if !(0.0..=1.0).contains(&ratio) {
+                    return Err(GeneratorError::InvalidMatchRatio(ratio));
+                }
+                if !(0.0..=1.0).contains(&overlap) {
+                    return Err(GeneratorError::InvalidOverlap(overlap));
+                }
+            }
+            MatchDistribution::Fixed { single, shared } => {
+                let total = single * streams + shared;
+                if total > scorable_turns {
+                    return Err(GeneratorError::TooManyMatches {
+                        requested: total,
+                        available: scorable_turns,
+                    });
                 }
-                sequence.push(new_stimulus.clone());
             }
         }
+        Ok(())
+    }
+}
 
-        // Step 3: Validate
-        let actual_matches = (n..length).filter(|&i| sequence[i] == sequence[i - n]).count();
-        let actual_ratio = actual_matches as f32 / length as f32;
+/// Checks that a session selects at least one modality and none of them twice.
+pub fn validate_modalities(modalities: &[Modality]) -> Result<(), GeneratorError> {
+    if modalities.is_empty() {
+        return Err(GeneratorError::NoModalities);
+    }
+    let mut seen = HashSet::new();
+    for modality in modalities {
+        if !seen.insert(modality) {
+            return Err(GeneratorError::DuplicateModality(*modality));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a lure rate is a fraction between 0 and 1.
+pub fn validate_lure_rate(rate: f32) -> Result<(), GeneratorError> {
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(GeneratorError::InvalidLureRate(rate));
+    }
+    Ok(())
+}
+
+/// Seeds are kept below 2^53 so they survive a round trip through JavaScript numbers.
+const MAX_SEED: u64 = (1 << 53) - 1;
+
+/// Draws a fresh seed for sessions that don't request a specific one.
+pub fn random_seed() -> u64 {
+    rand::thread_rng().gen_range(0..=MAX_SEED)
+}
 
-        if (LOWER_BOUND_RATIO..=UPPER_BOUND_RATIO).contains(&actual_ratio) {
-            return sequence;
+/// Creates the deterministic RNG that drives sequence generation.
+/// ChaCha8 is used instead of `StdRng` because its output is stable across `rand` releases,
+/// which keeps stored seeds replayable.
+pub fn seeded_rng(seed: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(seed)
+}
+
+/// Generates a single N-Back sequence that matches exactly at `match_indices`. Turn `i` is
+/// compared with the stimulus `lookbacks[i]` turns back. The sequence starts with `history`,
+/// the turns already presented, and only the turns after it are generated.
+/// Non-match turns draw from the stimuli that differ from the N-back one, so no retries are needed.
+/// When `lure_indices` is given, lures appear exactly on those turns and nowhere else;
+/// `None` is returned if a planned lure has no stimulus to repeat or a non-lure has nothing left.
+fn generate_single_nback_sequence<T, R>(
+    lookbacks: &[usize],
+    history: &[T],
+    stimulus_set: &[T],
+    match_indices: &HashSet<usize>,
+    lure_indices: Option<&HashSet<usize>>,
+    rng: &mut R,
+) -> Option<Vec<T>>
+where
+    T: Clone + Eq + Hash + Debug,
+    R: Rng + ?Sized,
+{
+    let mut sequence = Vec::with_capacity(lookbacks.len());
+    sequence.extend_from_slice(history);
+    for (i, &n) in lookbacks.iter().enumerate().skip(history.len()) {
+        if i < n {
+            sequence.push(stimulus_set.choose(rng)?.clone());
+            continue;
         }
+
+        let previous_stimulus = &sequence[i - n];
+        if match_indices.contains(&i) {
+            sequence.push(previous_stimulus.clone());
+            continue;
+        }
+
+        let lure_stimuli: Vec<&T> = lure_source_indices(n, i)
+            .map(|j| &sequence[j])
+            .filter(|s| *s != previous_stimulus)
+            .collect();
+        let next = match lure_indices {
+            Some(lures) if lures.contains(&i) => *lure_stimuli.choose(rng)?,
+            Some(_) => *stimulus_set
+                .iter()
+                .filter(|s| *s != previous_stimulus && !lure_stimuli.contains(s))
+                .collect::<Vec<&T>>()
+                .choose(rng)?,
+            None => *stimulus_set
+                .iter()
+                .filter(|s| *s != previous_stimulus)
+                .collect::<Vec<&T>>()
+                .choose(rng)?,
+        };
+        sequence.push(next.clone());
     }
+    Some(sequence)
 }
 
+/// Fails unless `stimulus_set` holds at least two distinct values.
+fn check_stimulus_set<T: Eq + Hash>(stimulus_set: &[T]) -> Result<(), GeneratorError> {
+    let distinct = stimulus_set.iter().collect::<HashSet<_>>().len();
+    if distinct < 2 {
+        return Err(GeneratorError::StimulusSetTooSmall { size: distinct });
+    }
+    Ok(())
+}
 
-/// Generates both audio and visual sequences for a Dual N-Back task.
-pub fn generate_dual_nback_sequences(
-    n: usize,
+/// The answer turn `i` of an arithmetic sequence asks for: the digit `n` turns back combined with
+/// the digit of turn `i` by its operation. `None` for turns before the first N, which have no
+/// operation.
+pub fn arithmetic_answer(sequence: &[String], n: usize, i: usize) -> Option<i64> {
+    if i < n {
+        return None;
+    }
+    let digit = |token: &str| token.trim_start_matches(ARITHMETIC_OPERATIONS).parse::<i64>().ok();
+    let (earlier, current) = (digit(&sequence[i - n])?, digit(&sequence[i])?);
+    match sequence[i].chars().next()? {
+        '+' => Some(earlier + current),
+        '-' => Some(earlier - current),
+        '*' => Some(earlier * current),
+        _ => None,
+    }
+}
+
+/// Generates an arithmetic sequence: a random digit on every turn, preceded by a random
+/// operation on the turns that have an N-back predecessor. Answers are not planned, so any
+/// digit will do.
+fn generate_arithmetic_sequence<R>(lookbacks: &[usize], history: &[String], digits: &[String], rng: &mut R) -> Option<Vec<String>>
+where
+    R: Rng + ?Sized,
+{
+    let mut sequence = history.to_vec();
+    for (i, &n) in lookbacks.iter().enumerate().skip(history.len()) {
+        let digit = digits.choose(rng)?;
+        sequence.push(match i < n {
+            true => digit.clone(),
+            false => format!("{}{}", ARITHMETIC_OPERATIONS.choose(rng)?, digit),
+        });
+    }
+    Some(sequence)
+}
+
+/// Whether turn `i` of `sequence` repeats the stimulus `n` turns back.
+pub fn is_match<T: Eq>(sequence: &[T], n: usize, i: usize) -> bool {
+    i >= n && sequence[i] == sequence[i - n]
+}
+
+/// Whether turn `i` of a `modality` sequence asks for a response: a repeat of the stimulus `n`
+/// turns back, or for arithmetic every turn that has an answer.
+pub fn is_target(modality: Modality, sequence: &[String], n: usize, i: usize) -> bool {
+    match modality.scores_repeats() {
+        true => is_match(sequence, n, i),
+        false => i >= n,
+    }
+}
+
+/// Returns the turns on which `sequence` repeats its N-back predecessor.
+fn match_indices_of<T: Eq>(lookbacks: &[usize], sequence: &[T]) -> HashSet<usize> {
+    (0..sequence.len()).filter(|&i| is_match(sequence, lookbacks[i], i)).collect()
+}
+
+/// The turns a lure at turn `i` may repeat: N-1 back (only when N > 1) and N+1 back.
+fn lure_source_indices(n: usize, i: usize) -> impl Iterator<Item = usize> {
+    let near = (n >= 2).then(|| i + 1 - n);
+    let far = (i > n).then(|| i - n - 1);
+    near.into_iter().chain(far)
+}
+
+/// Whether turn `i` of `sequence` is a lure: not a match, but a repeat from N-1 or N+1 back.
+pub fn is_lure<T: Eq>(sequence: &[T], n: usize, i: usize) -> bool {
+    i >= n
+        && sequence[i] != sequence[i - n]
+        && lure_source_indices(n, i).any(|j| sequence[i] == sequence[j])
+}
+
+/// Returns the turns on which `sequence` presents a lure.
+fn lure_indices_of<T: Eq>(lookbacks: &[usize], sequence: &[T]) -> HashSet<usize> {
+    (0..sequence.len()).filter(|&i| is_lure(sequence, lookbacks[i], i)).collect()
+}
+
+/// Picks `rate` of the scorable turns from `first` on, among those that don't match and can
+/// hold a lure.
+fn plan_lure_indices<R>(
+    first: usize,
+    lookbacks: &[usize],
+    rate: f32,
+    match_indices: &HashSet<usize>,
+    rng: &mut R,
+) -> HashSet<usize>
+where
+    R: Rng + ?Sized,
+{
+    let length = lookbacks.len();
+    let num_lures = ((length - first) as f32 * rate).ceil() as usize;
+    let mut slots: Vec<usize> = (first..length)
+        .filter(|&i| !match_indices.contains(&i) && lure_source_indices(lookbacks[i], i).next().is_some())
+        .collect();
+    slots.shuffle(rng);
+    slots.into_iter().take(num_lures).collect()
+}
+
+/// Draws the N each turn from `start` on is compared against. The first `n` turns have no
+/// predecessor to compare with in either mode, so they keep `n`.
+fn plan_lookbacks<R>(n: usize, start: usize, length: usize, mode: NLevelMode, rng: &mut R) -> Vec<usize>
+where
+    R: Rng + ?Sized,
+{
+    (start..length)
+        .map(|i| match mode {
+            NLevelMode::Variable if i >= n => rng.gen_range(1..=n),
+            _ => n,
+        })
+        .collect()
+}
+
+/// Picks the turns from `first` on on which each of `streams` streams matches, in stream order.
+fn plan_match_indices<R>(
+    first: usize,
     length: usize,
-    auditory_stimulus_set: AuditoryStimulusSet,
-) -> (Vec<String>, Vec<u8>) {
-    if n >= length {
-        panic!("N-value must be less than the sequence length.");
+    distribution: MatchDistribution,
+    streams: usize,
+    rng: &mut R,
+) -> Vec<HashSet<usize>>
+where
+    R: Rng + ?Sized,
+{
+    let mut slots: Vec<usize> = (first..length).collect();
+    slots.shuffle(rng);
+
+    match distribution {
+        MatchDistribution::Ratio { ratio, overlap } => {
+            let num_target_matches = ((length - first) as f32 * ratio).ceil() as usize;
+            let wanted_overlap = (num_target_matches as f32 * overlap).round() as usize;
+            let mut matched_so_far: HashSet<usize> = HashSet::new();
+            let mut plans = Vec::with_capacity(streams);
+
+            for stream in 0..streams {
+                slots.shuffle(rng);
+                let (overlapping, free): (Vec<usize>, Vec<usize>) =
+                    slots.iter().partition(|i| matched_so_far.contains(i));
+
+                // The first stream has nothing to overlap with. Later streams take their share
+                // of overlapping turns, fill up with free turns, and overlap further only once
+                // the free turns run out.
+                let from_overlapping = if stream == 0 { 0 } else { wanted_overlap.min(overlapping.len()) };
+                let from_free = (num_target_matches - from_overlapping).min(free.len());
+                let extra = num_target_matches - from_overlapping - from_free;
+
+                let plan: HashSet<usize> = overlapping
+                    .iter()
+                    .take(from_overlapping + extra)
+                    .chain(free.iter().take(from_free))
+                    .copied()
+                    .collect();
+                matched_so_far.extend(&plan);
+                plans.push(plan);
+            }
+            plans
+        }
+        MatchDistribution::Fixed { single, shared } => {
+            let mut slots = slots.into_iter();
+            let shared_slots: Vec<usize> = slots.by_ref().take(shared).collect();
+            (0..streams)
+                .map(|_| shared_slots.iter().copied().chain(slots.by_ref().take(single)).collect())
+                .collect()
+        }
     }
+}
 
-    const ALL_LETTERS: &[&str] = &[
-        "A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M", "N", "O", "P", "Q", "R",
-        "S", "T", "U", "V", "W", "X", "Y", "Z",
-    ];
-    const NON_CONFUSING_LETTERS: &[&str] = &["A", "K", "Q", "R", "U", "W", "H", "L", "O"];
-    const TIAN_GAN_DI_ZHI: &[&str] = &[
-        "jia", "yi", "bing", "ding", "wu", "ji", "geng", "xin", "ren", "gui", "zi", "chou",
-        "yin", "mao", "chen", "si", "wu_branch", "wei", "shen", "you", "xu", "hai",
-    ];
+/// The sequences of one session, with the N each turn is scored against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NBackSequences {
+    /// `lookbacks[i]` is the N of turn `i`. Turns with `i < lookbacks[i]` are not scored.
+    pub lookbacks: Vec<usize>,
+    pub streams: BTreeMap<Modality, Vec<String>>,
+}
 
-    let auditory_stimuli: &[&str] = match auditory_stimulus_set {
-        AuditoryStimulusSet::AllLetters => ALL_LETTERS,
-        AuditoryStimulusSet::NonConfusingLetters => NON_CONFUSING_LETTERS,
-        AuditoryStimulusSet::TianGanDiZhi => TIAN_GAN_DI_ZHI,
+/// Generates one sequence per stream for an N-Back task with any number of modalities.
+/// In variable mode `n` is the highest N a turn may ask for.
+/// The same `seed` always yields the same sequences.
+pub fn generate_nback_sequences(
+    n: usize,
+    mode: NLevelMode,
+    length: usize,
+    streams: &[StreamSpec],
+    match_distribution: MatchDistribution,
+    seed: u64,
+) -> Result<NBackSequences, GeneratorError> {
+    let repeat_streams = streams.iter().filter(|s| s.modality.scores_repeats()).count();
+    match_distribution.validate(n, length, repeat_streams)?;
+    let history = NBackSequences { lookbacks: Vec::new(), streams: BTreeMap::new() };
+    generate_after(&history, n, mode, length, streams, match_distribution, seed)
+}
+
+/// Regenerates the turns after `history` with a new `n`, keeping the turns already presented.
+/// The first turns after a change are scored against the stimuli before it. A fixed match
+/// distribution counts for the whole session, so the new turns get their share of it.
+pub fn continue_nback_sequences(
+    history: &NBackSequences,
+    n: usize,
+    mode: NLevelMode,
+    length: usize,
+    streams: &[StreamSpec],
+    match_distribution: MatchDistribution,
+    seed: u64,
+) -> Result<NBackSequences, GeneratorError> {
+    let start = history.lookbacks.len();
+    let first = start.max(n);
+    if n == 0 || first >= length {
+        return Err(GeneratorError::InvalidNLevel { n, length });
+    }
+    let match_distribution = match match_distribution {
+        MatchDistribution::Fixed { single, shared } => {
+            let share = (length - first) as f32 / (length - n) as f32;
+            let scale = |count: usize| (count as f32 * share).round() as usize;
+            MatchDistribution::Fixed { single: scale(single), shared: scale(shared) }
+        }
+        ratio => ratio,
     };
+    generate_after(history, n, mode, length, streams, match_distribution, seed)
+}
 
-    let visual_stimuli: Vec<u8> = (0..9).collect(); // Always 3x3 grid
+fn generate_after(
+    history: &NBackSequences,
+    n: usize,
+    mode: NLevelMode,
+    length: usize,
+    streams: &[StreamSpec],
+    match_distribution: MatchDistribution,
+    seed: u64,
+) -> Result<NBackSequences, GeneratorError> {
+    let modalities: Vec<Modality> = streams.iter().map(|s| s.modality).collect();
+    validate_modalities(&modalities)?;
+    for stream in streams {
+        validate_lure_rate(stream.lure_rate)?;
+        check_stimulus_set(&stream.stimuli)?;
+    }
+    let start = history.lookbacks.len();
+    let first = start.max(n);
+    let generated = |indices: HashSet<usize>| -> HashSet<usize> {
+        indices.into_iter().filter(|&i| i >= start).collect()
+    };
 
-    // 1. Generate audio sequence
-    let audio_sequence_raw =
-        generate_single_nback_sequence(n, length, auditory_stimuli, &HashSet::new());
-    let audio_sequence: Vec<String> = audio_sequence_raw.iter().map(|s| s.to_string()).collect();
+    let mut rng = seeded_rng(seed);
+    'attempt: for _ in 0..MAX_ATTEMPTS {
+        // 1. Decide which N each turn asks for and where each stream matches
+        let mut lookbacks = history.lookbacks.clone();
+        lookbacks.extend(plan_lookbacks(n, start, length, mode, &mut rng));
+        let (repeat_streams, answer_streams): (Vec<&StreamSpec>, Vec<&StreamSpec>) =
+            streams.iter().partition(|s| s.modality.scores_repeats());
+        let match_plans = plan_match_indices(first, length, match_distribution, repeat_streams.len(), &mut rng);
 
+        let mut sequences = BTreeMap::new();
+        for stream in answer_streams {
+            let presented = history.streams.get(&stream.modality).map_or(&[][..], Vec::as_slice);
+            let Some(sequence) = generate_arithmetic_sequence(&lookbacks, presented, &stream.stimuli, &mut rng) else {
+                continue 'attempt;
+            };
+            sequences.insert(stream.modality, sequence);
+        }
+        for (stream, match_indices) in repeat_streams.into_iter().zip(&match_plans) {
+            // 2. Decide where the stream lures
+            let lure_indices = (stream.lure_rate > 0.0)
+                .then(|| plan_lure_indices(first, &lookbacks, stream.lure_rate, match_indices, &mut rng));
 
-    // 2. Find audio match indices
-    let audio_match_indices: HashSet<usize> = (n..length)
-        .filter(|&i| audio_sequence[i] == audio_sequence[i - n])
-        .collect();
+            // 3. Generate the sequence
+            let presented = history.streams.get(&stream.modality).map_or(&[][..], Vec::as_slice);
+            let Some(sequence) = generate_single_nback_sequence(
+                &lookbacks,
+                presented,
+                &stream.stimuli,
+                match_indices,
+                lure_indices.as_ref(),
+                &mut rng,
+            ) else {
+                continue 'attempt;
+            };
 
-    // 3. Generate visual sequence, avoiding audio match indices
-    let visual_sequence =
-        generate_single_nback_sequence(n, length, &visual_stimuli, &audio_match_indices);
+            // 4. Verify the new turns realise exactly the planned matches and lures
+            let lures_hold = lure_indices
+                .as_ref()
+                .is_none_or(|planned| *planned == generated(lure_indices_of(&lookbacks, &sequence)));
+            if generated(match_indices_of(&lookbacks, &sequence)) != *match_indices || !lures_hold {
+                continue 'attempt;
+            }
+            sequences.insert(stream.modality, sequence);
+        }
+        return Ok(NBackSequences { lookbacks, streams: sequences });
+    }
 
-    (audio_sequence, visual_sequence)
+    Err(GeneratorError::ExhaustedAttempts { attempts: MAX_ATTEMPTS })
 }
 
 
@@ -135,45 +685,196 @@ pub fn generate_dual_nback_sequences(
 mod tests {
     use super::*;
 
+    fn streams(modalities: &[Modality], lure_rate: f32) -> Vec<StreamSpec> {
+        modalities
+            .iter()
+            .map(|&modality| StreamSpec {
+                modality,
+                stimuli: stimulus_tokens(
+                    modality,
+                    &AuditoryStimulusSet::NonConfusingLetters,
+                    GridLayout::default(),
+                )
+                .unwrap(),
+                lure_rate,
+            })
+            .collect()
+    }
+
+    fn fixed(n: usize, length: usize) -> Vec<usize> {
+        vec![n; length]
+    }
+
+    fn dual() -> Vec<StreamSpec> {
+        streams(&[Modality::Position, Modality::Audio], 0.0)
+    }
+
     #[test]
     fn test_single_sequence_generation_ratio() {
         let n = 2;
         let length = 50;
         let stimulus_set: Vec<u8> = (0..9).collect();
-        let sequence = generate_single_nback_sequence(n, length, &stimulus_set, &HashSet::new());
+        let mut rng = seeded_rng(7);
+        let match_indices = plan_match_indices(n, length, MatchDistribution::default(), 1, &mut rng).remove(0);
+        let sequence =
+            generate_single_nback_sequence(&fixed(n, length), &[], &stimulus_set, &match_indices, None, &mut rng).unwrap();
 
         assert_eq!(sequence.len(), length);
 
-        let matches = (n..length).filter(|&i| sequence[i] == sequence[i - n]).count();
-        let ratio = matches as f32 / length as f32;
+        let matches: HashSet<usize> = (n..length).filter(|&i| sequence[i] == sequence[i - n]).collect();
+        let ratio = matches.len() as f32 / length as f32;
 
         println!("Generated sequence with match ratio: {}", ratio);
-        assert!(ratio >= LOWER_BOUND_RATIO, "Ratio should be >= {}", LOWER_BOUND_RATIO);
-        assert!(ratio <= UPPER_BOUND_RATIO, "Ratio should be <= {}", UPPER_BOUND_RATIO);
+        assert_eq!(matches, match_indices);
+        assert_eq!(matches.len(), 10); // ceil(48 * 0.2)
     }
 
     #[test]
     fn test_dual_sequence_generation() {
         let n = 3;
         let length = 100;
-        let (audio_seq, visual_seq) =
-            generate_dual_nback_sequences(n, length, AuditoryStimulusSet::AllLetters);
+        let sequences = generate_nback_sequences(n, NLevelMode::Fixed, length, &dual(), MatchDistribution::default(), 42)
+            .unwrap()
+            .streams;
+        let audio_seq = &sequences[&Modality::Audio];
+        let visual_seq = &sequences[&Modality::Position];
 
         assert_eq!(audio_seq.len(), length);
         assert_eq!(visual_seq.len(), length);
 
-        // It's not a hard guarantee, but we can check that the overlap is low.
-        let audio_match_indices: HashSet<usize> = (n..length)
-            .filter(|&i| audio_seq[i] == audio_seq[i - n])
-            .collect();
-        let visual_match_indices: HashSet<usize> = (n..length)
-            .filter(|&i| visual_seq[i] == visual_seq[i - n])
-            .collect();
-        
+        // With 20% matches per stream there are enough free turns to avoid any overlap.
+        let audio_match_indices = match_indices_of(&fixed(n, length), audio_seq);
+        let visual_match_indices = match_indices_of(&fixed(n, length), visual_seq);
+
         let overlap = audio_match_indices.intersection(&visual_match_indices).count();
         println!("Dual sequence generated with {} overlapping matches.", overlap);
-        // This is a soft test; in rare cases, overlap might be high, but it should be low on average.
-        assert!(overlap < 10, "Overlap should be minimal"); // Increased tolerance for randomness
+        assert_eq!(audio_match_indices.len(), 20);
+        assert_eq!(visual_match_indices.len(), 20);
+        assert_eq!(overlap, 0, "Overlap should be avoided");
+    }
+
+    #[test]
+    fn test_quad_sequence_generation() {
+        let n = 2;
+        let length = 42;
+        let modalities = [Modality::Position, Modality::Audio, Modality::Color, Modality::Shape];
+        let distribution = MatchDistribution::default();
+        let sequences =
+            generate_nback_sequences(n, NLevelMode::Fixed, length, &streams(&modalities, 0.0), distribution, 9)
+                .unwrap()
+                .streams;
+
+        assert_eq!(sequences.keys().copied().collect::<Vec<_>>(), modalities.to_vec());
+        let mut all_matches = HashSet::new();
+        for sequence in sequences.values() {
+            let matches = match_indices_of(&fixed(n, length), sequence);
+            assert_eq!(matches.len(), 8); // ceil(40 * 0.2)
+            all_matches.extend(matches);
+        }
+        // 4 streams x 8 matches fit into 40 turns without any overlap.
+        assert_eq!(all_matches.len(), 32);
+        assert!(sequences[&Modality::Color].iter().all(|c| COLORS.contains(&c.as_str())));
+    }
+
+    #[test]
+    fn test_arithmetic_stream_has_an_answer_on_every_scorable_turn() {
+        let n = 2;
+        let length = 30;
+        let modalities = [Modality::Position, Modality::Arithmetic];
+        let distribution = MatchDistribution::Fixed { single: 6, shared: 0 };
+        let sequences =
+            generate_nback_sequences(n, NLevelMode::Fixed, length, &streams(&modalities, 0.2), distribution, 3)
+                .unwrap()
+                .streams;
+
+        // Only the position stream takes part in the match plan
+        assert_eq!(match_indices_of(&fixed(n, length), &sequences[&Modality::Position]).len(), 6);
+        let arithmetic = &sequences[&Modality::Arithmetic];
+        assert!(arithmetic[..n].iter().all(|t| DIGITS.contains(&t.as_str())));
+        for i in n..length {
+            assert!(ARITHMETIC_OPERATIONS.contains(&arithmetic[i].chars().next().unwrap()), "{}", arithmetic[i]);
+            assert!(arithmetic_answer(arithmetic, n, i).is_some());
+            assert!(is_target(Modality::Arithmetic, arithmetic, n, i));
+        }
+        assert!(!is_target(Modality::Arithmetic, arithmetic, n, n - 1));
+    }
+
+    #[test]
+    fn test_arithmetic_answers() {
+        let sequence: Vec<String> = ["7", "2", "-3", "*4", "+9"].iter().map(|t| t.to_string()).collect();
+        let answers: Vec<Option<i64>> = (0..sequence.len()).map(|i| arithmetic_answer(&sequence, 2, i)).collect();
+        // Only the digit of the earlier turn counts, not its operation
+        assert_eq!(answers, vec![None, None, Some(4), Some(8), Some(12)]);
+        assert_eq!(arithmetic_answer(&sequence, 1, 1), None); // No operation on turn 1
+    }
+
+    #[test]
+    fn test_full_overlap_stacks_matches() {
+        let n = 2;
+        let length = 42;
+        let distribution = MatchDistribution::Ratio { ratio: 0.2, overlap: 1.0 };
+        let modalities = [Modality::Position, Modality::Audio, Modality::Color];
+        let sequences =
+            generate_nback_sequences(n, NLevelMode::Fixed, length, &streams(&modalities, 0.0), distribution, 4)
+                .unwrap()
+                .streams;
+
+        let position_matches = match_indices_of(&fixed(n, length), &sequences[&Modality::Position]);
+        assert_eq!(match_indices_of(&fixed(n, length), &sequences[&Modality::Audio]), position_matches);
+        assert_eq!(match_indices_of(&fixed(n, length), &sequences[&Modality::Color]), position_matches);
+    }
+
+    #[test]
+    fn test_flat_ratio_distribution() {
+        let n = 2;
+        let length = 50;
+        let distribution = MatchDistribution::Ratio { ratio: 0.3, overlap: 0.0 };
+        let sequences = generate_nback_sequences(n, NLevelMode::Fixed, length, &dual(), distribution, 5).unwrap().streams;
+
+        assert_eq!(match_indices_of(&fixed(n, length), &sequences[&Modality::Audio]).len(), 15); // ceil(48 * 0.3)
+        assert_eq!(match_indices_of(&fixed(n, length), &sequences[&Modality::Position]).len(), 15);
+    }
+
+    #[test]
+    fn test_fixed_distribution_matches_jaeggi_block() {
+        let n = 2;
+        let length = 20 + n;
+        let distribution = MatchDistribution::Fixed { single: 6, shared: 2 };
+        let sequences = generate_nback_sequences(n, NLevelMode::Fixed, length, &dual(), distribution, 11).unwrap().streams;
+
+        let audio_match_indices = match_indices_of(&fixed(n, length), &sequences[&Modality::Audio]);
+        let visual_match_indices = match_indices_of(&fixed(n, length), &sequences[&Modality::Position]);
+
+        assert_eq!(audio_match_indices.len(), 8);
+        assert_eq!(visual_match_indices.len(), 8);
+        assert_eq!(audio_match_indices.intersection(&visual_match_indices).count(), 2);
+    }
+
+    #[test]
+    fn test_match_distribution_validation() {
+        assert!(MatchDistribution::default().validate(2, 20, 2).is_ok());
+        assert_eq!(
+            MatchDistribution::Ratio { ratio: 1.5, overlap: 0.0 }.validate(2, 20, 2),
+            Err(GeneratorError::InvalidMatchRatio(1.5))
+        );
+        assert!(MatchDistribution::Ratio { ratio: -0.1, overlap: 0.0 }.validate(2, 20, 2).is_err());
+        assert_eq!(
+            MatchDistribution::Ratio { ratio: 0.2, overlap: 2.0 }.validate(2, 20, 2),
+            Err(GeneratorError::InvalidOverlap(2.0))
+        );
+
+        let jaeggi = MatchDistribution::Fixed { single: 6, shared: 2 };
+        assert!(jaeggi.validate(2, 22, 2).is_ok());
+        assert_eq!(
+            jaeggi.validate(2, 15, 2),
+            Err(GeneratorError::TooManyMatches { requested: 14, available: 13 })
+        );
+        // A third modality needs room for six more single matches.
+        assert!(jaeggi.validate(2, 22, 3).is_ok());
+        assert_eq!(
+            jaeggi.validate(2, 21, 3),
+            Err(GeneratorError::TooManyMatches { requested: 20, available: 19 })
+        );
     }
 
     #[test]
@@ -181,7 +882,10 @@ mod tests {
         let n = 1;
         let length = 15; // Test with a length < 20
         let stimulus_set: Vec<u8> = (0..3).collect();
-        let sequence = generate_single_nback_sequence(n, length, &stimulus_set, &HashSet::new());
+        let mut rng = seeded_rng(3);
+        let match_indices = plan_match_indices(n, length, MatchDistribution::default(), 1, &mut rng).remove(0);
+        let sequence =
+            generate_single_nback_sequence(&fixed(n, length), &[], &stimulus_set, &match_indices, None, &mut rng).unwrap();
 
         let matches = (n..length).filter(|&i| sequence[i] == sequence[i - n]).count();
         let ratio = matches as f32 / length as f32;
@@ -191,4 +895,175 @@ mod tests {
         // We just check that the sequence was generated.
         assert_eq!(sequence.len(), length);
     }
+
+    #[test]
+    fn test_same_seed_reproduces_sequences() {
+        let distribution = MatchDistribution::default();
+        let first = generate_nback_sequences(2, NLevelMode::Fixed, 30, &dual(), distribution, 1234).unwrap();
+        let second = generate_nback_sequences(2, NLevelMode::Fixed, 30, &dual(), distribution, 1234).unwrap();
+        assert_eq!(first, second);
+
+        let other = generate_nback_sequences(2, NLevelMode::Fixed, 30, &dual(), distribution, 1235).unwrap();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_random_seed_is_js_safe() {
+        for _ in 0..100 {
+            assert!(random_seed() <= MAX_SEED);
+        }
+    }
+
+    #[test]
+    fn test_infeasible_parameters_return_errors() {
+        let distribution = MatchDistribution::default();
+
+        assert_eq!(
+            generate_nback_sequences(5, NLevelMode::Fixed, 5, &dual(), distribution, 0),
+            Err(GeneratorError::InvalidNLevel { n: 5, length: 5 })
+        );
+        assert_eq!(
+            generate_nback_sequences(0, NLevelMode::Fixed, 20, &dual(), distribution, 0),
+            Err(GeneratorError::InvalidNLevel { n: 0, length: 20 })
+        );
+        assert_eq!(
+            generate_nback_sequences(2, NLevelMode::Fixed, 20, &streams(&[Modality::Position], 2.0), distribution, 0),
+            Err(GeneratorError::InvalidLureRate(2.0))
+        );
+        assert_eq!(
+            generate_nback_sequences(2, NLevelMode::Fixed, 20, &[], distribution, 0),
+            Err(GeneratorError::NoModalities)
+        );
+        assert_eq!(
+            generate_nback_sequences(
+                2,
+                NLevelMode::Fixed,
+                20,
+                &streams(&[Modality::Color, Modality::Color], 0.0),
+                distribution,
+                0
+            ),
+            Err(GeneratorError::DuplicateModality(Modality::Color))
+        );
+        assert_eq!(check_stimulus_set(&["A", "A"]), Err(GeneratorError::StimulusSetTooSmall { size: 1 }));
+    }
+
+    #[test]
+    fn test_edge_parameters_terminate() {
+        // N right below the length, and a ratio that makes every scorable turn a match.
+        let distribution = MatchDistribution::Ratio { ratio: 1.0, overlap: 0.0 };
+        let sequences = generate_nback_sequences(9, NLevelMode::Fixed, 10, &dual(), distribution, 8).unwrap().streams;
+        for sequence in sequences.values() {
+            assert_eq!(sequence[9], sequence[0]);
+        }
+
+        // A two-value stimulus set still has a non-match for every turn.
+        let stimulus_set = ["X", "Y"];
+        let mut rng = seeded_rng(1);
+        let distribution = MatchDistribution::Ratio { ratio: 0.5, overlap: 0.0 };
+        let match_indices = plan_match_indices(1, 30, distribution, 1, &mut rng).remove(0);
+        let sequence =
+            generate_single_nback_sequence(&fixed(1, 30), &[], &stimulus_set, &match_indices, None, &mut rng).unwrap();
+        assert_eq!(match_indices_of(&fixed(1, 30), &sequence), match_indices);
+    }
+
+    #[test]
+    fn test_lures_are_placed_exactly() {
+        let n = 3;
+        let length = 60;
+        let mut specs = dual();
+        specs[0].lure_rate = 0.1;
+        specs[1].lure_rate = 0.2;
+        let sequences = generate_nback_sequences(n, NLevelMode::Fixed, length, &specs, MatchDistribution::default(), 21)
+            .unwrap()
+            .streams;
+        let audio_seq = &sequences[&Modality::Audio];
+        let visual_seq = &sequences[&Modality::Position];
+
+        assert_eq!(lure_indices_of(&fixed(n, length), audio_seq).len(), 12); // ceil(57 * 0.2)
+        assert_eq!(lure_indices_of(&fixed(n, length), visual_seq).len(), 6); // ceil(57 * 0.1)
+        // Lures never replace matches.
+        assert_eq!(match_indices_of(&fixed(n, length), audio_seq).len(), 12);
+        assert_eq!(match_indices_of(&fixed(n, length), visual_seq).len(), 12);
+    }
+
+    #[test]
+    fn test_variable_n_uses_per_turn_lookbacks() {
+        let n = 3;
+        let length = 60;
+        let mut specs = dual();
+        specs[1].lure_rate = 0.1;
+        let generated =
+            generate_nback_sequences(n, NLevelMode::Variable, length, &specs, MatchDistribution::default(), 17)
+                .unwrap();
+        let lookbacks = &generated.lookbacks;
+
+        assert_eq!(lookbacks.len(), length);
+        assert!(lookbacks[..n].iter().all(|&k| k == n));
+        assert!(lookbacks[n..].iter().all(|k| (1..=n).contains(k)));
+        // Every N from 1 to 3 shows up over 57 scorable turns.
+        assert_eq!(lookbacks[n..].iter().collect::<HashSet<_>>().len(), n);
+
+        for sequence in generated.streams.values() {
+            assert_eq!(match_indices_of(lookbacks, sequence).len(), 12); // ceil(57 * 0.2)
+        }
+        assert_eq!(lure_indices_of(lookbacks, &generated.streams[&Modality::Audio]).len(), 6);
+
+        let fixed_mode =
+            generate_nback_sequences(n, NLevelMode::Fixed, length, &specs, MatchDistribution::default(), 17)
+                .unwrap();
+        assert_eq!(fixed_mode.lookbacks, fixed(n, length));
+    }
+
+    #[test]
+    fn test_continuation_keeps_history_and_switches_n() {
+        let length = 60;
+        let specs = dual();
+        let full = generate_nback_sequences(2, NLevelMode::Fixed, length, &specs, MatchDistribution::default(), 3)
+            .unwrap();
+        let start = 20;
+        let history = NBackSequences {
+            lookbacks: full.lookbacks[..start].to_vec(),
+            streams: full.streams.iter().map(|(&m, s)| (m, s[..start].to_vec())).collect(),
+        };
+
+        let continued =
+            continue_nback_sequences(&history, 3, NLevelMode::Fixed, length, &specs, MatchDistribution::default(), 4)
+                .unwrap();
+        assert_eq!(continued.lookbacks[..start], history.lookbacks[..]);
+        assert!(continued.lookbacks[start..].iter().all(|&k| k == 3));
+        for (modality, sequence) in &continued.streams {
+            assert_eq!(sequence.len(), length);
+            assert_eq!(sequence[..start], history.streams[modality][..]);
+            let new_matches = match_indices_of(&continued.lookbacks, sequence).into_iter().filter(|&i| i >= start);
+            assert_eq!(new_matches.count(), 8); // ceil(40 * 0.2)
+        }
+
+        // A fixed distribution is shared out over the whole session
+        let fixed_distribution = MatchDistribution::Fixed { single: 6, shared: 2 };
+        let continued =
+            continue_nback_sequences(&history, 3, NLevelMode::Fixed, length, &specs, fixed_distribution, 4).unwrap();
+        for sequence in continued.streams.values() {
+            let new_matches = match_indices_of(&continued.lookbacks, sequence).into_iter().filter(|&i| i >= start);
+            assert_eq!(new_matches.count(), 5); // round(6 * 40 / 57) + round(2 * 40 / 57)
+        }
+
+        assert_eq!(
+            continue_nback_sequences(&history, 60, NLevelMode::Fixed, length, &specs, MatchDistribution::default(), 4),
+            Err(GeneratorError::InvalidNLevel { n: 60, length })
+        );
+    }
+
+    #[test]
+    fn test_is_lure() {
+        // N = 2: turn 3 repeats turn 2 (N-1), turn 4 repeats turn 1 (N+1), turn 5 is a real match.
+        let sequence = ["A", "B", "C", "C", "B", "C"];
+        assert!(!is_lure(&sequence, 2, 2));
+        assert!(is_lure(&sequence, 2, 3));
+        assert!(is_lure(&sequence, 2, 4));
+        assert!(!is_lure(&sequence, 2, 5));
+        // N = 1 has no N-1 lure; the repeat from two back is the only kind.
+        assert!(is_lure(&["A", "B", "A"], 1, 2));
+        assert!(!is_lure(&["A", "B", "B"], 1, 2));
+    }
 }
@@ -0,0 +1,172 @@
+use crate::error::AppError;
+use crate::game::{AppState, GamePhase, GameState, UserResponse};
+use crate::storage::DbState;
+use crate::FrontendGameState;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+// --- Events ---
+// A new turn is on screen. Payload: the frontend game state for that turn.
+pub const STIMULUS_ONSET: &str = "stimulus_onset";
+// A turn's response window closed. Payload: the recorded `GameEvent`.
+pub const TURN_CLOSED: &str = "turn_closed";
+// The last turn closed and the session was saved. Payload: its `GameSessionSummary`.
+pub const SESSION_FINISHED: &str = "session_finished";
+// The last turn closed but the session could not be saved. Payload: the `AppError`.
+pub const SESSION_SAVE_FAILED: &str = "session_save_failed";
+// The checkpoint could not be saved or cleared, so resuming may not pick up the latest turn.
+// Payload: the `AppError`.
+pub const CHECKPOINT_FAILED: &str = "checkpoint_failed";
+
+enum ClockCommand {
+    Pause,
+    Resume,
+}
+
+struct ClockHandle {
+    commands: Sender<ClockCommand>,
+    stopped: Arc<AtomicBool>,
+}
+
+/// Owns the timer thread that advances the running session every `speed_ms`.
+/// Responses arrive independently through `register_response`; the clock only closes turns.
+#[derive(Default)]
+pub struct SessionClock(Mutex<Option<ClockHandle>>);
+
+impl SessionClock {
+    /// Starts a clock for the session in `AppState`, stopping any previous one.
+    /// Call it while holding the `AppState` lock so an old clock can't close a turn of the
+    /// new session.
+    pub fn start(&self, app: AppHandle) {
+        let (commands, receiver) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let previous = self.0.lock().unwrap().replace(ClockHandle { commands, stopped: stopped.clone() });
+        if let Some(previous) = previous {
+            previous.stopped.store(true, Ordering::SeqCst);
+        }
+        std::thread::spawn(move || run(app, receiver, stopped));
+    }
+
+    pub fn pause(&self) {
+        self.send(ClockCommand::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(ClockCommand::Resume);
+    }
+
+    /// Stops the clock without closing the current turn.
+    pub fn stop(&self) {
+        if let Some(handle) = self.0.lock().unwrap().take() {
+            handle.stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn send(&self, command: ClockCommand) {
+        if let Some(handle) = self.0.lock().unwrap().as_ref() {
+            // The thread is gone once the session has finished; nothing left to control.
+            let _ = handle.commands.send(command);
+        }
+    }
+}
+
+/// Deadlines are kept on the monotonic clock and advance by exactly `speed_ms` per turn, so
+/// time spent handling a turn doesn't accumulate as drift.
+fn run(app: AppHandle, commands: Receiver<ClockCommand>, stopped: Arc<AtomicBool>) {
+    let app_state = app.state::<AppState>();
+    let mut deadline = {
+        let game_state = app_state.0.lock().unwrap();
+        emit_onset(&app, &game_state);
+        Instant::now() + Duration::from_millis(game_state.speed_ms())
+    };
+    // Time left on the current turn while paused
+    let mut paused_remaining: Option<Duration> = None;
+
+    loop {
+        let command = match paused_remaining {
+            Some(_) => commands.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            None => commands.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+        };
+        match command {
+            Ok(ClockCommand::Pause) => {
+                if paused_remaining.is_none() {
+                    paused_remaining = Some(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
+            Ok(ClockCommand::Resume) => {
+                if let Some(remaining) = paused_remaining.take() {
+                    deadline = Instant::now() + remaining;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+            Err(RecvTimeoutError::Timeout) => {
+                let mut game_state = app_state.0.lock().unwrap();
+                if stopped.load(Ordering::SeqCst) || !game_state.is_active() {
+                    return;
+                }
+                // Paused after the deadline passed but before the command arrived: close the
+                // turn as soon as the session resumes.
+                if game_state.is_paused() {
+                    paused_remaining = Some(Duration::ZERO);
+                    continue;
+                }
+
+                game_state.process_turn(UserResponse::default());
+                if let Some(event) = game_state.event_history.last() {
+                    emit(&app, TURN_CLOSED, event.clone());
+                }
+
+                let db_state = app.state::<DbState>();
+                let db = db_state.0.lock().unwrap();
+                if game_state.phase == GamePhase::Completed {
+                    match crate::save_finished_session(&**db, &game_state) {
+                        Ok(summary) => {
+                            emit(&app, SESSION_FINISHED, summary);
+                            if let Err(e) = db.clear_checkpoint() {
+                                eprintln!("Failed to clear session checkpoint: {}", e);
+                                emit(&app, CHECKPOINT_FAILED, AppError::from(e));
+                            }
+                        }
+                        // Keep the checkpoint taken before the last turn, so the session is
+                        // offered for resuming at the next start instead of being lost.
+                        Err(e) => {
+                            eprintln!("Failed to save game session: {}", e);
+                            emit(&app, SESSION_SAVE_FAILED, AppError::from(e));
+                        }
+                    }
+                    return;
+                }
+                if let Some(checkpoint) = game_state.checkpoint() {
+                    if let Err(e) = db.save_checkpoint(&checkpoint) {
+                        eprintln!("Failed to checkpoint game session: {}", e);
+                        emit(&app, CHECKPOINT_FAILED, AppError::from(e));
+                    }
+                }
+                drop(db);
+
+                emit_onset(&app, &game_state);
+                // A staircase may have changed the time per turn at the end of a block
+                let speed = Duration::from_millis(game_state.speed_ms());
+                deadline += speed;
+                // After a long stall (e.g. the machine slept) start the turn afresh instead of
+                // rushing through the missed ones.
+                if deadline < Instant::now() {
+                    deadline = Instant::now() + speed;
+                }
+            }
+        }
+    }
+}
+
+fn emit_onset(app: &AppHandle, game_state: &GameState) {
+    emit(app, STIMULUS_ONSET, FrontendGameState::from(game_state));
+}
+
+fn emit<S: serde::Serialize + Clone>(app: &AppHandle, event: &str, payload: S) {
+    if let Err(e) = app.emit(event, payload) {
+        eprintln!("Failed to emit {}: {}", event, e);
+    }
+}
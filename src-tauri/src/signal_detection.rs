@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+
+/// How hit and false-alarm rates of 0 or 1 are kept finite before they are converted to
+/// z-scores. Without a correction a perfect block would have an infinite d'.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateCorrection {
+    /// Hautus (1995): add 0.5 to every count and 1 to every total, for all rates.
+    #[default]
+    LogLinear,
+    /// Macmillan & Kaplan (1985): replace 0 with 1/(2N) and 1 with 1 - 1/(2N), leaving other
+    /// rates as they are.
+    HalfTrial,
+}
+
+impl RateCorrection {
+    /// Rate of `count` out of `total` with the correction applied. `total` must be non-zero.
+    pub fn rate(self, count: u32, total: u32) -> f64 {
+        let (count, total) = (count as f64, total as f64);
+        match self {
+            RateCorrection::LogLinear => (count + 0.5) / (total + 1.0),
+            RateCorrection::HalfTrial => {
+                let half_trial = 1.0 / (2.0 * total);
+                (count / total).clamp(half_trial, 1.0 - half_trial)
+            }
+        }
+    }
+}
+
+/// Signal-detection measures of one modality's responses.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SdtMetrics {
+    /// Sensitivity: z(H) - z(F).
+    pub d_prime: f64,
+    /// Response bias: -(z(H) + z(F)) / 2. Negative values mean a liberal bias towards
+    /// claiming matches.
+    pub criterion: f64,
+    /// Likelihood ratio at the criterion: exp(c · d').
+    pub beta: f64,
+    /// Non-parametric sensitivity, 0.5 at chance and 1.0 for perfect discrimination.
+    pub a_prime: f64,
+    /// Non-parametric bias between -1 (liberal) and 1 (conservative).
+    pub b_double_prime: f64,
+}
+
+impl SdtMetrics {
+    /// Computes the measures from raw counts. Returns `None` unless there was at least one
+    /// match turn and one non-match turn.
+    ///
+    /// d', c and β use corrected rates. A′ and B″ are defined for rates of 0 and 1, so they
+    /// use the raw rates (Grier, 1971, with the Snodgrass & Corwin form for H < F).
+    pub fn from_counts(
+        hits: u32,
+        misses: u32,
+        false_alarms: u32,
+        correct_rejections: u32,
+        correction: RateCorrection,
+    ) -> Option<Self> {
+        let signal_trials = hits + misses;
+        let noise_trials = false_alarms + correct_rejections;
+        if signal_trials == 0 || noise_trials == 0 {
+            return None;
+        }
+
+        let z_hit = inverse_normal_cdf(correction.rate(hits, signal_trials));
+        let z_false_alarm = inverse_normal_cdf(correction.rate(false_alarms, noise_trials));
+        let d_prime = z_hit - z_false_alarm;
+        let criterion = -(z_hit + z_false_alarm) / 2.0;
+
+        let h = hits as f64 / signal_trials as f64;
+        let f = false_alarms as f64 / noise_trials as f64;
+        let a_prime = if h == f {
+            0.5
+        } else {
+            let diff = h - f;
+            0.5 + diff.signum() * (diff * diff + diff.abs()) / (4.0 * h.max(f) - 4.0 * h * f)
+        };
+        let spread = h * (1.0 - h) + f * (1.0 - f);
+        let b_double_prime = if spread == 0.0 {
+            0.0
+        } else {
+            (h - f).signum() * (h * (1.0 - h) - f * (1.0 - f)) / spread
+        };
+
+        Some(Self {
+            d_prime,
+            criterion,
+            beta: (criterion * d_prime).exp(),
+            a_prime,
+            b_double_prime,
+        })
+    }
+}
+
+/// Quantile function of the standard normal distribution for `p` in (0, 1), using Acklam's
+/// rational approximation (relative error below 1.15e-9).
+pub fn inverse_normal_cdf(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e1,
+        2.209460984245205e2,
+        -2.759285104469687e2,
+        1.38357751867269e2,
+        -3.066479806614716e1,
+        2.506628277459239,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e1,
+        1.615858368580409e2,
+        -1.556989798598866e2,
+        6.680131188771972e1,
+        -1.328068155288572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-3,
+        -3.223964580411365e-1,
+        -2.400758277161838,
+        -2.549732539343734,
+        4.374664141464968,
+        2.938163982698783,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-3,
+        3.224671290700398e-1,
+        2.445134137142996,
+        3.754408661907416,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn test_inverse_normal_cdf_known_quantiles() {
+        assert_close(inverse_normal_cdf(0.5), 0.0);
+        assert_close(inverse_normal_cdf(0.975), 1.959964);
+        assert_close(inverse_normal_cdf(0.8), 0.841621);
+        assert_close(inverse_normal_cdf(0.01), -2.326348);
+        assert_close(inverse_normal_cdf(0.999), 3.090232);
+        assert_eq!(inverse_normal_cdf(0.0), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_metrics_for_unbiased_observer() {
+        // H = 0.8, F = 0.2: not extreme, so the 1/(2N) correction leaves the rates alone
+        let m = SdtMetrics::from_counts(8, 2, 2, 8, RateCorrection::HalfTrial).unwrap();
+        assert_close(m.d_prime, 1.683242);
+        assert_close(m.criterion, 0.0);
+        assert_close(m.beta, 1.0);
+        assert_close(m.a_prime, 0.875);
+        assert_close(m.b_double_prime, 0.0);
+
+        // The log-linear correction shrinks every rate towards 0.5
+        let m = SdtMetrics::from_counts(8, 2, 2, 8, RateCorrection::LogLinear).unwrap();
+        assert_close(m.d_prime, 1.495717);
+    }
+
+    #[test]
+    fn test_metrics_for_liberal_observer() {
+        // H = 0.9, F = 0.3
+        let m = SdtMetrics::from_counts(9, 1, 3, 7, RateCorrection::HalfTrial).unwrap();
+        assert_close(m.d_prime, 1.805952);
+        assert_close(m.criterion, -0.378576);
+        assert_close(m.beta, 0.504751);
+        assert_close(m.a_prime, 0.880952);
+        assert_close(m.b_double_prime, -0.4);
+
+        let m = SdtMetrics::from_counts(9, 1, 3, 7, RateCorrection::LogLinear).unwrap();
+        assert_close(m.d_prime, 1.569593);
+        assert_close(m.criterion, -0.312007);
+        assert_close(m.beta, 0.612795);
+    }
+
+    #[test]
+    fn test_extreme_rates_stay_finite() {
+        // Perfect block: H = 1, F = 0
+        let m = SdtMetrics::from_counts(10, 0, 0, 10, RateCorrection::HalfTrial).unwrap();
+        assert_close(m.d_prime, 3.289707); // Rates become 0.95 and 0.05
+        assert_close(m.a_prime, 1.0);
+        assert_close(m.b_double_prime, 0.0);
+
+        let m = SdtMetrics::from_counts(10, 0, 0, 10, RateCorrection::LogLinear).unwrap();
+        assert_close(m.d_prime, 3.381243); // Rates become 10.5/11 and 0.5/11
+
+        // Pressing on every turn: no discrimination at all
+        let m = SdtMetrics::from_counts(10, 0, 10, 0, RateCorrection::HalfTrial).unwrap();
+        assert_close(m.d_prime, 0.0);
+        assert_close(m.a_prime, 0.5);
+        assert!(m.criterion < 0.0);
+
+        assert_eq!(SdtMetrics::from_counts(0, 0, 3, 7, RateCorrection::LogLinear), None);
+        assert_eq!(SdtMetrics::from_counts(4, 1, 0, 0, RateCorrection::LogLinear), None);
+    }
+}
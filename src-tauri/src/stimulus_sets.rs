@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+// --- Manifest ---
+
+/// A custom auditory set as described on disk, e.g.
+///
+/// ```json
+/// {
+///   "id": "german_letters",
+///   "name": "German letters",
+///   "stimuli": [{ "token": "A", "file": "german/a.mp3" }]
+/// }
+/// ```
+///
+/// Sound files are relative to one of the sound directories (the bundled `sounds` resource
+/// directory or `sounds` in the app-data directory).
+#[derive(Deserialize, Debug, Clone)]
+pub struct StimulusSetManifest {
+    pub id: String,
+    pub name: String,
+    pub stimuli: Vec<ManifestStimulus>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ManifestStimulus {
+    pub token: String,
+    pub file: PathBuf,
+}
+
+// --- Registered Sets ---
+
+/// A validated custom auditory set, with every sound resolved to an existing file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CustomStimulusSet {
+    pub id: String,
+    pub name: String,
+    pub stimuli: Vec<CustomStimulus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CustomStimulus {
+    pub token: String,
+    pub sound: PathBuf,
+}
+
+impl CustomStimulusSet {
+    /// The tokens the audio stream is generated from.
+    pub fn tokens(&self) -> Vec<String> {
+        self.stimuli.iter().map(|s| s.token.clone()).collect()
+    }
+}
+
+/// Reasons a manifest cannot be registered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StimulusSetError {
+    /// The manifest file could not be read.
+    Io(String),
+    /// The manifest is not valid JSON or is missing fields.
+    Parse(String),
+    /// IDs are non-empty and limited to ASCII letters, digits, `-` and `_`.
+    InvalidId(String),
+    /// A set needs two distinct tokens, otherwise every turn would be a match.
+    TooFewStimuli { count: usize },
+    /// Each token may only appear once per set.
+    DuplicateToken(String),
+    /// Sound paths must be relative and stay inside the sound directory.
+    UnsafePath(PathBuf),
+    /// No sound directory contains the file for a token.
+    MissingSound { token: String, file: PathBuf },
+}
+
+impl fmt::Display for StimulusSetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StimulusSetError::Io(e) => write!(f, "Failed to read stimulus set manifest: {}", e),
+            StimulusSetError::Parse(e) => write!(f, "Invalid stimulus set manifest: {}", e),
+            StimulusSetError::InvalidId(id) => write!(
+                f,
+                "Stimulus set ID '{}' is invalid; use letters, digits, '-' and '_' only",
+                id
+            ),
+            StimulusSetError::TooFewStimuli { count } => write!(
+                f,
+                "Stimulus set has {} distinct stimuli, at least 2 are required",
+                count
+            ),
+            StimulusSetError::DuplicateToken(token) => {
+                write!(f, "Stimulus '{}' appears more than once", token)
+            }
+            StimulusSetError::UnsafePath(path) => write!(
+                f,
+                "Sound path '{}' must be relative to the sound directory",
+                path.display()
+            ),
+            StimulusSetError::MissingSound { token, file } => write!(
+                f,
+                "No sound file '{}' found for stimulus '{}'",
+                file.display(),
+                token
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StimulusSetError {}
+
+pub fn load_manifest(path: &Path) -> Result<StimulusSetManifest, StimulusSetError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| StimulusSetError::Io(e.to_string()))?;
+    serde_json::from_str(&contents).map_err(|e| StimulusSetError::Parse(e.to_string()))
+}
+
+impl StimulusSetManifest {
+    /// Validates the manifest and resolves each sound against `sound_dirs`, in order.
+    pub fn resolve(self, sound_dirs: &[PathBuf]) -> Result<CustomStimulusSet, StimulusSetError> {
+        let valid_id = !self.id.is_empty()
+            && self.id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_id {
+            return Err(StimulusSetError::InvalidId(self.id));
+        }
+
+        let mut seen = HashSet::new();
+        let mut stimuli = Vec::with_capacity(self.stimuli.len());
+        for stimulus in self.stimuli {
+            if !seen.insert(stimulus.token.clone()) {
+                return Err(StimulusSetError::DuplicateToken(stimulus.token));
+            }
+            if !stimulus.file.components().all(|c| matches!(c, Component::Normal(_))) {
+                return Err(StimulusSetError::UnsafePath(stimulus.file));
+            }
+            let sound = sound_dirs
+                .iter()
+                .map(|dir| dir.join(&stimulus.file))
+                .find(|path| path.is_file())
+                .ok_or_else(|| StimulusSetError::MissingSound {
+                    token: stimulus.token.clone(),
+                    file: stimulus.file.clone(),
+                })?;
+            stimuli.push(CustomStimulus { token: stimulus.token, sound });
+        }
+        if stimuli.len() < 2 {
+            return Err(StimulusSetError::TooFewStimuli { count: stimuli.len() });
+        }
+
+        Ok(CustomStimulusSet { id: self.id, name: self.name, stimuli })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sound_dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nback-sounds-{}-{}", name, std::process::id()));
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        dir
+    }
+
+    fn manifest(id: &str, stimuli: &[(&str, &str)]) -> StimulusSetManifest {
+        StimulusSetManifest {
+            id: id.to_string(),
+            name: "Test set".to_string(),
+            stimuli: stimuli
+                .iter()
+                .map(|(token, file)| ManifestStimulus { token: token.to_string(), file: file.into() })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_resolve_searches_sound_dirs_in_order() {
+        let bundled = sound_dir("bundled", &["digits/1.mp3", "digits/2.mp3"]);
+        let user = sound_dir("user", &["digits/2.mp3", "digits/3.mp3"]);
+        let dirs = [bundled.clone(), user.clone()];
+
+        let set = manifest("digits", &[("1", "digits/1.mp3"), ("2", "digits/2.mp3"), ("3", "digits/3.mp3")])
+            .resolve(&dirs)
+            .unwrap();
+        assert_eq!(set.tokens(), vec!["1", "2", "3"]);
+        assert_eq!(set.stimuli[1].sound, bundled.join("digits/2.mp3"));
+        assert_eq!(set.stimuli[2].sound, user.join("digits/3.mp3"));
+
+        let missing = manifest("digits", &[("1", "digits/1.mp3"), ("4", "digits/4.mp3")]).resolve(&dirs);
+        assert_eq!(
+            missing,
+            Err(StimulusSetError::MissingSound { token: "4".into(), file: "digits/4.mp3".into() })
+        );
+    }
+
+    #[test]
+    fn test_resolve_rejects_invalid_manifests() {
+        let dirs = [sound_dir("invalid", &["a.mp3", "b.mp3"])];
+
+        let bad_id = manifest("../sets", &[("a", "a.mp3"), ("b", "b.mp3")]).resolve(&dirs);
+        assert_eq!(bad_id, Err(StimulusSetError::InvalidId("../sets".into())));
+
+        let duplicate = manifest("set", &[("a", "a.mp3"), ("a", "b.mp3")]).resolve(&dirs);
+        assert_eq!(duplicate, Err(StimulusSetError::DuplicateToken("a".into())));
+
+        let escaping = manifest("set", &[("a", "../a.mp3")]).resolve(&dirs);
+        assert_eq!(escaping, Err(StimulusSetError::UnsafePath("../a.mp3".into())));
+
+        let single = manifest("set", &[("a", "a.mp3")]).resolve(&dirs);
+        assert_eq!(single, Err(StimulusSetError::TooFewStimuli { count: 1 }));
+    }
+}
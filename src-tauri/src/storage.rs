@@ -0,0 +1,893 @@
+//! Where settings, sessions, profiles and custom auditory sets are kept.
+//!
+//! Commands work against the [`Storage`] trait. Two backends implement it, each behind a cargo
+//! feature: the original sled database (`sled-store`) and SQLite (`sqlite-store`). With both
+//! enabled, as by default, new installations store their data in SQLite, and an existing sled
+//! database stays in use until it is moved over with [`migrate`].
+
+use crate::game::SessionCheckpoint;
+use crate::history_query::{HistoryQuery, HistoryQueryResult};
+use crate::persistence::{GameSession, GameSessionSummary, UserSettings};
+use crate::profiles::Profile;
+use crate::stimulus_sets::CustomStimulusSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[cfg(feature = "sled-store")]
+pub mod sled_store;
+#[cfg(feature = "sqlite-store")]
+pub mod sqlite_store;
+
+#[cfg(not(any(feature = "sled-store", feature = "sqlite-store")))]
+compile_error!("enable at least one storage backend: the `sled-store` or `sqlite-store` feature");
+
+pub struct DbState(pub Mutex<Box<dyn Storage>>);
+
+/// A store of everything the app persists.
+///
+/// Settings, sessions and the checkpoint belong to the active profile; profiles and custom
+/// auditory sets are shared. A store always holds the default profile, and opens as the
+/// profile that was active last.
+pub trait Storage: Send {
+    // --- Profiles ---
+
+    /// ID of the profile the settings, session and checkpoint methods work on.
+    fn profile_id(&self) -> &str;
+
+    /// Makes a stored profile active, also for later startups. Use
+    /// [`profiles::switch_profile`](crate::profiles::switch_profile), which checks it exists.
+    fn set_active_profile(&mut self, profile_id: &str) -> Result<(), StorageError>;
+
+    /// Returns every profile, oldest first.
+    fn list_profiles(&self) -> Result<Vec<Profile>, StorageError>;
+
+    fn load_profile(&self, profile_id: &str) -> Result<Option<Profile>, StorageError>;
+
+    /// Adds a profile, or replaces the one with the same ID.
+    fn save_profile(&self, profile: &Profile) -> Result<(), StorageError>;
+
+    /// Removes a profile with its settings, sessions and checkpoint.
+    fn remove_profile(&self, profile_id: &str) -> Result<(), StorageError>;
+
+    // --- Settings ---
+
+    fn save_settings(&self, settings: &UserSettings) -> Result<(), StorageError>;
+
+    /// The stored settings, or the defaults if there are none. Settings that cannot be decoded
+    /// are an error rather than the defaults, so they are not replaced unnoticed.
+    fn load_settings(&self) -> Result<UserSettings, StorageError>;
+
+    // --- Sessions ---
+
+    /// Adds a session to the history, or replaces the one with the same ID.
+    fn save_session(&self, session: &GameSession) -> Result<(), StorageError>;
+
+    fn load_session(&self, session_id: &str) -> Result<Option<GameSession>, StorageError>;
+
+    fn session_exists(&self, session_id: &str) -> Result<bool, StorageError>;
+
+    /// Removes one session from the history. Returns whether it existed.
+    fn delete_session(&self, session_id: &str) -> Result<bool, StorageError>;
+
+    /// Returns every stored session with its event history, newest first.
+    fn load_full_sessions(&self) -> Result<Vec<GameSession>, StorageError>;
+
+    /// Lists one page of the history matching `query`, without reading any event history.
+    fn query_history(&self, query: &HistoryQuery) -> Result<HistoryQueryResult, StorageError>;
+
+    /// Returns the summary of every stored session, newest first.
+    fn load_all_sessions(&self) -> Result<Vec<GameSessionSummary>, StorageError> {
+        Ok(self.query_history(&HistoryQuery::default())?.summaries)
+    }
+
+    /// Every tag used in the history, sorted.
+    fn load_all_tags(&self) -> Result<BTreeSet<String>, StorageError> {
+        Ok(self.load_all_sessions()?.into_iter().flat_map(|s| s.tags).collect())
+    }
+
+    // --- Checkpoint ---
+
+    /// Stores the in-progress session, replacing the previous checkpoint. It is on disk when
+    /// this returns, so it survives the app being closed.
+    fn save_checkpoint(&self, checkpoint: &SessionCheckpoint) -> Result<(), StorageError>;
+
+    /// The stored checkpoint. One that cannot be read is ignored.
+    fn load_checkpoint(&self) -> Result<Option<SessionCheckpoint>, StorageError>;
+
+    /// Removes the checkpoint once its session has been saved or aborted.
+    fn clear_checkpoint(&self) -> Result<(), StorageError>;
+
+    // --- Custom auditory sets ---
+
+    fn save_stimulus_set(&self, set: &CustomStimulusSet) -> Result<(), StorageError>;
+
+    fn load_stimulus_set(&self, id: &str) -> Result<Option<CustomStimulusSet>, StorageError>;
+
+    /// Returns every registered custom auditory set, ordered by ID.
+    fn load_all_stimulus_sets(&self) -> Result<Vec<CustomStimulusSet>, StorageError>;
+
+    /// Removes a custom auditory set. Returns whether it existed.
+    fn delete_stimulus_set(&self, id: &str) -> Result<bool, StorageError>;
+
+    // --- Maintenance ---
+
+    /// Removes all data of every profile, leaving only an empty default profile active.
+    fn clear_all_data(&mut self) -> Result<(), StorageError>;
+
+    /// Writes pending changes to disk.
+    fn flush(&self) -> Result<(), StorageError>;
+
+    /// Writes a copy of the whole store, every profile included, to `path`, which must not
+    /// exist yet. Open it again with [`open_snapshot`].
+    fn snapshot(&self, path: &Path) -> Result<(), StorageError>;
+
+    /// Reads through the whole store, failing with the first damage found.
+    fn check_integrity(&self) -> Result<(), StorageError>;
+}
+
+/// A storage backend. Which of them a build includes depends on its features.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sled,
+    Sqlite,
+}
+
+impl Backend {
+    /// The backends this build was compiled with. The first one is used for new stores.
+    pub fn compiled() -> Vec<Backend> {
+        [(cfg!(feature = "sqlite-store"), Backend::Sqlite), (cfg!(feature = "sled-store"), Backend::Sled)]
+            .into_iter()
+            .filter_map(|(compiled, backend)| compiled.then_some(backend))
+            .collect()
+    }
+
+    /// Name of its database in the app-data directory: a directory for sled, a file for SQLite.
+    pub fn db_name(self) -> &'static str {
+        match self {
+            Backend::Sled => "nback.db",
+            Backend::Sqlite => "nback.sqlite3",
+        }
+    }
+
+    /// Names of every file the database may consist of.
+    fn file_names(self) -> Vec<String> {
+        match self {
+            Backend::Sled => vec![self.db_name().to_string()],
+            Backend::Sqlite => ["", "-wal", "-shm", "-journal"]
+                .map(|suffix| format!("{}{}", self.db_name(), suffix))
+                .to_vec(),
+        }
+    }
+
+    /// The backend of the store at `path`, for stores that exist.
+    fn of_path(path: &Path) -> Option<Backend> {
+        if path.is_dir() {
+            Some(Backend::Sled)
+        } else if path.is_file() {
+            Some(Backend::Sqlite)
+        } else {
+            None
+        }
+    }
+}
+
+/// The backend of the store in the app-data directory: the first compiled one whose database
+/// exists there, or the one for new stores.
+pub fn active_backend(app_data_dir: &Path) -> Backend {
+    let compiled = Backend::compiled();
+    compiled.iter().copied().find(|b| app_data_dir.join(b.db_name()).exists()).unwrap_or(compiled[0])
+}
+
+/// The backend in use and the ones the data could be moved to.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Backends {
+    pub active: Backend,
+    pub compiled: Vec<Backend>,
+}
+
+pub fn backends(app_data_dir: &Path) -> Backends {
+    Backends { active: active_backend(app_data_dir), compiled: Backend::compiled() }
+}
+
+/// Opens the store in the app-data directory with its backend.
+pub fn open(app_data_dir: &Path) -> Result<Box<dyn Storage>, StorageError> {
+    open_as(active_backend(app_data_dir), app_data_dir)
+}
+
+/// Opens, or starts, the store of `backend` in the app-data directory.
+pub fn open_as(backend: Backend, app_data_dir: &Path) -> Result<Box<dyn Storage>, StorageError> {
+    open_backend(backend, &app_data_dir.join(backend.db_name()))
+}
+
+fn open_backend(backend: Backend, path: &Path) -> Result<Box<dyn Storage>, StorageError> {
+    match backend {
+        #[cfg(feature = "sled-store")]
+        Backend::Sled => Ok(Box::new(sled_store::SledStorage::open(path)?)),
+        #[cfg(feature = "sqlite-store")]
+        Backend::Sqlite => Ok(Box::new(sqlite_store::SqliteStorage::open(path)?)),
+        #[allow(unreachable_patterns)]
+        _ => {
+            let message = format!("This build has no {:?} backend", backend);
+            Err(std::io::Error::new(std::io::ErrorKind::Unsupported, message).into())
+        }
+    }
+}
+
+/// The files and directories of the database in the app-data directory, of those that exist.
+/// The ones of the store in use come first, followed by any of another backend.
+pub fn database_files(app_data_dir: &Path) -> Vec<PathBuf> {
+    let active = active_backend(app_data_dir);
+    let mut backends = Backend::compiled();
+    backends.sort_by_key(|&backend| backend != active);
+    backends
+        .into_iter()
+        .flat_map(Backend::file_names)
+        .map(|name| app_data_dir.join(name))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Moves the store in the app-data directory, open as `db`, to another backend: copies
+/// everything into a new database, switches `db` over to it, and renames the old database to
+/// `<name>.migrated`. The old one is kept that way, but neither opened nor recovered again.
+/// The copy is written under a temporary name first, so an interrupted migration leaves the
+/// old store in use.
+pub fn migrate(db: &mut Box<dyn Storage>, app_data_dir: &Path, to: Backend) -> Result<CopyReport, StorageError> {
+    let from = active_backend(app_data_dir);
+    if from == to {
+        let message = format!("The data is already stored with {:?}", to);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into());
+    }
+    let path = app_data_dir.join(to.db_name());
+    ensure_vacant(&path)?;
+    let retired: Vec<(PathBuf, PathBuf)> = from
+        .file_names()
+        .into_iter()
+        .map(|name| (app_data_dir.join(&name), app_data_dir.join(format!("{}.migrated", name))))
+        .collect();
+    for (_, migrated) in &retired {
+        ensure_vacant(migrated)?;
+    }
+
+    let partial = app_data_dir.join(format!("{}.partial", to.db_name()));
+    if partial.is_dir() {
+        std::fs::remove_dir_all(&partial)?;
+    } else if partial.exists() {
+        std::fs::remove_file(&partial)?;
+    }
+    let mut copy = open_backend(to, &partial)?;
+    let report = copy_storage(&mut **db, &mut *copy)?;
+    drop(copy);
+    std::fs::rename(&partial, &path)?;
+
+    // The old store is closed before its files are moved
+    *db = open_backend(to, &path)?;
+    for (file, migrated) in retired {
+        if file.exists() {
+            std::fs::rename(file, migrated)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Records read back from a damaged database by [`salvage`]. Settings and sessions are paired
+/// with the ID of the profile they belong to.
+#[derive(Debug, Default)]
+pub struct Salvage {
+    pub profiles: Vec<Profile>,
+    pub settings: Vec<(String, UserSettings)>,
+    pub sessions: Vec<(String, GameSession)>,
+    pub stimulus_sets: Vec<CustomStimulusSet>,
+    /// Records, or runs of records, that could not be read or decoded.
+    pub unreadable: usize,
+}
+
+/// Reads every record that can still be read and decoded from the database at `path`, a sled
+/// directory or a SQLite file, without relying on the damaged parts.
+pub fn salvage(path: &Path) -> Result<Salvage, StorageError> {
+    #[cfg(feature = "sled-store")]
+    if path.is_dir() {
+        return sled_store::salvage(path);
+    }
+    #[cfg(feature = "sqlite-store")]
+    if path.is_file() {
+        return sqlite_store::salvage(path);
+    }
+    Err(StorageError::Corrupt(format!("{} is not a database", path.display())))
+}
+
+/// Opens a copy written by [`Storage::snapshot`] with the backend that wrote it, so backups
+/// taken before a [`migrate`] still open, upgrading it if an older version of the app wrote it.
+/// A path that doesn't exist yet becomes a new store of the backend for new stores.
+pub fn open_snapshot(path: &Path) -> Result<Box<dyn Storage>, StorageError> {
+    let backend = Backend::of_path(path).unwrap_or(Backend::compiled()[0]);
+    open_backend(backend, path)
+}
+
+/// Fails if `path` is taken, so a snapshot never merges into or replaces an existing file.
+fn ensure_vacant(path: &Path) -> Result<(), StorageError> {
+    if path.exists() {
+        let message = format!("{} already exists", path.display());
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message).into());
+    }
+    Ok(())
+}
+
+/// What `copy_storage` copied.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct CopyReport {
+    pub profiles: usize,
+    pub sessions: usize,
+    pub stimulus_sets: usize,
+}
+
+/// Copies every profile with its settings, sessions and checkpoint, and every custom auditory
+/// set, from one store into another, in either direction between the backends. Records in `to`
+/// with the same IDs are replaced. Both stores end up on the profile that was active in `from`.
+pub fn copy_storage(from: &mut dyn Storage, to: &mut dyn Storage) -> Result<CopyReport, StorageError> {
+    let active = from.profile_id().to_string();
+    let mut report = CopyReport::default();
+    for profile in from.list_profiles()? {
+        to.save_profile(&profile)?;
+        from.set_active_profile(&profile.id)?;
+        to.set_active_profile(&profile.id)?;
+        to.save_settings(&from.load_settings()?)?;
+        for session in from.load_full_sessions()? {
+            to.save_session(&session)?;
+            report.sessions += 1;
+        }
+        match from.load_checkpoint()? {
+            Some(checkpoint) => to.save_checkpoint(&checkpoint)?,
+            None => to.clear_checkpoint()?,
+        }
+        report.profiles += 1;
+    }
+    for set in from.load_all_stimulus_sets()? {
+        to.save_stimulus_set(&set)?;
+        report.stimulus_sets += 1;
+    }
+    from.set_active_profile(&active)?;
+    to.set_active_profile(&active)?;
+    to.flush()?;
+    Ok(report)
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    #[cfg(feature = "sled-store")]
+    Sled(sled::Error),
+    #[cfg(feature = "sqlite-store")]
+    Sqlite(rusqlite::Error),
+    Io(std::io::Error),
+    /// A record could not be encoded for storage.
+    Encode(bincode::Error),
+    /// A stored record could not be decoded.
+    Decode(bincode::Error),
+    /// The integrity check found damage.
+    Corrupt(String),
+}
+
+impl StorageError {
+    /// Whether the error means the database itself is damaged, rather than e.g. locked by
+    /// another instance of the app or out of reach.
+    pub fn is_damage(&self) -> bool {
+        match self {
+            #[cfg(feature = "sled-store")]
+            StorageError::Sled(sled::Error::Corruption { .. } | sled::Error::ReportableBug(_)) => true,
+            #[cfg(feature = "sled-store")]
+            StorageError::Sled(sled::Error::Io(e)) => {
+                matches!(e.kind(), std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof)
+            }
+            #[cfg(feature = "sqlite-store")]
+            StorageError::Sqlite(e) => matches!(
+                e.sqlite_error_code(),
+                Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+            ),
+            StorageError::Corrupt(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "sled-store")]
+impl From<sled::Error> for StorageError {
+    fn from(e: sled::Error) -> Self {
+        StorageError::Sled(e)
+    }
+}
+
+#[cfg(feature = "sqlite-store")]
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+impl From<bincode::Error> for StorageError {
+    fn from(e: bincode::Error) -> Self {
+        StorageError::Encode(e)
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(e: std::io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "sled-store")]
+            StorageError::Sled(e) => write!(f, "Database error: {}", e),
+            #[cfg(feature = "sqlite-store")]
+            StorageError::Sqlite(e) => write!(f, "Database error: {}", e),
+            StorageError::Io(e) => write!(f, "Database file error: {}", e),
+            StorageError::Encode(e) => write!(f, "Failed to encode record: {}", e),
+            StorageError::Decode(e) => write!(f, "Failed to decode record: {}", e),
+            StorageError::Corrupt(problem) => write!(f, "Database is damaged: {}", problem),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+#[cfg(all(test, feature = "sled-store", feature = "sqlite-store"))]
+mod tests {
+    use super::*;
+    use sled_store::SledStorage;
+    use sqlite_store::SqliteStorage;
+
+    #[test]
+    fn test_copy_sled_to_sqlite_and_back() {
+        conformance::copy_round_trip(
+            &mut SledStorage::temporary(),
+            &mut SqliteStorage::in_memory(),
+            &mut SledStorage::temporary(),
+        );
+    }
+
+    #[test]
+    fn test_migrate_both_ways_and_retire_the_old_store() {
+        let dir = conformance::temp_path("storage-migrate");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut db = open(&dir).unwrap();
+        assert_eq!(active_backend(&dir), Backend::Sqlite);
+        conformance::save_daily_sessions(&*db, 3);
+
+        let report = migrate(&mut db, &dir, Backend::Sled).unwrap();
+        assert_eq!(report.sessions, 3);
+        assert_eq!(active_backend(&dir), Backend::Sled);
+        assert!(dir.join("nback.sqlite3.migrated").is_file());
+        assert_eq!(database_files(&dir), vec![dir.join(Backend::Sled.db_name())]);
+        assert_eq!(db.load_all_sessions().unwrap().len(), 3);
+        assert!(migrate(&mut db, &dir, Backend::Sled).is_err());
+
+        conformance::save_daily_sessions(&*db, 4);
+        migrate(&mut db, &dir, Backend::Sqlite).unwrap();
+        assert!(dir.join("nback.db.migrated").is_dir());
+        drop(db);
+        let mut db = open(&dir).unwrap();
+        assert_eq!(db.load_all_sessions().unwrap().len(), 4);
+
+        // A second move to sled would replace the store retired by the first one
+        assert!(migrate(&mut db, &dir, Backend::Sled).is_err());
+        assert_eq!(active_backend(&dir), Backend::Sqlite);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+/// Tests every backend runs, through `storage_conformance_tests!`.
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use crate::difficulty::{DifficultyPolicy, Staircase};
+    use crate::game::{AccuracyStats, GameEvent, GameState, Stimulus, UserResponse};
+    use crate::history_query::SortOrder;
+    use crate::persistence::{AbortedSessionPolicy, SessionOutcome};
+    use crate::profiles::{self, ProfileError, DEFAULT_PROFILE_ID};
+    use crate::sequence_generator::{AuditoryStimulusSet, GridLayout, MatchDistribution, Modality, NLevelMode};
+    use crate::stimulus_sets::CustomStimulus;
+    use chrono::{TimeZone, Utc};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    /// Generates one `#[test]` per conformance test, each on a fresh store from `$open`.
+    macro_rules! storage_conformance_tests {
+        ($open:expr; $($name:ident),* $(,)?) => {
+            $(
+                #[test]
+                fn $name() {
+                    let mut db = $open;
+                    $crate::storage::conformance::$name(&mut db);
+                }
+            )*
+        };
+        ($open:expr) => {
+            $crate::storage::conformance::storage_conformance_tests!(
+                $open;
+                settings_round_trip,
+                sessions_round_trip,
+                annotate_tag_and_delete_sessions,
+                query_filters_sorts_and_pages,
+                stimulus_sets_round_trip,
+                checkpoint_round_trip,
+                profiles_have_separate_settings_and_history,
+                create_rename_and_delete_profiles,
+                clear_all_data_leaves_default_profile,
+            );
+        };
+    }
+    pub(crate) use storage_conformance_tests;
+
+    fn stats_with_hits(hits: u32) -> BTreeMap<Modality, AccuracyStats> {
+        let stats = AccuracyStats { true_positives: hits, ..Default::default() };
+        BTreeMap::from([(Modality::Position, stats.clone()), (Modality::Audio, stats)])
+    }
+
+    /// Saves one session a day from 1 May 2024, alternating N = 2 and N = 3.
+    pub(crate) fn save_daily_sessions(db: &dyn Storage, days: u32) -> Vec<GameSession> {
+        (0..days)
+            .map(|day| {
+                let settings = UserSettings { n_level: 2 + (day as usize % 2), ..Default::default() };
+                let mut session = GameSession::new(settings, day as u64, vec![], BTreeMap::new());
+                session.timestamp = Utc.with_ymd_and_hms(2024, 5, 1 + day, 12, 0, 0).unwrap();
+                session.id = format!("session_{}", day);
+                if day % 3 == 0 {
+                    session.annotate(String::new(), ["tired".to_string()]);
+                }
+                db.save_session(&session).unwrap();
+                session
+            })
+            .collect()
+    }
+
+    pub(crate) fn ids(result: &HistoryQueryResult) -> Vec<&str> {
+        result.summaries.iter().map(|s| s.id.as_str()).collect()
+    }
+
+    pub(crate) fn settings_round_trip(db: &mut dyn Storage) {
+        // Test loading default settings
+        let loaded_settings = db.load_settings().unwrap();
+        assert_eq!(loaded_settings.n_level, 2);
+        assert_eq!(loaded_settings.auditory_stimulus_set, AuditoryStimulusSet::AllLetters);
+
+        // Test saving and loading custom settings
+        let custom_settings = UserSettings {
+            n_level: 3,
+            n_level_mode: NLevelMode::Variable,
+            speed_ms: 1500,
+            session_length: 25,
+            auditory_stimulus_set: AuditoryStimulusSet::NonConfusingLetters,
+            grid_layout: GridLayout::Rect { rows: 3, cols: 4 },
+            modalities: vec![Modality::Position, Modality::Audio, Modality::Color],
+            match_distribution: MatchDistribution::Fixed { single: 6, shared: 2 },
+            lure_rates: BTreeMap::from([(Modality::Position, 0.1)]),
+            seed: Some(99),
+            aborted_sessions: AbortedSessionPolicy::KeepPartial,
+            difficulty_policy: DifficultyPolicy::BrainWorkshop,
+            staircase: Some(Staircase::default()),
+        };
+        db.save_settings(&custom_settings).unwrap();
+        let loaded_settings = db.load_settings().unwrap();
+        assert_eq!(loaded_settings.n_level, 3);
+        assert_eq!(loaded_settings.n_level_mode, NLevelMode::Variable);
+        assert_eq!(loaded_settings.session_length, 25);
+        assert_eq!(loaded_settings.auditory_stimulus_set, AuditoryStimulusSet::NonConfusingLetters);
+        assert_eq!(loaded_settings.modalities.len(), 3);
+        assert_eq!(
+            loaded_settings.match_distribution,
+            MatchDistribution::Fixed { single: 6, shared: 2 }
+        );
+        assert_eq!(loaded_settings.lure_rates.get(&Modality::Position), Some(&0.1));
+        assert_eq!(loaded_settings.seed, Some(99));
+        assert_eq!(loaded_settings.grid_layout, GridLayout::Rect { rows: 3, cols: 4 });
+        assert_eq!(loaded_settings.aborted_sessions, AbortedSessionPolicy::KeepPartial);
+        assert_eq!(loaded_settings.difficulty_policy, DifficultyPolicy::BrainWorkshop);
+        assert_eq!(loaded_settings.staircase, Some(Staircase::default()));
+    }
+
+    pub(crate) fn sessions_round_trip(db: &mut dyn Storage) {
+        // Initially, there should be no sessions
+        let summaries = db.load_all_sessions().unwrap();
+        assert!(summaries.is_empty());
+
+        // Create and save two sessions
+        let settings = UserSettings::default();
+        let event_history1 = vec![GameEvent {
+            turn_index: 0,
+            stimulus: Stimulus(BTreeMap::from([
+                (Modality::Position, "1".to_string()),
+                (Modality::Audio, "A".to_string()),
+            ])),
+            n_back: 2,
+            matches: BTreeSet::new(),
+            lures: BTreeSet::new(),
+            user_response: UserResponse::default(),
+            reaction_times_ms: BTreeMap::new(),
+            pauses: vec![],
+            level_change: None,
+        }];
+        let session1 = GameSession::new(settings.clone(), 1, event_history1, stats_with_hits(1));
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+
+        let event_history2 = vec![]; // Empty for simplicity
+        let mut session2 = GameSession::new(settings.clone(), 2, event_history2, stats_with_hits(2));
+        session2.outcome = SessionOutcome::Aborted;
+
+        db.save_session(&session1).unwrap();
+        db.save_session(&session2).unwrap();
+
+        // Load session summaries and check
+        let summaries = db.load_all_sessions().unwrap();
+        assert_eq!(summaries.len(), 2);
+
+        // Check if they are sorted correctly (newest first) and data is intact
+        assert_eq!(summaries[0].id, session2.id);
+        assert_eq!(summaries[0].stats[&Modality::Position].true_positives, 2);
+        assert_eq!(summaries[0].outcome, SessionOutcome::Aborted);
+        assert_eq!(summaries[1].outcome, SessionOutcome::Completed);
+        assert_eq!(summaries[1].turns_played, 1);
+        assert_eq!(summaries[1].stats[&Modality::Position].true_positives, 1);
+        let full: Vec<String> = db.load_full_sessions().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(full, vec![session2.id.clone(), session1.id.clone()]);
+
+        // Load a single full session by ID and check its details
+        let loaded_session1 = db.load_session(&session1.id).unwrap().unwrap();
+        assert_eq!(loaded_session1.id, session1.id);
+        assert_eq!(loaded_session1.seed, 1);
+        assert_eq!(loaded_session1.event_history.len(), 1);
+        assert_eq!(loaded_session1.event_history[0].stimulus.position(), Some(1));
+        assert!(db.session_exists(&session1.id).unwrap());
+
+        // Test loading a non-existent session
+        assert!(db.load_session("non-existent-id").unwrap().is_none());
+        assert!(!db.session_exists("non-existent-id").unwrap());
+    }
+
+    pub(crate) fn annotate_tag_and_delete_sessions(db: &mut dyn Storage) {
+        let mut session1 = GameSession::new(UserSettings::default(), 1, vec![], stats_with_hits(1));
+        let session2 = GameSession::new(UserSettings::default(), 2, vec![], stats_with_hits(2));
+        db.save_session(&session1).unwrap();
+        session1.annotate(
+            "Late evening".to_string(),
+            ["tired ", "", "experiment-A", "tired"].map(String::from),
+        );
+        db.save_session(&session1).unwrap();
+        db.save_session(&session2).unwrap();
+
+        let loaded = db.load_session(&session1.id).unwrap().unwrap();
+        assert_eq!(loaded.notes, "Late evening");
+        assert_eq!(loaded.tags, BTreeSet::from(["experiment-A".to_string(), "tired".to_string()]));
+        assert_eq!(db.load_all_tags().unwrap(), loaded.tags);
+        let summaries = db.load_all_sessions().unwrap();
+        assert_eq!(summaries.len(), 2);
+        let summary = summaries.into_iter().find(|s| s.id == session1.id).unwrap();
+        assert!(summary.tags.contains("tired"));
+
+        assert!(db.delete_session(&session1.id).unwrap());
+        assert!(!db.delete_session(&session1.id).unwrap());
+        assert!(db.load_session(&session1.id).unwrap().is_none());
+        assert_eq!(db.load_all_sessions().unwrap().len(), 1);
+        assert!(db.load_all_tags().unwrap().is_empty());
+    }
+
+    pub(crate) fn query_filters_sorts_and_pages(db: &mut dyn Storage) {
+        save_daily_sessions(db, 10);
+
+        let all = db.query_history(&HistoryQuery::default()).unwrap();
+        assert_eq!(all.total, 10);
+        assert_eq!(all.summaries[0].id, "session_9");
+
+        let query = HistoryQuery {
+            from: Some(Utc.with_ymd_and_hms(2024, 5, 3, 0, 0, 0).unwrap()),
+            // Inclusive, down to the exact timestamp of the session on 7 May
+            to: Some(Utc.with_ymd_and_hms(2024, 5, 7, 12, 0, 0).unwrap()),
+            order: SortOrder::OldestFirst,
+            ..Default::default()
+        };
+        let result = db.query_history(&query).unwrap();
+        assert_eq!(ids(&result), vec!["session_2", "session_3", "session_4", "session_5", "session_6"]);
+
+        let page = HistoryQuery { offset: 1, limit: Some(2), ..query.clone() };
+        let result = db.query_history(&page).unwrap();
+        assert_eq!(ids(&result), vec!["session_3", "session_4"]);
+        assert_eq!(result.total, 5);
+
+        let filtered = HistoryQuery { n_level: Some(3), ..query.clone() };
+        assert_eq!(ids(&db.query_history(&filtered).unwrap()), vec!["session_3", "session_5"]);
+
+        let tagged = HistoryQuery { tag: Some("tired".to_string()), ..Default::default() };
+        assert_eq!(
+            ids(&db.query_history(&tagged).unwrap()),
+            vec!["session_9", "session_6", "session_3", "session_0"]
+        );
+
+        let letters = HistoryQuery { stimulus_set: Some(AuditoryStimulusSet::AllLetters), ..Default::default() };
+        assert_eq!(db.query_history(&letters).unwrap().total, 10);
+        let other_set = HistoryQuery { stimulus_set: Some(AuditoryStimulusSet::TianGanDiZhi), ..Default::default() };
+        assert_eq!(db.query_history(&other_set).unwrap().total, 0);
+    }
+
+    pub(crate) fn stimulus_sets_round_trip(db: &mut dyn Storage) {
+        let set = |id: &str| CustomStimulusSet {
+            id: id.to_string(),
+            name: format!("Set {}", id),
+            stimuli: ["eins", "zwei"]
+                .iter()
+                .map(|token| CustomStimulus {
+                    token: token.to_string(),
+                    sound: format!("/sounds/{}/{}.mp3", id, token).into(),
+                })
+                .collect(),
+        };
+        db.save_stimulus_set(&set("words")).unwrap();
+        db.save_stimulus_set(&set("digits")).unwrap();
+
+        assert_eq!(db.load_stimulus_set("words").unwrap(), Some(set("words")));
+        let ids: Vec<String> = db.load_all_stimulus_sets().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec!["digits", "words"]);
+
+        assert!(db.delete_stimulus_set("words").unwrap());
+        assert!(!db.delete_stimulus_set("words").unwrap());
+        assert_eq!(db.load_stimulus_set("words").unwrap(), None);
+    }
+
+    pub(crate) fn checkpoint_round_trip(db: &mut dyn Storage) {
+        assert!(db.load_checkpoint().unwrap().is_none());
+
+        let mut game_state = GameState::new(UserSettings::default()).unwrap();
+        game_state.start();
+        game_state.process_turn(UserResponse::default());
+        db.save_checkpoint(&game_state.checkpoint().unwrap()).unwrap();
+
+        let restored = GameState::restore(db.load_checkpoint().unwrap().unwrap());
+        assert_eq!(restored.current_turn_index, 1);
+        assert_eq!(restored.seed, game_state.seed);
+        assert_eq!(restored.peek_stimulus(), game_state.peek_stimulus());
+
+        db.clear_checkpoint().unwrap();
+        assert!(db.load_checkpoint().unwrap().is_none());
+    }
+
+    pub(crate) fn profiles_have_separate_settings_and_history(db: &mut dyn Storage) {
+        assert_eq!(db.profile_id(), DEFAULT_PROFILE_ID);
+        db.save_settings(&UserSettings { n_level: 3, ..Default::default() }).unwrap();
+        let session = GameSession::new(UserSettings::default(), 1, vec![], BTreeMap::new());
+        db.save_session(&session).unwrap();
+
+        let ada = profiles::create_profile(db, " Ada ").unwrap();
+        assert_eq!(ada.name, "Ada");
+        profiles::switch_profile(db, &ada.id).unwrap();
+        assert_eq!(db.load_settings().unwrap().n_level, 2);
+        assert!(db.load_all_sessions().unwrap().is_empty());
+        assert!(!db.session_exists(&session.id).unwrap());
+        db.save_settings(&UserSettings { n_level: 5, ..Default::default() }).unwrap();
+
+        profiles::switch_profile(db, DEFAULT_PROFILE_ID).unwrap();
+        assert_eq!(db.load_settings().unwrap().n_level, 3);
+        assert_eq!(db.load_all_sessions().unwrap().len(), 1);
+    }
+
+    pub(crate) fn create_rename_and_delete_profiles(db: &mut dyn Storage) {
+        assert!(matches!(profiles::create_profile(db, "  "), Err(ProfileError::EmptyName)));
+        let ada = profiles::create_profile(db, "Ada").unwrap();
+        assert!(matches!(profiles::create_profile(db, "ada"), Err(ProfileError::DuplicateName(_))));
+        let bob = profiles::create_profile(db, "Bob").unwrap();
+
+        assert_eq!(profiles::rename_profile(db, &bob.id, "Bob").unwrap().name, "Bob");
+        assert!(matches!(profiles::rename_profile(db, &bob.id, "Ada"), Err(ProfileError::DuplicateName(_))));
+        assert_eq!(profiles::rename_profile(db, &bob.id, "Robert").unwrap().name, "Robert");
+        let names: Vec<String> = db.list_profiles().unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["Default", "Ada", "Robert"]);
+
+        assert!(matches!(profiles::delete_profile(db, DEFAULT_PROFILE_ID), Err(ProfileError::DeleteDefault)));
+        profiles::switch_profile(db, &ada.id).unwrap();
+        db.save_settings(&UserSettings { n_level: 6, ..Default::default() }).unwrap();
+        db.save_session(&GameSession::new(UserSettings::default(), 1, vec![], BTreeMap::new())).unwrap();
+        assert!(matches!(profiles::delete_profile(db, &ada.id), Err(ProfileError::DeleteActive)));
+
+        profiles::switch_profile(db, &bob.id).unwrap();
+        profiles::delete_profile(db, &ada.id).unwrap();
+        assert!(matches!(profiles::switch_profile(db, &ada.id), Err(ProfileError::NotFound(_))));
+        assert_eq!(db.list_profiles().unwrap().len(), 2);
+
+        // A new profile never picks up data left behind by a deleted one
+        let ada = Profile { name: "Ada again".to_string(), ..ada };
+        db.save_profile(&ada).unwrap();
+        profiles::switch_profile(db, &ada.id).unwrap();
+        assert_eq!(db.load_settings().unwrap().n_level, 2);
+        assert!(db.load_all_sessions().unwrap().is_empty());
+    }
+
+    pub(crate) fn clear_all_data_leaves_default_profile(db: &mut dyn Storage) {
+        let ada = profiles::create_profile(db, "Ada").unwrap();
+        profiles::switch_profile(db, &ada.id).unwrap();
+        save_daily_sessions(db, 2);
+        db.save_settings(&UserSettings { n_level: 4, ..Default::default() }).unwrap();
+
+        db.clear_all_data().unwrap();
+        assert_eq!(db.profile_id(), DEFAULT_PROFILE_ID);
+        let profiles: Vec<String> = db.list_profiles().unwrap().into_iter().map(|p| p.id).collect();
+        assert_eq!(profiles, vec![DEFAULT_PROFILE_ID]);
+        assert!(db.load_all_sessions().unwrap().is_empty());
+        assert_eq!(db.load_settings().unwrap().n_level, 2);
+    }
+
+    /// Stores two profiles with their own settings and history, leaving the second one active
+    /// with a checkpoint. Returns the profiles and the checkpoint's seed.
+    fn save_two_profiles(db: &mut dyn Storage) -> (Vec<Profile>, u64) {
+        let ada = profiles::create_profile(db, "Ada").unwrap();
+        db.save_settings(&UserSettings { n_level: 3, ..Default::default() }).unwrap();
+        save_daily_sessions(db, 4);
+        profiles::switch_profile(db, &ada.id).unwrap();
+        db.save_settings(&UserSettings { n_level: 5, ..Default::default() }).unwrap();
+        save_daily_sessions(db, 2);
+        let mut game_state = GameState::new(UserSettings::default()).unwrap();
+        game_state.start();
+        db.save_checkpoint(&game_state.checkpoint().unwrap()).unwrap();
+        (db.list_profiles().unwrap(), game_state.seed)
+    }
+
+    /// Checks `db` holds what `save_two_profiles` stored.
+    fn check_two_profiles(db: &mut dyn Storage, stored_profiles: &[Profile], seed: u64) {
+        assert_eq!(db.profile_id(), stored_profiles[1].id);
+        assert_eq!(db.load_settings().unwrap().n_level, 5);
+        assert_eq!(db.load_all_sessions().unwrap().len(), 2);
+        assert_eq!(db.load_checkpoint().unwrap().unwrap().seed, seed);
+        assert_eq!(db.list_profiles().unwrap(), stored_profiles);
+
+        profiles::switch_profile(db, DEFAULT_PROFILE_ID).unwrap();
+        assert_eq!(db.load_settings().unwrap().n_level, 3);
+        assert!(db.load_checkpoint().unwrap().is_none());
+        let tagged = HistoryQuery { tag: Some("tired".to_string()), ..Default::default() };
+        assert_eq!(ids(&db.query_history(&tagged).unwrap()), vec!["session_3", "session_0"]);
+        assert_eq!(db.load_session("session_2").unwrap().unwrap().seed, 2);
+    }
+
+    /// Copies a store with two profiles into `to` and back into `back`, checking nothing is
+    /// lost either way.
+    #[cfg(all(feature = "sled-store", feature = "sqlite-store"))]
+    pub(crate) fn copy_round_trip<'a>(
+        from: &mut dyn Storage,
+        to: &'a mut (dyn Storage + 'a),
+        back: &'a mut (dyn Storage + 'a),
+    ) {
+        let (stored_profiles, seed) = save_two_profiles(from);
+
+        let report = copy_storage(from, to).unwrap();
+        assert_eq!(report, CopyReport { profiles: 2, sessions: 6, stimulus_sets: 0 });
+        assert_eq!(copy_storage(to, back).unwrap(), report);
+
+        for db in [to, back] {
+            check_two_profiles(db, &stored_profiles, seed);
+        }
+    }
+
+    /// A fresh path in the temporary directory, for stores that must live on disk.
+    pub(crate) fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nback-tests-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    /// Snapshots a store with two profiles to `path` and reopens the copy with `reopen`,
+    /// checking it is complete and independent of the original.
+    pub(crate) fn snapshot_round_trip(db: &mut dyn Storage, path: &Path, reopen: impl Fn(&Path) -> Box<dyn Storage>) {
+        let (stored_profiles, seed) = save_two_profiles(db);
+        db.snapshot(path).unwrap();
+        assert!(db.snapshot(path).is_err(), "an existing snapshot must not be overwritten");
+        db.clear_all_data().unwrap();
+
+        let mut copy = reopen(path);
+        check_two_profiles(&mut *copy, &stored_profiles, seed);
+    }
+}
@@ -0,0 +1,728 @@
+//! The sled backend. Every record is a versioned envelope (see [`migrations`]) in a sled tree
+//! or under a top-level key. Settings, sessions, session summaries and the checkpoint are
+//! stored per profile, under names scoped by [`scoped_name`]; the default profile keeps the
+//! unscoped names, so data from before profiles existed stays where it was.
+
+use crate::game::SessionCheckpoint;
+use crate::history_query::{HistoryQuery, HistoryQueryResult, SortOrder};
+use crate::migrations::{self, Versioned, CURRENT_VERSION};
+use crate::persistence::{GameSession, GameSessionSummary, UserSettings};
+use crate::profiles::{self, Profile, DEFAULT_PROFILE_ID};
+use crate::stimulus_sets::CustomStimulusSet;
+use super::{Salvage, Storage, StorageError};
+use chrono::{DateTime, Utc};
+use sled::{Db, Tree};
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::path::Path;
+
+const SETTINGS_KEY: &str = "user_settings";
+const CHECKPOINT_KEY: &str = "session_checkpoint";
+const SESSIONS_TREE: &str = "game_sessions";
+/// Summaries of the sessions, keyed by timestamp, so the history can be listed, filtered and
+/// paged without deserializing any event history. Derived from the sessions tree: kept in step
+/// by `save_session` and `delete_session`, and rebuilt whenever the two disagree.
+const SUMMARIES_TREE: &str = "game_session_summaries";
+/// How many sessions the last rebuild of the index left out because they could not be read,
+/// so that they are not mistaken for sessions the index lost track of.
+const UNINDEXED_KEY: &str = "unindexed_sessions";
+const STIMULUS_SETS_TREE: &str = "auditory_stimulus_sets";
+const PROFILES_TREE: &str = "profiles";
+const ACTIVE_PROFILE_KEY: &str = "active_profile";
+/// The schema version every record in the database has been upgraded to.
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// The database as seen by the active profile.
+pub struct SledStorage {
+    db: Db,
+    profile_id: String,
+}
+
+impl SledStorage {
+    /// Opens the database at `path`, upgrading records stored by older versions of the app.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        let db = sled::open(path)?;
+        let report = migrate_database(&db)?;
+        if report.upgraded > 0 || report.unreadable > 0 {
+            eprintln!(
+                "Migrated database: {} records upgraded, {} unreadable",
+                report.upgraded, report.unreadable
+            );
+        }
+        Self::with_db(db)
+    }
+
+    /// Opens a database that is deleted when dropped.
+    #[cfg(test)]
+    pub(crate) fn temporary() -> Self {
+        Self::with_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    /// Opens `db` as the profile that was active last, creating the default profile on
+    /// first use.
+    fn with_db(db: Db) -> Result<Self, StorageError> {
+        let mut storage = Self { db, profile_id: DEFAULT_PROFILE_ID.to_string() };
+        profiles::ensure_default_profile(&storage)?;
+        let active = storage.db.get(ACTIVE_PROFILE_KEY)?.map(|id| String::from_utf8_lossy(&id).into_owned());
+        if let Some(id) = active {
+            if storage.profiles()?.contains_key(id.as_bytes())? {
+                storage.profile_id = id;
+            }
+        }
+        storage.ensure_index()?;
+        Ok(storage)
+    }
+
+    /// The key or tree name `base` stands for in the active profile.
+    fn scoped(&self, base: &str) -> String {
+        scoped_name(base, &self.profile_id)
+    }
+
+    fn sessions(&self) -> Result<Tree, sled::Error> {
+        self.db.open_tree(self.scoped(SESSIONS_TREE))
+    }
+
+    fn summaries(&self) -> Result<Tree, sled::Error> {
+        self.db.open_tree(self.scoped(SUMMARIES_TREE))
+    }
+
+    fn profiles(&self) -> Result<Tree, sled::Error> {
+        self.db.open_tree(PROFILES_TREE)
+    }
+
+    /// Rebuilds the active profile's summary index if it, together with the sessions it left
+    /// out as unreadable, does not cover the same number of sessions as the sessions tree, e.g.
+    /// for history saved before the index existed.
+    fn ensure_index(&self) -> Result<(), StorageError> {
+        let unindexed = stored_count(&self.db, &self.scoped(UNINDEXED_KEY))?;
+        if self.summaries()?.len() + unindexed != self.sessions()?.len() {
+            rebuild_index(&self.db, &self.profile_id)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the settings, sessions and checkpoint of one profile.
+    fn clear_profile_data(&self, profile_id: &str) -> Result<(), sled::Error> {
+        self.db.drop_tree(scoped_name(SESSIONS_TREE, profile_id))?;
+        self.db.drop_tree(scoped_name(SUMMARIES_TREE, profile_id))?;
+        self.db.remove(scoped_name(SETTINGS_KEY, profile_id))?;
+        self.db.remove(scoped_name(CHECKPOINT_KEY, profile_id))?;
+        self.db.remove(scoped_name(UNINDEXED_KEY, profile_id))?;
+        Ok(())
+    }
+}
+
+impl Storage for SledStorage {
+    fn profile_id(&self) -> &str {
+        &self.profile_id
+    }
+
+    fn set_active_profile(&mut self, profile_id: &str) -> Result<(), StorageError> {
+        self.db.insert(ACTIVE_PROFILE_KEY, profile_id.as_bytes())?;
+        self.profile_id = profile_id.to_string();
+        self.ensure_index()?;
+        Ok(())
+    }
+
+    fn list_profiles(&self) -> Result<Vec<Profile>, StorageError> {
+        let mut profiles: Vec<Profile> = Vec::new();
+        for item in self.profiles()?.iter() {
+            let (_, bytes) = item?;
+            match migrations::decode::<Profile>(&bytes) {
+                Ok((profile, _)) => profiles.push(profile),
+                Err(e) => eprintln!("Skipping profile due to deserialization error: {}", e),
+            }
+        }
+        profiles.sort_by_key(|p| p.created_at);
+        Ok(profiles)
+    }
+
+    fn load_profile(&self, profile_id: &str) -> Result<Option<Profile>, StorageError> {
+        Ok(self
+            .profiles()?
+            .get(profile_id.as_bytes())?
+            .and_then(|bytes| migrations::decode(&bytes).ok())
+            .map(|(profile, _)| profile))
+    }
+
+    fn save_profile(&self, profile: &Profile) -> Result<(), StorageError> {
+        self.profiles()?.insert(profile.id.as_bytes(), migrations::encode(profile)?)?;
+        Ok(())
+    }
+
+    fn remove_profile(&self, profile_id: &str) -> Result<(), StorageError> {
+        self.clear_profile_data(profile_id)?;
+        self.profiles()?.remove(profile_id.as_bytes())?;
+        Ok(())
+    }
+
+    fn save_settings(&self, settings: &UserSettings) -> Result<(), StorageError> {
+        let bytes = migrations::encode(settings)?;
+        self.db.insert(self.scoped(SETTINGS_KEY), bytes)?;
+        Ok(())
+    }
+
+    fn load_settings(&self) -> Result<UserSettings, StorageError> {
+        match self.db.get(self.scoped(SETTINGS_KEY))? {
+            Some(bytes) => Ok(migrations::decode(&bytes).map_err(StorageError::Decode)?.0),
+            None => Ok(UserSettings::default()),
+        }
+    }
+
+    fn save_session(&self, session: &GameSession) -> Result<(), StorageError> {
+        let bytes = migrations::encode(session)?;
+        let summaries = self.summaries()?;
+        if let Some(previous) = self.sessions()?.insert(session.id.as_bytes(), bytes)? {
+            // Re-saving a session may move its timestamp, and with it its index key
+            if let Ok((previous, _)) = migrations::decode::<GameSession>(&previous) {
+                summaries.remove(index_key(previous.timestamp, &previous.id))?;
+            }
+        }
+        index_session(&summaries, session)?;
+        Ok(())
+    }
+
+    fn load_session(&self, session_id: &str) -> Result<Option<GameSession>, StorageError> {
+        match self.sessions()?.get(session_id.as_bytes())? {
+            Some(bytes) => {
+                match migrations::decode::<GameSession>(&bytes) {
+                    Ok((session, _)) => Ok(Some(session)),
+                    Err(e) => {
+                        eprintln!("Failed to deserialize session {}: {}", session_id, e);
+                        Ok(None)
+                    }
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn session_exists(&self, session_id: &str) -> Result<bool, StorageError> {
+        Ok(self.sessions()?.contains_key(session_id.as_bytes())?)
+    }
+
+    fn delete_session(&self, session_id: &str) -> Result<bool, StorageError> {
+        let sessions = self.sessions()?;
+        let Some(bytes) = sessions.remove(session_id.as_bytes())? else {
+            return Ok(false);
+        };
+        match migrations::decode::<GameSession>(&bytes) {
+            Ok((session, _)) => {
+                self.summaries()?.remove(index_key(session.timestamp, &session.id))?;
+            }
+            // Without the timestamp the summary cannot be found by key, so start the index over
+            Err(_) => rebuild_index(&self.db, &self.profile_id)?,
+        }
+        Ok(true)
+    }
+
+    fn load_full_sessions(&self) -> Result<Vec<GameSession>, StorageError> {
+        let mut sessions: Vec<GameSession> = Vec::new();
+        for item in self.sessions()?.iter() {
+            let (_, bytes) = item?;
+            match migrations::decode::<GameSession>(&bytes) {
+                Ok((session, _)) => sessions.push(session),
+                Err(e) => {
+                    eprintln!("Skipping session due to deserialization error: {}", e);
+                }
+            }
+        }
+        // Sort by timestamp, newest first
+        sessions.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        Ok(sessions)
+    }
+
+    /// The date range is served by the index order; the other filters only decode the
+    /// summaries inside it.
+    fn query_history(&self, query: &HistoryQuery) -> Result<HistoryQueryResult, StorageError> {
+        let range = self.summaries()?.range(index_range(query));
+        let items: Box<dyn Iterator<Item = sled::Result<(sled::IVec, sled::IVec)>>> = match query.order {
+            SortOrder::NewestFirst => Box::new(range.rev()),
+            SortOrder::OldestFirst => Box::new(range),
+        };
+        let limit = query.limit.unwrap_or(usize::MAX);
+        let mut summaries = Vec::new();
+        let mut total = 0;
+        for item in items {
+            let (_, bytes) = item?;
+            let summary = match migrations::decode::<GameSessionSummary>(&bytes) {
+                Ok((summary, _)) => summary,
+                Err(e) => {
+                    eprintln!("Skipping unreadable session summary: {}", e);
+                    continue;
+                }
+            };
+            if !matches(query, &summary) {
+                continue;
+            }
+            if total >= query.offset && summaries.len() < limit {
+                summaries.push(summary);
+            }
+            total += 1;
+        }
+        Ok(HistoryQueryResult { summaries, total })
+    }
+
+    fn save_checkpoint(&self, checkpoint: &SessionCheckpoint) -> Result<(), StorageError> {
+        let bytes = migrations::encode(checkpoint)?;
+        self.db.insert(self.scoped(CHECKPOINT_KEY), bytes)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn load_checkpoint(&self) -> Result<Option<SessionCheckpoint>, StorageError> {
+        match self.db.get(self.scoped(CHECKPOINT_KEY))? {
+            Some(bytes) => match migrations::decode(&bytes) {
+                Ok((checkpoint, _)) => Ok(Some(checkpoint)),
+                Err(e) => {
+                    eprintln!("Ignoring unreadable session checkpoint: {}", e);
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn clear_checkpoint(&self) -> Result<(), StorageError> {
+        self.db.remove(self.scoped(CHECKPOINT_KEY))?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn save_stimulus_set(&self, set: &CustomStimulusSet) -> Result<(), StorageError> {
+        let tree = self.db.open_tree(STIMULUS_SETS_TREE)?;
+        let bytes = migrations::encode(set)?;
+        tree.insert(set.id.as_bytes(), bytes)?;
+        Ok(())
+    }
+
+    fn load_stimulus_set(&self, id: &str) -> Result<Option<CustomStimulusSet>, StorageError> {
+        let tree = self.db.open_tree(STIMULUS_SETS_TREE)?;
+        match tree.get(id.as_bytes())? {
+            Some(bytes) => match migrations::decode::<CustomStimulusSet>(&bytes) {
+                Ok((set, _)) => Ok(Some(set)),
+                Err(e) => {
+                    eprintln!("Failed to deserialize stimulus set {}: {}", id, e);
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn load_all_stimulus_sets(&self) -> Result<Vec<CustomStimulusSet>, StorageError> {
+        let tree = self.db.open_tree(STIMULUS_SETS_TREE)?;
+        let mut sets = Vec::new();
+        for item in tree.iter() {
+            let (_, bytes) = item?;
+            match migrations::decode::<CustomStimulusSet>(&bytes) {
+                Ok((set, _)) => sets.push(set),
+                Err(e) => eprintln!("Skipping stimulus set due to deserialization error: {}", e),
+            }
+        }
+        Ok(sets)
+    }
+
+    fn delete_stimulus_set(&self, id: &str) -> Result<bool, StorageError> {
+        let tree = self.db.open_tree(STIMULUS_SETS_TREE)?;
+        Ok(tree.remove(id.as_bytes())?.is_some())
+    }
+
+    fn clear_all_data(&mut self) -> Result<(), StorageError> {
+        for profile_id in stored_profile_ids(&self.db)? {
+            self.clear_profile_data(&profile_id)?;
+        }
+        self.db.drop_tree(PROFILES_TREE)?;
+        self.db.remove(ACTIVE_PROFILE_KEY)?;
+        self.db.drop_tree(STIMULUS_SETS_TREE)?;
+        *self = Self::with_db(self.db.clone())?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn snapshot(&self, path: &Path) -> Result<(), StorageError> {
+        super::ensure_vacant(path)?;
+        let copy = sled::open(path)?;
+        for name in self.db.tree_names() {
+            let (from, to) = (self.db.open_tree(&name)?, copy.open_tree(&name)?);
+            for item in from.iter() {
+                let (key, value) = item?;
+                to.insert(key, value)?;
+            }
+        }
+        copy.flush()?;
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> Result<(), StorageError> {
+        // sled checksums what it reads from disk, so reading every entry once finds damage
+        for name in self.db.tree_names() {
+            for item in self.db.open_tree(&name)?.iter() {
+                item?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn scoped_name(base: &str, profile_id: &str) -> String {
+    if profile_id == DEFAULT_PROFILE_ID {
+        base.to_string()
+    } else {
+        format!("{}:{}", base, profile_id)
+    }
+}
+
+/// IDs of every stored profile, read without decoding the profiles themselves. The default
+/// profile is always included, even in a database from before profiles existed.
+fn stored_profile_ids(db: &Db) -> Result<BTreeSet<String>, sled::Error> {
+    let mut ids = BTreeSet::from([DEFAULT_PROFILE_ID.to_string()]);
+    for key in db.open_tree(PROFILES_TREE)?.iter().keys() {
+        ids.insert(String::from_utf8_lossy(&key?).into_owned());
+    }
+    Ok(ids)
+}
+
+/// Reads every record that can still be read from a damaged database. The data of a profile
+/// whose own record is lost is recovered too.
+pub fn salvage(path: &Path) -> Result<Salvage, StorageError> {
+    let db = sled::open(path)?;
+    let mut unreadable = 0;
+    let profiles: Vec<Profile> = salvage_tree(&db, PROFILES_TREE, &mut unreadable);
+    let mut profile_ids = stored_profile_ids(&db).unwrap_or_default();
+    profile_ids.extend(profiles.iter().map(|p| p.id.clone()));
+    profile_ids.insert(DEFAULT_PROFILE_ID.to_string());
+
+    let mut settings = Vec::new();
+    let mut sessions = Vec::new();
+    for id in profile_ids {
+        match db.get(scoped_name(SETTINGS_KEY, &id)).map(|bytes| bytes.map(|b| migrations::decode(&b))) {
+            Ok(Some(Ok((record, _)))) => settings.push((id.clone(), record)),
+            Ok(None) => {}
+            Ok(Some(Err(_))) | Err(_) => unreadable += 1,
+        }
+        let recovered: Vec<GameSession> = salvage_tree(&db, &scoped_name(SESSIONS_TREE, &id), &mut unreadable);
+        sessions.extend(recovered.into_iter().map(|session| (id.clone(), session)));
+    }
+    let stimulus_sets = salvage_tree(&db, STIMULUS_SETS_TREE, &mut unreadable);
+    Ok(Salvage { profiles, settings, sessions, stimulus_sets, unreadable })
+}
+
+/// Decodes the records of the tree `name` up to the first entry sled cannot read, counting that
+/// entry, and every record that does not decode, as unreadable. A tree that cannot be opened
+/// counts as one unreadable record.
+fn salvage_tree<T: Versioned>(db: &Db, name: &str, unreadable: &mut usize) -> Vec<T> {
+    let mut records = Vec::new();
+    let Ok(tree) = db.open_tree(name) else {
+        *unreadable += 1;
+        return records;
+    };
+    for item in tree.iter() {
+        match item.map(|(_, bytes)| migrations::decode(&bytes)) {
+            Ok(Ok((record, _))) => records.push(record),
+            Ok(Err(_)) => *unreadable += 1,
+            Err(_) => {
+                *unreadable += 1;
+                break;
+            }
+        }
+    }
+    records
+}
+
+// --- Summary Index ---
+
+/// Index key of a session: its timestamp as big-endian nanoseconds with the sign bit flipped,
+/// so keys sort chronologically, followed by the session ID to keep equal timestamps apart.
+fn index_key(timestamp: DateTime<Utc>, session_id: &str) -> Vec<u8> {
+    let mut key = timestamp_prefix(timestamp).to_vec();
+    key.extend_from_slice(session_id.as_bytes());
+    key
+}
+
+fn timestamp_prefix(timestamp: DateTime<Utc>) -> [u8; 8] {
+    let nanos = timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX);
+    ((nanos as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+/// Index keys of the sessions inside a query's date range.
+fn index_range(query: &HistoryQuery) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let start = match query.from {
+        Some(from) => Bound::Included(timestamp_prefix(from).to_vec()),
+        None => Bound::Unbounded,
+    };
+    // Every key of a session at `to` starts with its prefix, so end just past them
+    let end = match query.to.and_then(|to| to.checked_add_signed(chrono::Duration::nanoseconds(1))) {
+        Some(after) => Bound::Excluded(timestamp_prefix(after).to_vec()),
+        None => Bound::Unbounded,
+    };
+    (start, end)
+}
+
+/// Whether a summary passes every filter of a query except the date range.
+fn matches(query: &HistoryQuery, summary: &GameSessionSummary) -> bool {
+    query.n_level.is_none_or(|n| summary.settings.n_level == n)
+        && query.stimulus_set.as_ref().is_none_or(|set| &summary.settings.auditory_stimulus_set == set)
+        && query.tag.as_ref().is_none_or(|tag| summary.tags.contains(tag))
+}
+
+fn index_session(summaries: &Tree, session: &GameSession) -> Result<(), StorageError> {
+    let summary = GameSessionSummary::from(session);
+    summaries.insert(index_key(session.timestamp, &session.id), migrations::encode(&summary)?)?;
+    Ok(())
+}
+
+/// Replaces the index of a profile with one built from its sessions, and records how many of
+/// them could not be read.
+fn rebuild_index(db: &Db, profile_id: &str) -> Result<(), StorageError> {
+    let summaries = db.open_tree(scoped_name(SUMMARIES_TREE, profile_id))?;
+    summaries.clear()?;
+    let mut unindexed: u64 = 0;
+    for item in db.open_tree(scoped_name(SESSIONS_TREE, profile_id))?.iter() {
+        let (_, bytes) = item?;
+        match migrations::decode::<GameSession>(&bytes) {
+            Ok((session, _)) => index_session(&summaries, &session)?,
+            Err(_) => unindexed += 1,
+        }
+    }
+    db.insert(scoped_name(UNINDEXED_KEY, profile_id), &unindexed.to_le_bytes())?;
+    Ok(())
+}
+
+/// A count stored under `key`; zero when there is none.
+fn stored_count(db: &Db, key: &str) -> Result<usize, sled::Error> {
+    Ok(db
+        .get(key)?
+        .and_then(|bytes| Some(u64::from_le_bytes(bytes.as_ref().try_into().ok()?)))
+        .unwrap_or(0) as usize)
+}
+
+// --- Migration ---
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Records rewritten at the current schema version.
+    pub upgraded: usize,
+    /// Records that could not be decoded at any version. They are left untouched.
+    pub unreadable: usize,
+}
+
+/// Upgrades every stored record to the current schema version. Runs at startup; once a pass
+/// has upgraded everything, later startups skip the scan.
+pub fn migrate_database(db: &Db) -> Result<MigrationReport, StorageError> {
+    let mut report = MigrationReport::default();
+    if stored_schema_version(db)? == Some(CURRENT_VERSION) {
+        return Ok(report);
+    }
+
+    migrate_tree::<Profile>(&db.open_tree(PROFILES_TREE)?, &mut report)?;
+    for profile_id in stored_profile_ids(db)? {
+        let checkpoint_key = scoped_name(CHECKPOINT_KEY, &profile_id);
+        // A checkpoint from before checkpoints existed cannot be resumed, so it is dropped
+        if let Some(bytes) = db.get(&checkpoint_key)? {
+            if migrations::decode::<SessionCheckpoint>(&bytes).is_err() {
+                db.remove(&checkpoint_key)?;
+            }
+        }
+        migrate_record::<SessionCheckpoint>(db, checkpoint_key.as_bytes(), &mut report)?;
+        let settings_key = scoped_name(SETTINGS_KEY, &profile_id);
+        migrate_record::<UserSettings>(db, settings_key.as_bytes(), &mut report)?;
+        let sessions = db.open_tree(scoped_name(SESSIONS_TREE, &profile_id))?;
+        migrate_tree::<GameSession>(&sessions, &mut report)?;
+        // Summaries are only readable at the current version, so they are rebuilt rather
+        // than upgraded
+        rebuild_index(db, &profile_id)?;
+    }
+    migrate_tree::<CustomStimulusSet>(&db.open_tree(STIMULUS_SETS_TREE)?, &mut report)?;
+
+    // Leave the version unset while records are unreadable, so the next startup retries them
+    if report.unreadable == 0 {
+        db.insert(SCHEMA_VERSION_KEY, &CURRENT_VERSION.to_le_bytes())?;
+    }
+    db.flush()?;
+    Ok(report)
+}
+
+fn stored_schema_version(db: &Db) -> Result<Option<u32>, sled::Error> {
+    Ok(db
+        .get(SCHEMA_VERSION_KEY)?
+        .and_then(|bytes| Some(u32::from_le_bytes(bytes.as_ref().try_into().ok()?))))
+}
+
+fn migrate_tree<T: Versioned>(tree: &Tree, report: &mut MigrationReport) -> Result<(), StorageError> {
+    for item in tree.iter() {
+        let (key, _) = item?;
+        migrate_record::<T>(tree, &key, report)?;
+    }
+    Ok(())
+}
+
+/// Rewrites one record at the current version if it was stored at an older one. `Db`
+/// dereferences to its default tree, so this also covers top-level keys.
+fn migrate_record<T: Versioned>(
+    tree: &Tree,
+    key: &[u8],
+    report: &mut MigrationReport,
+) -> Result<(), StorageError> {
+    let Some(bytes) = tree.get(key)? else {
+        return Ok(());
+    };
+    match migrations::decode::<T>(&bytes) {
+        Ok((_, CURRENT_VERSION)) => {}
+        Ok((value, _)) => {
+            tree.insert(key, migrations::encode(&value)?)?;
+            report.upgraded += 1;
+        }
+        Err(e) => {
+            eprintln!("Cannot migrate record {}: {}", String::from_utf8_lossy(key), e);
+            report.unreadable += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance::{self, storage_conformance_tests};
+    use std::collections::BTreeMap;
+
+    storage_conformance_tests!(SledStorage::temporary());
+
+    #[test]
+    fn test_salvage_skips_unreadable_records() {
+        let path = conformance::temp_path("sled-salvage");
+        let storage = SledStorage::open(&path).unwrap();
+        conformance::save_daily_sessions(&storage, 3);
+        storage.sessions().unwrap().insert("session_1", &b"garbled"[..]).unwrap();
+        drop(storage);
+
+        let salvage = salvage(&path).unwrap();
+        let ids: Vec<&str> = salvage.sessions.iter().map(|(_, s)| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["session_0", "session_2"]);
+        assert_eq!(salvage.unreadable, 1);
+        assert_eq!(salvage.profiles.len(), 1);
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = conformance::temp_path("sled-snapshot");
+        let mut storage = SledStorage::temporary();
+        conformance::snapshot_round_trip(&mut storage, &path, |path| Box::new(SledStorage::open(path).unwrap()));
+        let _ = std::fs::remove_dir_all(&path);
+    }
+
+    #[test]
+    fn test_migrate_database_upgrades_legacy_records() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        // Settings as the first release stored them: n_level, speed_ms and session_length
+        db.insert(SETTINGS_KEY, bincode::serialize(&(3usize, 1500u64, 25usize)).unwrap()).unwrap();
+        let sessions = db.open_tree(SESSIONS_TREE).unwrap();
+        sessions.insert("session_garbled", &b"garbled"[..]).unwrap();
+        let session = GameSession::new(UserSettings::default(), 1, vec![], BTreeMap::new());
+        sessions.insert(session.id.as_bytes(), migrations::encode(&session).unwrap()).unwrap();
+
+        let report = migrate_database(&db).unwrap();
+        assert_eq!(report, MigrationReport { upgraded: 1, unreadable: 1 });
+        assert_eq!(stored_schema_version(&db).unwrap(), None);
+        let stored = db.get(SETTINGS_KEY).unwrap().unwrap();
+        let (settings, version) = migrations::decode::<UserSettings>(&stored).unwrap();
+        assert_eq!((settings.n_level, settings.speed_ms, version), (3, 1500, CURRENT_VERSION));
+        let storage = SledStorage::with_db(db.clone()).unwrap();
+        assert_eq!(storage.load_all_sessions().unwrap().len(), 1);
+
+        // Unreadable records are kept and retried until they are gone
+        assert_eq!(migrate_database(&db).unwrap(), MigrationReport { upgraded: 0, unreadable: 1 });
+        sessions.remove("session_garbled").unwrap();
+        assert_eq!(migrate_database(&db).unwrap(), MigrationReport::default());
+        assert_eq!(stored_schema_version(&db).unwrap(), Some(CURRENT_VERSION));
+    }
+
+    #[test]
+    fn test_default_profile_keeps_unscoped_data() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        // Settings saved before profiles existed
+        let legacy = UserSettings { n_level: 4, ..Default::default() };
+        db.insert(SETTINGS_KEY, migrations::encode(&legacy).unwrap()).unwrap();
+
+        let mut storage = SledStorage::with_db(db.clone()).unwrap();
+        assert_eq!(storage.profile_id(), DEFAULT_PROFILE_ID);
+        assert_eq!(storage.load_settings().unwrap().n_level, 4);
+        let profiles = storage.list_profiles().unwrap();
+        assert_eq!(profiles.len(), 1);
+
+        // Other profiles are scoped, and the active one is remembered across restarts
+        let ada = profiles::create_profile(&storage, "Ada").unwrap();
+        profiles::switch_profile(&mut storage, &ada.id).unwrap();
+        storage.save_settings(&UserSettings { n_level: 5, ..Default::default() }).unwrap();
+        assert!(db.contains_key(scoped_name(SETTINGS_KEY, &ada.id)).unwrap());
+        let storage = SledStorage::with_db(db).unwrap();
+        assert_eq!(storage.profile_id(), ada.id);
+        assert_eq!(storage.load_settings().unwrap().n_level, 5);
+    }
+
+    #[test]
+    fn test_queries_do_not_read_event_histories() {
+        let storage = SledStorage::temporary();
+        conformance::save_daily_sessions(&storage, 3);
+        // Break every full session; listing the history must not notice
+        let sessions = storage.sessions().unwrap();
+        for key in sessions.iter().keys() {
+            sessions.insert(key.unwrap(), &b"garbled"[..]).unwrap();
+        }
+        assert_eq!(storage.load_all_sessions().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_index_follows_deletes_and_is_rebuilt() {
+        let storage = SledStorage::temporary();
+        let sessions = conformance::save_daily_sessions(&storage, 4);
+        assert!(storage.delete_session(&sessions[1].id).unwrap());
+        assert_eq!(storage.query_history(&HistoryQuery::default()).unwrap().total, 3);
+
+        // An index that lost track of the sessions is rebuilt from them
+        storage.summaries().unwrap().clear().unwrap();
+        storage.ensure_index().unwrap();
+        let result = storage.query_history(&HistoryQuery::default()).unwrap();
+        assert_eq!(conformance::ids(&result), vec!["session_3", "session_2", "session_0"]);
+    }
+
+    #[test]
+    fn test_unreadable_sessions_do_not_rebuild_the_index_again() {
+        let mut storage = SledStorage::temporary();
+        conformance::save_daily_sessions(&storage, 3);
+        storage.sessions().unwrap().insert("session_garbled", &b"garbled"[..]).unwrap();
+        storage.ensure_index().unwrap();
+        assert_eq!(storage.query_history(&HistoryQuery::default()).unwrap().total, 3);
+
+        // A tag added to the index alone is lost if it is rebuilt from the sessions
+        let summaries = storage.summaries().unwrap();
+        let (key, bytes) = summaries.first().unwrap().unwrap();
+        let (mut summary, _) = migrations::decode::<GameSessionSummary>(&bytes).unwrap();
+        summary.tags.insert("index-only".to_string());
+        summaries.insert(key, migrations::encode(&summary).unwrap()).unwrap();
+        let profile_id = storage.profile_id().to_string();
+        storage.set_active_profile(&profile_id).unwrap();
+        let query = HistoryQuery { tag: Some("index-only".to_string()), ..Default::default() };
+        assert_eq!(storage.query_history(&query).unwrap().total, 1);
+    }
+
+    #[test]
+    fn test_unreadable_checkpoint_is_ignored() {
+        let storage = SledStorage::temporary();
+        storage.db.insert(CHECKPOINT_KEY, b"not a checkpoint".to_vec()).unwrap();
+        assert!(storage.load_checkpoint().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unreadable_settings_are_an_error() {
+        let storage = SledStorage::temporary();
+        storage.db.insert(SETTINGS_KEY, b"not settings".to_vec()).unwrap();
+        assert!(matches!(storage.load_settings(), Err(StorageError::Decode(_))));
+    }
+}
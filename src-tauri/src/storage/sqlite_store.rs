@@ -0,0 +1,648 @@
+//! The SQLite backend. Settings, sessions, checkpoints and custom auditory sets are stored as
+//! versioned envelopes (see [`migrations`]), with the columns history queries filter and sort
+//! on kept next to them, so listings never decode an event history.
+
+use crate::game::SessionCheckpoint;
+use crate::history_query::{HistoryQuery, HistoryQueryResult, SortOrder};
+use crate::migrations::{self, Versioned, CURRENT_VERSION};
+use crate::persistence::{GameSession, GameSessionSummary, UserSettings};
+use crate::profiles::{self, Profile, DEFAULT_PROFILE_ID};
+use crate::sequence_generator::AuditoryStimulusSet;
+use crate::stimulus_sets::CustomStimulusSet;
+use super::{Salvage, Storage, StorageError};
+use chrono::{DateTime, Utc};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Row};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS profiles (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS settings (
+        profile_id TEXT PRIMARY KEY REFERENCES profiles (id) ON DELETE CASCADE,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS checkpoints (
+        profile_id TEXT PRIMARY KEY REFERENCES profiles (id) ON DELETE CASCADE,
+        record BLOB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        profile_id TEXT NOT NULL REFERENCES profiles (id) ON DELETE CASCADE,
+        id TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        n_level INTEGER NOT NULL,
+        stimulus_set TEXT NOT NULL,
+        summary BLOB NOT NULL,
+        record BLOB NOT NULL,
+        PRIMARY KEY (profile_id, id)
+    );
+    CREATE INDEX IF NOT EXISTS sessions_by_timestamp ON sessions (profile_id, timestamp, id);
+    CREATE TABLE IF NOT EXISTS session_tags (
+        profile_id TEXT NOT NULL,
+        session_id TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (profile_id, session_id, tag),
+        FOREIGN KEY (profile_id, session_id) REFERENCES sessions (profile_id, id) ON DELETE CASCADE
+    );
+    CREATE TABLE IF NOT EXISTS stimulus_sets (
+        id TEXT PRIMARY KEY,
+        record BLOB NOT NULL
+    );
+";
+
+const ACTIVE_PROFILE_KEY: &str = "active_profile";
+
+/// The database as seen by the active profile.
+pub struct SqliteStorage {
+    conn: Connection,
+    profile_id: String,
+}
+
+impl SqliteStorage {
+    /// Opens or creates the database at `path`.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Opens a database that only lives in memory.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Self::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    /// Sets up the schema and opens the connection as the profile that was active last,
+    /// creating the default profile on first use.
+    fn with_connection(conn: Connection) -> Result<Self, StorageError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)?;
+        let mut storage = Self { conn, profile_id: DEFAULT_PROFILE_ID.to_string() };
+        storage.upgrade_records()?;
+        profiles::ensure_default_profile(&storage)?;
+        let active: Option<String> = storage
+            .conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [ACTIVE_PROFILE_KEY], |row| row.get(0))
+            .optional()?;
+        if let Some(id) = active {
+            if storage.load_profile(&id)?.is_some() {
+                storage.profile_id = id;
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Rewrites records stored by an older version of the app at the current schema version,
+    /// and recomputes the session summaries, which are only readable at the current version.
+    /// The version the records were last upgraded to is kept in SQLite's `user_version`. It is
+    /// left as it was while records are unreadable, so the next startup retries them.
+    fn upgrade_records(&self) -> Result<(), StorageError> {
+        let version: u32 = self.conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version == CURRENT_VERSION {
+            return Ok(());
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        let mut unreadable = upgrade_table::<UserSettings>(&tx, "settings", "profile_id")?
+            + upgrade_table::<SessionCheckpoint>(&tx, "checkpoints", "profile_id")?
+            + upgrade_table::<CustomStimulusSet>(&tx, "stimulus_sets", "id")?;
+        let sessions: Vec<(String, String, Vec<u8>)> = tx
+            .prepare("SELECT profile_id, id, record FROM sessions")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<Result<_, _>>()?;
+        for (profile_id, id, record) in sessions {
+            match migrations::decode::<GameSession>(&record) {
+                Ok((session, _)) => {
+                    tx.execute(
+                        "UPDATE sessions SET summary = ?1, record = ?2 WHERE profile_id = ?3 AND id = ?4",
+                        params![encode_summary(&session)?, migrations::encode(&session)?, profile_id, id],
+                    )?;
+                }
+                Err(e) => {
+                    eprintln!("Cannot migrate session {}: {}", id, e);
+                    unreadable += 1;
+                }
+            }
+        }
+        if unreadable == 0 {
+            tx.pragma_update(None, "user_version", CURRENT_VERSION)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_record<T: Versioned>(&self, sql: &str, key: &str) -> Result<Option<T>, StorageError> {
+        let record: Option<Vec<u8>> = self.conn.query_row(sql, [key], |row| row.get(0)).optional()?;
+        Ok(record.and_then(|bytes| match migrations::decode(&bytes) {
+            Ok((value, _)) => Some(value),
+            Err(e) => {
+                eprintln!("Ignoring unreadable record {}: {}", key, e);
+                None
+            }
+        }))
+    }
+
+    /// Decodes the records returned by `sql`, skipping unreadable ones.
+    fn load_records<T: Versioned>(&self, sql: &str, params: impl rusqlite::Params) -> Result<Vec<T>, StorageError> {
+        let records: Vec<Vec<u8>> = self
+            .conn
+            .prepare(sql)?
+            .query_map(params, |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(records
+            .iter()
+            .filter_map(|bytes| match migrations::decode(bytes) {
+                Ok((value, _)) => Some(value),
+                Err(e) => {
+                    eprintln!("Skipping unreadable record: {}", e);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn profile_id(&self) -> &str {
+        &self.profile_id
+    }
+
+    fn set_active_profile(&mut self, profile_id: &str) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)
+             ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            [ACTIVE_PROFILE_KEY, profile_id],
+        )?;
+        self.profile_id = profile_id.to_string();
+        Ok(())
+    }
+
+    fn list_profiles(&self) -> Result<Vec<Profile>, StorageError> {
+        let profiles = self
+            .conn
+            .prepare("SELECT id, name, created_at FROM profiles ORDER BY created_at, id")?
+            .query_map([], profile_from_row)?
+            .collect::<Result<_, _>>()?;
+        Ok(profiles)
+    }
+
+    fn load_profile(&self, profile_id: &str) -> Result<Option<Profile>, StorageError> {
+        Ok(self
+            .conn
+            .query_row("SELECT id, name, created_at FROM profiles WHERE id = ?1", [profile_id], profile_from_row)
+            .optional()?)
+    }
+
+    fn save_profile(&self, profile: &Profile) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT INTO profiles (id, name, created_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET name = excluded.name, created_at = excluded.created_at",
+            params![profile.id, profile.name, nanos(profile.created_at)],
+        )?;
+        Ok(())
+    }
+
+    fn remove_profile(&self, profile_id: &str) -> Result<(), StorageError> {
+        // Its settings, sessions and checkpoint go with it
+        self.conn.execute("DELETE FROM profiles WHERE id = ?1", [profile_id])?;
+        Ok(())
+    }
+
+    fn save_settings(&self, settings: &UserSettings) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT INTO settings (profile_id, record) VALUES (?1, ?2)
+             ON CONFLICT (profile_id) DO UPDATE SET record = excluded.record",
+            params![self.profile_id, migrations::encode(settings)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_settings(&self) -> Result<UserSettings, StorageError> {
+        let record: Option<Vec<u8>> = self
+            .conn
+            .query_row("SELECT record FROM settings WHERE profile_id = ?1", [&self.profile_id], |row| row.get(0))
+            .optional()?;
+        match record {
+            Some(bytes) => Ok(migrations::decode(&bytes).map_err(StorageError::Decode)?.0),
+            None => Ok(UserSettings::default()),
+        }
+    }
+
+    fn save_session(&self, session: &GameSession) -> Result<(), StorageError> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO sessions (profile_id, id, timestamp, n_level, stimulus_set, summary, record)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (profile_id, id) DO UPDATE SET
+                 timestamp = excluded.timestamp,
+                 n_level = excluded.n_level,
+                 stimulus_set = excluded.stimulus_set,
+                 summary = excluded.summary,
+                 record = excluded.record",
+            params![
+                self.profile_id,
+                session.id,
+                nanos(session.timestamp),
+                session.settings.n_level as i64,
+                stimulus_set_key(&session.settings.auditory_stimulus_set),
+                encode_summary(session)?,
+                migrations::encode(session)?,
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM session_tags WHERE profile_id = ?1 AND session_id = ?2",
+            [&self.profile_id, &session.id],
+        )?;
+        for tag in &session.tags {
+            tx.execute(
+                "INSERT INTO session_tags (profile_id, session_id, tag) VALUES (?1, ?2, ?3)",
+                [&self.profile_id, &session.id, tag],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_session(&self, session_id: &str) -> Result<Option<GameSession>, StorageError> {
+        let record: Option<Vec<u8>> = self
+            .conn
+            .query_row(
+                "SELECT record FROM sessions WHERE profile_id = ?1 AND id = ?2",
+                [&self.profile_id, session_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match record.map(|bytes| migrations::decode::<GameSession>(&bytes)) {
+            Some(Ok((session, _))) => Ok(Some(session)),
+            Some(Err(e)) => {
+                eprintln!("Failed to deserialize session {}: {}", session_id, e);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn session_exists(&self, session_id: &str) -> Result<bool, StorageError> {
+        Ok(self.conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sessions WHERE profile_id = ?1 AND id = ?2)",
+            [&self.profile_id, session_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn delete_session(&self, session_id: &str) -> Result<bool, StorageError> {
+        let deleted = self.conn.execute(
+            "DELETE FROM sessions WHERE profile_id = ?1 AND id = ?2",
+            [&self.profile_id, session_id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn load_full_sessions(&self) -> Result<Vec<GameSession>, StorageError> {
+        self.load_records(
+            "SELECT record FROM sessions WHERE profile_id = ?1 ORDER BY timestamp DESC, id DESC",
+            [&self.profile_id],
+        )
+    }
+
+    /// Every filter is answered by SQLite from the indexed columns and the tags table.
+    fn query_history(&self, query: &HistoryQuery) -> Result<HistoryQueryResult, StorageError> {
+        let mut conditions = vec!["profile_id = ?"];
+        let mut values = vec![Value::Text(self.profile_id.clone())];
+        if let Some(from) = query.from {
+            conditions.push("timestamp >= ?");
+            values.push(Value::Integer(nanos(from)));
+        }
+        if let Some(to) = query.to {
+            conditions.push("timestamp <= ?");
+            values.push(Value::Integer(nanos(to)));
+        }
+        if let Some(n_level) = query.n_level {
+            conditions.push("n_level = ?");
+            values.push(Value::Integer(n_level as i64));
+        }
+        if let Some(set) = &query.stimulus_set {
+            conditions.push("stimulus_set = ?");
+            values.push(Value::Text(stimulus_set_key(set)));
+        }
+        if let Some(tag) = &query.tag {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM session_tags t
+                         WHERE t.profile_id = sessions.profile_id AND t.session_id = sessions.id AND t.tag = ?)",
+            );
+            values.push(Value::Text(tag.clone()));
+        }
+        let filter = conditions.join(" AND ");
+
+        let total: i64 = self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM sessions WHERE {}", filter),
+            params_from_iter(&values),
+            |row| row.get(0),
+        )?;
+        let order = match query.order {
+            SortOrder::NewestFirst => "DESC",
+            SortOrder::OldestFirst => "ASC",
+        };
+        values.push(Value::Integer(query.limit.map_or(-1, |limit| limit as i64)));
+        values.push(Value::Integer(query.offset as i64));
+        let summaries = self.load_records(
+            &format!(
+                "SELECT summary FROM sessions WHERE {} ORDER BY timestamp {}, id {} LIMIT ? OFFSET ?",
+                filter, order, order
+            ),
+            params_from_iter(&values),
+        )?;
+        Ok(HistoryQueryResult { summaries, total: total as usize })
+    }
+
+    fn load_all_tags(&self) -> Result<BTreeSet<String>, StorageError> {
+        let tags = self
+            .conn
+            .prepare("SELECT DISTINCT tag FROM session_tags WHERE profile_id = ?1")?
+            .query_map([&self.profile_id], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(tags)
+    }
+
+    fn save_checkpoint(&self, checkpoint: &SessionCheckpoint) -> Result<(), StorageError> {
+        // Committed on return, so the checkpoint is already on disk
+        self.conn.execute(
+            "INSERT INTO checkpoints (profile_id, record) VALUES (?1, ?2)
+             ON CONFLICT (profile_id) DO UPDATE SET record = excluded.record",
+            params![self.profile_id, migrations::encode(checkpoint)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_checkpoint(&self) -> Result<Option<SessionCheckpoint>, StorageError> {
+        self.load_record("SELECT record FROM checkpoints WHERE profile_id = ?1", &self.profile_id)
+    }
+
+    fn clear_checkpoint(&self) -> Result<(), StorageError> {
+        self.conn.execute("DELETE FROM checkpoints WHERE profile_id = ?1", [&self.profile_id])?;
+        Ok(())
+    }
+
+    fn save_stimulus_set(&self, set: &CustomStimulusSet) -> Result<(), StorageError> {
+        self.conn.execute(
+            "INSERT INTO stimulus_sets (id, record) VALUES (?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET record = excluded.record",
+            params![set.id, migrations::encode(set)?],
+        )?;
+        Ok(())
+    }
+
+    fn load_stimulus_set(&self, id: &str) -> Result<Option<CustomStimulusSet>, StorageError> {
+        self.load_record("SELECT record FROM stimulus_sets WHERE id = ?1", id)
+    }
+
+    fn load_all_stimulus_sets(&self) -> Result<Vec<CustomStimulusSet>, StorageError> {
+        self.load_records("SELECT record FROM stimulus_sets ORDER BY id", [])
+    }
+
+    fn delete_stimulus_set(&self, id: &str) -> Result<bool, StorageError> {
+        Ok(self.conn.execute("DELETE FROM stimulus_sets WHERE id = ?1", [id])? > 0)
+    }
+
+    fn clear_all_data(&mut self) -> Result<(), StorageError> {
+        self.conn.execute_batch(
+            "BEGIN;
+             DELETE FROM profiles;
+             DELETE FROM stimulus_sets;
+             DELETE FROM meta;
+             COMMIT;",
+        )?;
+        self.profile_id = DEFAULT_PROFILE_ID.to_string();
+        profiles::ensure_default_profile(self)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StorageError> {
+        // Every statement is committed as it runs
+        Ok(())
+    }
+
+    fn snapshot(&self, path: &Path) -> Result<(), StorageError> {
+        super::ensure_vacant(path)?;
+        self.conn.execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    fn check_integrity(&self) -> Result<(), StorageError> {
+        let problems: Vec<String> =
+            self.conn.prepare("PRAGMA quick_check")?.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+        if problems != ["ok"] {
+            return Err(StorageError::Corrupt(problems.join("; ")));
+        }
+        Ok(())
+    }
+}
+
+/// Reads every record that can still be read from a damaged database, opened read-only.
+pub fn salvage(path: &Path) -> Result<Salvage, StorageError> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut unreadable = 0;
+    let profiles = salvage_rows(&conn, "SELECT id, name, created_at FROM profiles", profile_from_row, &mut unreadable);
+    let settings = salvage_records(&conn, "SELECT profile_id, record FROM settings", &mut unreadable);
+    let sessions = salvage_records(&conn, "SELECT profile_id, record FROM sessions", &mut unreadable);
+    let stimulus_sets = salvage_records(&conn, "SELECT id, record FROM stimulus_sets", &mut unreadable)
+        .into_iter()
+        .map(|(_, set)| set)
+        .collect();
+    Ok(Salvage { profiles, settings, sessions, stimulus_sets, unreadable })
+}
+
+/// Reads the rows of `sql` up to the first one SQLite cannot read, counting that one, and
+/// every row `read` fails on, as unreadable.
+fn salvage_rows<T>(
+    conn: &Connection,
+    sql: &str,
+    mut read: impl FnMut(&Row) -> rusqlite::Result<T>,
+    unreadable: &mut usize,
+) -> Vec<T> {
+    let mut values = Vec::new();
+    let mut statement = match conn.prepare(sql) {
+        Ok(statement) => statement,
+        Err(_) => {
+            *unreadable += 1;
+            return values;
+        }
+    };
+    let Ok(mut rows) = statement.query([]) else {
+        *unreadable += 1;
+        return values;
+    };
+    loop {
+        match rows.next() {
+            Ok(Some(row)) => match read(row) {
+                Ok(value) => values.push(value),
+                Err(_) => *unreadable += 1,
+            },
+            Ok(None) => break,
+            Err(_) => {
+                *unreadable += 1;
+                break;
+            }
+        }
+    }
+    values
+}
+
+/// Decodes the `(key, record)` rows of `sql` that can be read.
+fn salvage_records<T: Versioned>(conn: &Connection, sql: &str, unreadable: &mut usize) -> Vec<(String, T)> {
+    let rows: Vec<(String, Vec<u8>)> = salvage_rows(conn, sql, |row| Ok((row.get(0)?, row.get(1)?)), unreadable);
+    rows.into_iter()
+        .filter_map(|(key, bytes)| match migrations::decode(&bytes) {
+            Ok((record, _)) => Some((key, record)),
+            Err(_) => {
+                *unreadable += 1;
+                None
+            }
+        })
+        .collect()
+}
+
+fn profile_from_row(row: &Row) -> rusqlite::Result<Profile> {
+    Ok(Profile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created_at: DateTime::from_timestamp_nanos(row.get(2)?),
+    })
+}
+
+fn nanos(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX)
+}
+
+/// The `stimulus_set` column: the set as JSON, which is unique for every set, custom ones
+/// included.
+fn stimulus_set_key(set: &AuditoryStimulusSet) -> String {
+    serde_json::to_string(set).unwrap()
+}
+
+fn encode_summary(session: &GameSession) -> bincode::Result<Vec<u8>> {
+    migrations::encode(&GameSessionSummary::from(session))
+}
+
+/// Rewrites the older records of a table at the current version. Returns how many could not be
+/// read.
+fn upgrade_table<T: Versioned>(conn: &Connection, table: &str, key: &str) -> Result<usize, StorageError> {
+    let rows: Vec<(String, Vec<u8>)> = conn
+        .prepare(&format!("SELECT {}, record FROM {}", key, table))?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut unreadable = 0;
+    for (id, record) in rows {
+        match migrations::decode::<T>(&record) {
+            Ok((_, CURRENT_VERSION)) => {}
+            Ok((value, _)) => {
+                conn.execute(
+                    &format!("UPDATE {} SET record = ?1 WHERE {} = ?2", table, key),
+                    params![migrations::encode(&value)?, id],
+                )?;
+            }
+            Err(e) => {
+                eprintln!("Cannot migrate record {} in {}: {}", id, table, e);
+                unreadable += 1;
+            }
+        }
+    }
+    Ok(unreadable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::conformance::{self, storage_conformance_tests};
+
+    storage_conformance_tests!(SqliteStorage::in_memory());
+
+    #[test]
+    fn test_queries_do_not_read_event_histories() {
+        let storage = SqliteStorage::in_memory();
+        conformance::save_daily_sessions(&storage, 3);
+        // Break every full session; listing the history must not notice
+        storage.conn.execute("UPDATE sessions SET record = x'00'", []).unwrap();
+        assert_eq!(storage.load_all_sessions().unwrap().len(), 3);
+        assert!(storage.load_full_sessions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = conformance::temp_path("sqlite-snapshot");
+        let mut storage = SqliteStorage::in_memory();
+        conformance::snapshot_round_trip(&mut storage, &path, |path| Box::new(SqliteStorage::open(path).unwrap()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_salvage_skips_unreadable_records() {
+        let path = conformance::temp_path("sqlite-salvage");
+        let storage = SqliteStorage::open(&path).unwrap();
+        conformance::save_daily_sessions(&storage, 3);
+        storage.conn.execute("UPDATE sessions SET record = x'00' WHERE id = 'session_1'", []).unwrap();
+        drop(storage);
+
+        let salvage = salvage(&path).unwrap();
+        let mut ids: Vec<&str> = salvage.sessions.iter().map(|(_, s)| s.id.as_str()).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["session_0", "session_2"]);
+        assert_eq!(salvage.unreadable, 1);
+        assert_eq!(salvage.profiles.len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_unreadable_settings_are_an_error() {
+        let storage = SqliteStorage::in_memory();
+        storage.save_settings(&UserSettings::default()).unwrap();
+        storage.conn.execute("UPDATE settings SET record = x'00'", []).unwrap();
+        assert!(matches!(storage.load_settings(), Err(StorageError::Decode(_))));
+    }
+
+    #[test]
+    fn test_state_survives_reopening() {
+        let dir = std::env::temp_dir().join(format!("nback-sqlite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(crate::storage::Backend::Sqlite.db_name());
+        let _ = std::fs::remove_file(&path);
+
+        let mut storage = SqliteStorage::open(&path).unwrap();
+        let ada = profiles::create_profile(&storage, "Ada").unwrap();
+        profiles::switch_profile(&mut storage, &ada.id).unwrap();
+        conformance::save_daily_sessions(&storage, 2);
+        drop(storage);
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.profile_id(), ada.id);
+        assert_eq!(storage.load_all_sessions().unwrap().len(), 2);
+        drop(storage);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_older_records_are_upgraded_on_open() {
+        let storage = SqliteStorage::in_memory();
+        let session = &conformance::save_daily_sessions(&storage, 1)[0];
+        // A summary written by a future layout change, and an older schema version
+        storage.conn.execute("UPDATE sessions SET summary = x'00'", []).unwrap();
+        storage.conn.pragma_update(None, "user_version", CURRENT_VERSION - 1).unwrap();
+
+        let storage = SqliteStorage::with_connection(storage.conn).unwrap();
+        assert_eq!(conformance::ids(&storage.query_history(&HistoryQuery::default()).unwrap()), vec![session.id.as_str()]);
+        let version: u32 = storage.conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, CURRENT_VERSION);
+
+        // An unreadable record keeps the version back, so it is retried on the next open
+        storage.conn.execute("UPDATE sessions SET record = x'00'", []).unwrap();
+        storage.conn.pragma_update(None, "user_version", CURRENT_VERSION - 1).unwrap();
+        let storage = SqliteStorage::with_connection(storage.conn).unwrap();
+        let version: u32 = storage.conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, CURRENT_VERSION - 1);
+    }
+}
@@ -0,0 +1,38 @@
+import React from 'react';
+import { useTranslation } from 'react-i18next';
+import { FeedbackState } from './GameControls';
+import './ArithmeticPanel.css';
+
+interface ArithmeticPanelProps {
+  // The turn's token: a bare digit on warm-up turns, otherwise an operation and a digit
+  token?: string;
+  answer: string;
+  feedback?: FeedbackState;
+  missed?: boolean;
+}
+
+// Typeset the backend's ASCII operations
+const OPERATIONS: Record<string, string> = { '+': '+', '-': '−', '*': '×' };
+
+const ArithmeticPanel: React.FC<ArithmeticPanelProps> = ({ token, answer, feedback = null, missed = false }) => {
+  const { t } = useTranslation();
+  const operation = token && OPERATIONS[token[0]];
+  const digit = operation ? token.slice(1) : token;
+
+  return (
+    <div className="arithmetic-panel">
+      <div className="arithmetic-token">
+        {operation && <span className="arithmetic-operation">{operation}</span>}
+        {digit}
+      </div>
+      <div className={`arithmetic-answer ${feedback ?? ''} ${missed ? 'missed' : ''}`}>
+        {answer}
+      </div>
+      <p className="arithmetic-hint">
+        {operation ? t('game.arithmeticHint') : t('game.arithmeticWarmUp')}
+      </p>
+    </div>
+  );
+};
+
+export default ArithmeticPanel;
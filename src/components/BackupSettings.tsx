@@ -0,0 +1,75 @@
+import React, { useState, useEffect } from 'react';
+import { useTranslation } from 'react-i18next';
+import { invoke } from '@tauri-apps/api/core';
+import { confirm, message } from '@tauri-apps/plugin-dialog';
+import { RotateCcw } from 'lucide-react';
+import { errorMessage } from '../utils/errors';
+import './BackupSettings.css';
+
+type BackupReason = 'Scheduled' | 'BeforeReset' | 'BeforeImport' | 'BeforeRestore';
+
+// Mirrors the backend's BackupInfo
+export interface BackupInfo {
+  id: string;
+  reason: BackupReason;
+  created_at: string; // ISO 8601 string
+  size_bytes: number;
+}
+
+const formatSize = (bytes: number) =>
+  bytes < 1024 * 1024 ? `${Math.ceil(bytes / 1024)} KB` : `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+
+const BackupSettings: React.FC = () => {
+  const { t } = useTranslation();
+  const [backups, setBackups] = useState<BackupInfo[]>([]);
+
+  useEffect(() => {
+    invoke<BackupInfo[]>('list_backups')
+      .then(setBackups)
+      .catch(error => console.error("Failed to load backups:", error));
+  }, []);
+
+  const handleRestore = async (backup: BackupInfo) => {
+    const date = new Date(backup.created_at).toLocaleString();
+    const confirmed = await confirm(t('settings.backups.restoreConfirmation', { date }), {
+      title: t('settings.backups.restore'),
+    });
+    if (!confirmed) return;
+
+    try {
+      await invoke('restore_backup', { id: backup.id });
+      // Profiles, settings and history have all been replaced, so start over
+      window.location.reload();
+    } catch (error) {
+      await message(errorMessage(error, t), { title: t('settings.backups.error'), kind: 'error' });
+    }
+  };
+
+  if (backups.length === 0) {
+    return <p className="setting-description">{t('settings.backups.empty')}</p>;
+  }
+
+  return (
+    <ul className="backup-list">
+      {backups.map(backup => (
+        <li key={backup.id} className="backup-item">
+          <span>
+            {new Date(backup.created_at).toLocaleString()}
+            <span className="backup-details">
+              {t(`settings.backups.reasons.${backup.reason}`)} · {formatSize(backup.size_bytes)}
+            </span>
+          </span>
+          <button
+            onClick={() => handleRestore(backup)}
+            className="key-action-btn"
+            aria-label={t('settings.backups.restore')}
+          >
+            <RotateCcw size={16} />
+          </button>
+        </li>
+      ))}
+    </ul>
+  );
+};
+
+export default BackupSettings;
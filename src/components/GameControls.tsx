@@ -1,20 +1,20 @@
 import React from 'react';
 import { useTranslation } from 'react-i18next';
 import Button from './ui/Button';
-import { Box, Volume2, Check, X, AlertCircle } from 'lucide-react';
+import { Check, X, AlertCircle } from 'lucide-react';
+import ModalityIcon from './ModalityIcon';
+import { Modality } from '../utils/stats';
 import './GameControls.css';
 
 export type FeedbackState = 'correct' | 'incorrect' | null;
 
 interface GameControlsProps {
-  onPositionMatch: () => void;
-  onAudioMatch: () => void;
-  positionDisabled?: boolean;
-  audioDisabled?: boolean;
-  positionFeedback?: FeedbackState;
-  audioFeedback?: FeedbackState;
-  positionMissed?: boolean;
-  audioMissed?: boolean;
+  // The session's modalities answered by a match button, in display order
+  modalities: Modality[];
+  onMatch: (modality: Modality) => void;
+  responded?: Partial<Record<Modality, boolean>>;
+  feedback?: Partial<Record<Modality, FeedbackState>>;
+  missed?: Partial<Record<Modality, boolean>>;
   animationDuration?: number;
 }
 
@@ -43,14 +43,11 @@ const MissedIcon: React.FC<{ duration: number; visible: boolean }> = ({ duration
 );
 
 const GameControls: React.FC<GameControlsProps> = ({
-  onPositionMatch,
-  onAudioMatch,
-  positionDisabled = false,
-  audioDisabled = false,
-  positionFeedback = null,
-  audioFeedback = null,
-  positionMissed = false,
-  audioMissed = false,
+  modalities,
+  onMatch,
+  responded = {},
+  feedback = {},
+  missed = {},
   animationDuration = 1000,
 }) => {
   const { t } = useTranslation();
@@ -62,24 +59,22 @@ const GameControls: React.FC<GameControlsProps> = ({
 
   return (
     <div className="game-controls-container">
-      <MissedIcon duration={animationDuration} visible={positionMissed} />
-      <Button
-        onClick={onPositionMatch}
-        disabled={positionDisabled}
-        className={getButtonClassName(positionFeedback)}
-      >
-        {positionFeedback ? <FeedbackIcon state={positionFeedback} /> : <Box size={20} className="btn-icon" />}
-        {t('game.position')}
-      </Button>
-      <Button
-        onClick={onAudioMatch}
-        disabled={audioDisabled}
-        className={getButtonClassName(audioFeedback)}
-      >
-        {audioFeedback ? <FeedbackIcon state={audioFeedback} /> : <Volume2 size={20} className="btn-icon" />}
-        {t('game.audio')}
-      </Button>
-      <MissedIcon duration={animationDuration} visible={audioMissed} />
+      {modalities.map(modality => {
+        const state = feedback[modality] ?? null;
+        return (
+          <div key={modality} className="modality-control">
+            <MissedIcon duration={animationDuration} visible={missed[modality] ?? false} />
+            <Button
+              onClick={() => onMatch(modality)}
+              disabled={responded[modality] ?? false}
+              className={getButtonClassName(state)}
+            >
+              {state ? <FeedbackIcon state={state} /> : <ModalityIcon modality={modality} className="btn-icon" />}
+              {t(`modalities.${modality.toLowerCase()}` as any)}
+            </Button>
+          </div>
+        );
+      })}
     </div>
   );
 };
@@ -1,21 +1,22 @@
 import React from 'react';
-import { BrainCircuit, Repeat, Target, Ear } from 'lucide-react';
+import { BrainCircuit, Repeat } from 'lucide-react';
+import ModalityIcon from './ModalityIcon';
+import { Modality } from '../utils/stats';
 import './GameHeader.css';
 
 interface GameHeaderProps {
   nLevel: number;
   turn: number;
   totalTurns: number;
-  visualAccuracy: number;
-  audioAccuracy: number;
+  // Running accuracy of each of the session's modalities, in display order
+  accuracies: [Modality, number][];
 }
 
 const GameHeader: React.FC<GameHeaderProps> = ({
   nLevel,
   turn,
   totalTurns,
-  visualAccuracy,
-  audioAccuracy,
+  accuracies,
 }) => {
   return (
     <div className="game-header-container">
@@ -23,14 +24,12 @@ const GameHeader: React.FC<GameHeaderProps> = ({
         <BrainCircuit size={20} />
         <span>{nLevel}</span>
       </div>
-      <div className="header-item">
-        <Target size={20} />
-        <span>{visualAccuracy.toFixed(0)}%</span>
-      </div>
-      <div className="header-item">
-        <Ear size={20} />
-        <span>{audioAccuracy.toFixed(0)}%</span>
-      </div>
+      {accuracies.map(([modality, accuracy]) => (
+        <div key={modality} className="header-item">
+          <ModalityIcon modality={modality} />
+          <span>{accuracy.toFixed(0)}%</span>
+        </div>
+      ))}
       <div className="header-item">
         <Repeat size={20} />
         <span>{turn + 1}/{totalTurns}</span>
@@ -1,33 +1,59 @@
 import React from 'react';
-import { useSettings } from '../contexts/SettingsContext';
+import { useSettings, GridLayout } from '../contexts/SettingsContext';
 import './Grid.css';
 
 interface GridProps {
   activeIndex?: number | null;
+  layout?: GridLayout;
+  // Drawn inside the active cell, e.g. the turn's color, shape or image
+  children?: React.ReactNode;
 }
 
-const Grid: React.FC<GridProps> = ({ activeIndex = null }) => {
+const DEFAULT_LAYOUT: GridLayout = { Square: { size: 3 } };
+
+const Grid: React.FC<GridProps> = ({ activeIndex = null, layout = DEFAULT_LAYOUT, children }) => {
   const { settings } = useSettings();
-  const size = 3;
-  const cells = Array.from({ length: size * size });
 
-  const gridStyle = {
-    gridTemplateColumns: `repeat(${size}, 1fr)`,
-  };
+  let rows = 3;
+  let cols = 3;
+  let ring = 0;
+  if ('Square' in layout) {
+    rows = cols = layout.Square.size;
+  } else if ('Rect' in layout) {
+    ({ rows, cols } = layout.Rect);
+  } else {
+    ring = layout.Ring.positions;
+  }
+  const cells = Array.from({ length: ring || rows * cols });
+
+  const gridStyle = ring
+    ? { display: 'block', position: 'relative' as const }
+    : {
+        gridTemplateColumns: `repeat(${cols}, 1fr)`,
+        gridTemplateRows: `repeat(${rows}, 1fr)`,
+      };
 
   return (
-    <div className="grid-container" style={gridStyle}>
+    <div className={`grid-container ${ring ? 'ring' : ''}`} style={gridStyle}>
       {cells.map((_, index) => {
         const isActive = activeIndex === index;
+        // Ring cells start at the top and go clockwise
+        const angle = (2 * Math.PI * index) / (ring || 1) - Math.PI / 2;
         const cellStyle = {
           animationDuration: isActive ? `${settings.speed_ms}ms` : undefined,
+          ...(ring && {
+            left: `${50 + 38 * Math.cos(angle)}%`,
+            top: `${50 + 38 * Math.sin(angle)}%`,
+          }),
         };
         return (
           <div
             key={index}
             className={`grid-cell ${isActive ? 'active' : ''}`}
             style={cellStyle}
-          />
+          >
+            {isActive && children}
+          </div>
         );
       })}
     </div>
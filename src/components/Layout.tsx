@@ -1,13 +1,26 @@
+import { useEffect } from "react";
 import { NavLink, Outlet, useLocation, useNavigate } from "react-router-dom";
 import { useTranslation } from "react-i18next";
-import { confirm } from "@tauri-apps/plugin-dialog";
+import { invoke } from "@tauri-apps/api/core";
+import { confirm, message } from "@tauri-apps/plugin-dialog";
 import { useGameStatus } from "../contexts/GameStatusContext";
 import { usePause } from "../contexts/PauseContext";
 import { Home, Settings, History } from "lucide-react";
+import { AppError, errorMessage } from "../utils/errors";
 import "./Layout.css";
 
 const getLinkClass = ({ isActive }: { isActive: boolean }) => (isActive ? "active" : "");
 
+// Mirrors the backend's RecoveryReport
+interface RecoveryReport {
+  problem: string;
+  quarantine_dir: string;
+  restored_backup: string | null;
+  recovered_profiles: number;
+  recovered_sessions: number;
+  unreadable_records: number;
+}
+
 const Layout = () => {
   const { t } = useTranslation();
   const location = useLocation();
@@ -15,6 +28,37 @@ const Layout = () => {
   const { isGameRunning, setIsGameRunning } = useGameStatus();
   const { requestPause } = usePause();
 
+  // Tell the user if a damaged database had to be rebuilt at startup
+  useEffect(() => {
+    invoke<RecoveryReport | null>('take_recovery_report')
+      .then(report => {
+        if (!report) return;
+        const backup = report.restored_backup
+          ? t('recovery.fromBackup', { backup: report.restored_backup })
+          : t('recovery.noBackup');
+        const details = t('recovery.message', {
+          problem: report.problem,
+          sessions: report.recovered_sessions,
+          profiles: report.recovered_profiles,
+          unreadable: report.unreadable_records,
+          quarantineDir: report.quarantine_dir,
+        });
+        return message(`${details} ${backup}`, { title: t('recovery.title'), kind: 'warning' });
+      })
+      .catch(e => console.error('Failed to load recovery report:', e));
+  }, [t]);
+
+  // Tell the user if the app started without being able to read their settings
+  useEffect(() => {
+    invoke<AppError | null>('take_startup_error')
+      .then(error => {
+        if (!error) return;
+        const details = `${t('startup.settingsUnreadable')}\n\n${errorMessage(error, t)}`;
+        return message(details, { title: t('startup.title'), kind: 'error' });
+      })
+      .catch(e => console.error('Failed to load startup error:', e));
+  }, [t]);
+
   const handleNavigate = async (e: React.MouseEvent, path: string) => {
     if (isGameRunning && location.pathname === '/') {
       e.preventDefault();
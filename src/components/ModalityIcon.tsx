@@ -0,0 +1,25 @@
+import React from 'react';
+import { Box, Volume2, Palette, Shapes, Image, Calculator } from 'lucide-react';
+import { Modality } from '../utils/stats';
+
+const ICONS: Record<Modality, React.FC<{ size?: number; className?: string }>> = {
+  Position: Box,
+  Audio: Volume2,
+  Color: Palette,
+  Shape: Shapes,
+  Image: Image,
+  Arithmetic: Calculator,
+};
+
+interface ModalityIconProps {
+  modality: Modality;
+  size?: number;
+  className?: string;
+}
+
+const ModalityIcon: React.FC<ModalityIconProps> = ({ modality, size = 20, className }) => {
+  const Icon = ICONS[modality];
+  return <Icon size={size} className={className} />;
+};
+
+export default ModalityIcon;
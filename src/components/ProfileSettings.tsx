@@ -0,0 +1,122 @@
+import React, { useState, useEffect } from 'react';
+import { useTranslation } from 'react-i18next';
+import { invoke } from '@tauri-apps/api/core';
+import { confirm, message } from '@tauri-apps/plugin-dialog';
+import { Plus, Pencil, Trash2 } from 'lucide-react';
+import Button from './ui/Button';
+import { errorMessage } from '../utils/errors';
+import './ProfileSettings.css';
+
+// Mirrors the backend's Profile
+export interface Profile {
+  id: string;
+  name: string;
+  created_at: string; // ISO 8601 string
+}
+
+const DEFAULT_PROFILE_ID = 'default';
+
+const ProfileSettings: React.FC = () => {
+  const { t } = useTranslation();
+  const [profiles, setProfiles] = useState<Profile[]>([]);
+  const [activeId, setActiveId] = useState<string>(DEFAULT_PROFILE_ID);
+  const [name, setName] = useState('');
+
+  const refresh = async () => {
+    const [all, active] = await Promise.all([
+      invoke<Profile[]>('list_profiles'),
+      invoke<Profile>('get_active_profile'),
+    ]);
+    setProfiles(all);
+    setActiveId(active.id);
+  };
+
+  useEffect(() => {
+    refresh().catch(error => console.error("Failed to load profiles:", error));
+  }, []);
+
+  // Reports backend errors such as a duplicate name to the user
+  const run = async (action: () => Promise<unknown>) => {
+    try {
+      await action();
+      await refresh();
+    } catch (error) {
+      await message(errorMessage(error, t), { title: t('settings.profiles.error'), kind: 'error' });
+    }
+  };
+
+  const handleSwitch = (id: string) => run(async () => {
+    await invoke('switch_profile', { id });
+    // Settings, history and any interrupted session all belong to the profile, so start over
+    window.location.reload();
+  });
+
+  const handleCreate = () => run(async () => {
+    await invoke('create_profile', { name });
+    setName('');
+  });
+
+  const handleRename = () => run(async () => {
+    await invoke('rename_profile', { id: activeId, name });
+    setName('');
+  });
+
+  const handleDelete = async (profile: Profile) => {
+    const confirmed = await confirm(t('settings.profiles.deleteConfirmation', { name: profile.name }), {
+      title: t('settings.profiles.delete'),
+    });
+    if (confirmed) {
+      await run(() => invoke('delete_profile', { id: profile.id }));
+    }
+  };
+
+  return (
+    <div className="profile-settings">
+      <select
+        id="active-profile"
+        className="select-input"
+        value={activeId}
+        onChange={(e) => handleSwitch(e.target.value)}
+      >
+        {profiles.map(profile => (
+          <option key={profile.id} value={profile.id}>{profile.name}</option>
+        ))}
+      </select>
+      <div className="profile-name-row">
+        <input
+          type="text"
+          className="select-input"
+          placeholder={t('settings.profiles.namePlaceholder')}
+          value={name}
+          onChange={(e) => setName(e.target.value)}
+        />
+        <Button type="button" variant="secondary" onClick={handleCreate} disabled={!name.trim()}>
+          <Plus size={16} className="btn-icon" />
+          {t('settings.profiles.create')}
+        </Button>
+        <Button type="button" variant="secondary" onClick={handleRename} disabled={!name.trim()}>
+          <Pencil size={16} className="btn-icon" />
+          {t('settings.profiles.rename')}
+        </Button>
+      </div>
+      <ul className="profile-list">
+        {profiles
+          .filter(profile => profile.id !== activeId && profile.id !== DEFAULT_PROFILE_ID)
+          .map(profile => (
+            <li key={profile.id} className="profile-item">
+              <span>{profile.name}</span>
+              <button
+                onClick={() => handleDelete(profile)}
+                className="key-action-btn remove-key-btn"
+                aria-label={t('settings.profiles.delete')}
+              >
+                <Trash2 size={16} />
+              </button>
+            </li>
+          ))}
+      </ul>
+    </div>
+  );
+};
+
+export default ProfileSettings;
@@ -0,0 +1,67 @@
+import React from 'react';
+import { Apple, Anchor, Bell, Bird, Cloud, Flower2, Key, Umbrella } from 'lucide-react';
+import { Modality } from '../utils/stats';
+import './StimulusGlyph.css';
+
+// The backend draws images from `image_1` to `image_8`
+const IMAGES: Record<string, React.FC<{ size?: number | string; color?: string }>> = {
+  image_1: Apple,
+  image_2: Anchor,
+  image_3: Bell,
+  image_4: Bird,
+  image_5: Cloud,
+  image_6: Flower2,
+  image_7: Key,
+  image_8: Umbrella,
+};
+
+// Corners of a regular polygon in a 100x100 box, starting at the top
+const polygon = (corners: number, radius = 46, inner?: number): string =>
+  Array.from({ length: inner ? corners * 2 : corners }, (_, i) => {
+    const r = inner && i % 2 ? inner : radius;
+    const angle = (Math.PI * 2 * i) / (inner ? corners * 2 : corners) - Math.PI / 2;
+    return `${50 + r * Math.cos(angle)},${50 + r * Math.sin(angle)}`;
+  }).join(' ');
+
+const SHAPES: Record<string, React.ReactNode> = {
+  circle: <circle cx="50" cy="50" r="46" />,
+  square: <rect x="8" y="8" width="84" height="84" />,
+  triangle: <polygon points="50,6 95,90 5,90" />,
+  diamond: <polygon points="50,4 96,50 50,96 4,50" />,
+  star: <polygon points={polygon(5, 48, 20)} />,
+  pentagon: <polygon points={polygon(5)} />,
+  hexagon: <polygon points={polygon(6)} />,
+  cross: <polygon points="35,5 65,5 65,35 95,35 95,65 65,65 65,95 35,95 35,65 5,65 5,35 35,35" />,
+};
+
+interface StimulusGlyphProps {
+  // The turn's stimulus; only the color, shape and image are drawn
+  stimuli: Partial<Record<Modality, string>>;
+  size?: number | string;
+}
+
+/**
+ * Draws the color, shape and image stimuli of one turn. The shape and image take the
+ * turn's color; a color on its own is shown as a swatch.
+ */
+const StimulusGlyph: React.FC<StimulusGlyphProps> = ({ stimuli, size = 64 }) => {
+  const { Color: color, Shape: shape, Image: image } = stimuli;
+  const fill = color ?? 'var(--accent-color)';
+  const ImageIcon = image ? IMAGES[image] : undefined;
+
+  return (
+    <div className="stimulus-glyph">
+      {shape && (
+        <svg viewBox="0 0 100 100" width={size} height={size} fill={fill} className="glyph-shape">
+          {SHAPES[shape]}
+        </svg>
+      )}
+      {ImageIcon && <ImageIcon size={size} color={fill} />}
+      {color && !shape && !ImageIcon && (
+        <div className="glyph-swatch" style={{ width: size, height: size, backgroundColor: color }} />
+      )}
+    </div>
+  );
+};
+
+export default StimulusGlyph;
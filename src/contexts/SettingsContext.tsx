@@ -2,8 +2,11 @@ import React, { createContext, useState, useEffect, useContext, ReactNode } from
 import { invoke } from '@tauri-apps/api/core';
 import { getCurrentWindow } from '@tauri-apps/api/window';
 import { locale } from '@tauri-apps/plugin-os';
+import { message } from '@tauri-apps/plugin-dialog';
 import { useLocalStorage } from '../hooks/useLocalStorage';
 import { useTranslation } from 'react-i18next';
+import { errorMessage } from '../utils/errors';
+import { Modality } from '../utils/stats';
 
 export enum AuditoryStimulusSet {
   AllLetters = 'AllLetters',
@@ -11,14 +14,54 @@ export enum AuditoryStimulusSet {
   TianGanDiZhi = 'TianGanDiZhi',
 }
 
+// A user-registered auditory set, referenced by ID
+export type CustomAuditoryStimulusSet = { Custom: string };
+
+// Mirrors the backend's GridLayout enum for the position modality
+export type GridLayout =
+  | { Square: { size: number } }
+  | { Rect: { rows: number; cols: number } }
+  | { Ring: { positions: number } };
+
 // Interface for settings stored in the backend
 export interface UserSettings {
   n_level: number;
+  n_level_mode?: 'Fixed' | 'Variable';
   speed_ms: number;
   session_length: number;
-  auditory_stimulus_set: AuditoryStimulusSet;
+  auditory_stimulus_set: AuditoryStimulusSet | CustomAuditoryStimulusSet;
+  grid_layout?: GridLayout;
+  modalities?: Modality[];
+  aborted_sessions?: 'Discard' | 'KeepPartial';
+  difficulty_policy?: DifficultyPolicy;
+  staircase?: Staircase | null;
+}
+
+// Mirrors the backend's DifficultyPolicy enum
+export type DifficultyPolicyName = 'Jaeggi' | 'BrainWorkshop' | 'DPrimeStaircase' | 'SpeedRamp';
+export type DifficultyPolicy =
+  | 'Jaeggi'
+  | 'BrainWorkshop'
+  | { DPrimeStaircase: { raise_at: number; lower_below: number } }
+  | { SpeedRamp: { step_ms: number; fastest_ms: number; slowest_ms: number } };
+
+// Mirrors the backend's Staircase: adapts N or speed between blocks of one session
+export interface Staircase {
+  block_length: number;
+  up_after: number;
+  down_after: number;
+  pass_accuracy: number;
+  target: 'NLevel' | { Speed: { step_ms: number; fastest_ms: number; slowest_ms: number } };
 }
 
+export const DEFAULT_STAIRCASE: Staircase = {
+  block_length: 20,
+  up_after: 2,
+  down_after: 1,
+  pass_accuracy: 0.8,
+  target: 'NLevel',
+};
+
 // Interface for all settings, including client-side ones
 export interface AppSettings extends UserSettings {
   theme: string;
@@ -29,11 +72,23 @@ export interface AppSettings extends UserSettings {
   reduceMotion: boolean;
   positionKeys: string[];
   audioKeys: string[];
+  colorKeys: string[];
+  shapeKeys: string[];
+  imageKeys: string[];
   autoAdjustNLevel: boolean;
-  highAccuracyThreshold: number;
-  lowAccuracyThreshold: number;
 }
 
+// The setting holding the keys of each modality answered by a keypress. Arithmetic is
+// answered by typing the result instead.
+export type KeyBindingSetting = 'positionKeys' | 'audioKeys' | 'colorKeys' | 'shapeKeys' | 'imageKeys';
+export const KEY_BINDINGS: Partial<Record<Modality, KeyBindingSetting>> = {
+  Position: 'positionKeys',
+  Audio: 'audioKeys',
+  Color: 'colorKeys',
+  Shape: 'shapeKeys',
+  Image: 'imageKeys',
+};
+
 // Default settings to be used on first load or if loading fails
 const defaultSettings: AppSettings = {
   n_level: 2,
@@ -48,9 +103,10 @@ const defaultSettings: AppSettings = {
   reduceMotion: false,
   positionKeys: ['p', 'h', '[', 'ArrowRight'],
   audioKeys: ['a', 'l', ']', 'ArrowLeft'],
+  colorKeys: ['c', 'j'],
+  shapeKeys: ['s', 'k'],
+  imageKeys: ['i', 'u'],
   autoAdjustNLevel: true,
-  highAccuracyThreshold: 90,
-  lowAccuracyThreshold: 50,
 };
 
 // Type for the context value
@@ -69,7 +125,7 @@ const SettingsContext = createContext<SettingsContextType | undefined>(undefined
 
 // Provider component
 export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }) => {
-  const { i18n } = useTranslation();
+  const { i18n, t } = useTranslation();
   const [settings, setSettings] = useState<AppSettings>(defaultSettings);
   const [initialState, setInitialState] = useState<AppSettings>(defaultSettings);
   const [isLoading, setIsLoading] = useState(true);
@@ -83,9 +139,10 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
   const [reduceMotion, setReduceMotion] = useLocalStorage('settings:reduceMotion', defaultSettings.reduceMotion);
   const [positionKeys, setPositionKeys] = useLocalStorage('settings:positionKeys', defaultSettings.positionKeys);
   const [audioKeys, setAudioKeys] = useLocalStorage('settings:audioKeys', defaultSettings.audioKeys);
+  const [colorKeys, setColorKeys] = useLocalStorage('settings:colorKeys', defaultSettings.colorKeys);
+  const [shapeKeys, setShapeKeys] = useLocalStorage('settings:shapeKeys', defaultSettings.shapeKeys);
+  const [imageKeys, setImageKeys] = useLocalStorage('settings:imageKeys', defaultSettings.imageKeys);
   const [autoAdjustNLevel, setAutoAdjustNLevel] = useLocalStorage('settings:autoAdjustNLevel', defaultSettings.autoAdjustNLevel);
-  const [highAccuracyThreshold, setHighAccuracyThreshold] = useLocalStorage('settings:highAccuracyThreshold', defaultSettings.highAccuracyThreshold);
-  const [lowAccuracyThreshold, setLowAccuracyThreshold] = useLocalStorage('settings:lowAccuracyThreshold', defaultSettings.lowAccuracyThreshold);
 
   const loadSettings = async () => {
     setIsLoading(true);
@@ -101,9 +158,10 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
         reduceMotion,
         positionKeys,
         audioKeys,
+        colorKeys,
+        shapeKeys,
+        imageKeys,
         autoAdjustNLevel,
-        highAccuracyThreshold,
-        lowAccuracyThreshold,
       };
       setSettings(fullSettings);
       setInitialState(fullSettings);
@@ -119,12 +177,17 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
         reduceMotion,
         positionKeys,
         audioKeys,
+        colorKeys,
+        shapeKeys,
+        imageKeys,
         autoAdjustNLevel,
-        highAccuracyThreshold,
-        lowAccuracyThreshold,
       };
       setSettings(fullSettings);
       setInitialState(fullSettings);
+      await message(`${t('settings.loadFailed')}\n\n${errorMessage(error, t)}`, {
+        title: t('settings.loadFailedTitle'),
+        kind: 'warning',
+      });
     } finally {
       setIsLoading(false);
     }
@@ -137,8 +200,8 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
 
   // Sync local storage values with the main settings state
   useEffect(() => {
-    setSettings(prev => ({ ...prev, theme, language, followSystemLanguage, followSystemTheme, allowFastSpeed, reduceMotion, positionKeys, audioKeys, autoAdjustNLevel, highAccuracyThreshold, lowAccuracyThreshold }));
-  }, [theme, language, followSystemLanguage, followSystemTheme, allowFastSpeed, reduceMotion, positionKeys, audioKeys, autoAdjustNLevel, highAccuracyThreshold, lowAccuracyThreshold]);
+    setSettings(prev => ({ ...prev, theme, language, followSystemLanguage, followSystemTheme, allowFastSpeed, reduceMotion, positionKeys, audioKeys, colorKeys, shapeKeys, imageKeys, autoAdjustNLevel }));
+  }, [theme, language, followSystemLanguage, followSystemTheme, allowFastSpeed, reduceMotion, positionKeys, audioKeys, colorKeys, shapeKeys, imageKeys, autoAdjustNLevel]);
 
   // Effect to apply theme and language changes globally
   useEffect(() => {
@@ -218,6 +281,12 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
       speed_ms: settings.speed_ms,
       session_length: settings.session_length,
       auditory_stimulus_set: settings.auditory_stimulus_set,
+      n_level_mode: settings.n_level_mode,
+      grid_layout: settings.grid_layout,
+      modalities: settings.modalities,
+      aborted_sessions: settings.aborted_sessions,
+      difficulty_policy: settings.difficulty_policy,
+      staircase: settings.staircase,
     };
     await invoke('save_user_settings', { settings: backendSettings });
     
@@ -230,9 +299,10 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
     setReduceMotion(settings.reduceMotion);
     setPositionKeys(settings.positionKeys);
     setAudioKeys(settings.audioKeys);
+    setColorKeys(settings.colorKeys);
+    setShapeKeys(settings.shapeKeys);
+    setImageKeys(settings.imageKeys);
     setAutoAdjustNLevel(settings.autoAdjustNLevel);
-    setHighAccuracyThreshold(settings.highAccuracyThreshold);
-    setLowAccuracyThreshold(settings.lowAccuracyThreshold);
 
     // Update initial state to reflect the saved state
     setInitialState(settings);
@@ -250,9 +320,10 @@ export const SettingsProvider: React.FC<{ children: ReactNode }> = ({ children }
     setReduceMotion(defaultSettings.reduceMotion);
     setPositionKeys(defaultSettings.positionKeys);
     setAudioKeys(defaultSettings.audioKeys);
+    setColorKeys(defaultSettings.colorKeys);
+    setShapeKeys(defaultSettings.shapeKeys);
+    setImageKeys(defaultSettings.imageKeys);
     setAutoAdjustNLevel(defaultSettings.autoAdjustNLevel);
-    setHighAccuracyThreshold(defaultSettings.highAccuracyThreshold);
-    setLowAccuracyThreshold(defaultSettings.lowAccuracyThreshold);
 
     // Directly set the state to default settings
     setSettings(defaultSettings);
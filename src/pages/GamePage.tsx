@@ -2,9 +2,10 @@ import React, { useState, useCallback, useEffect, useRef } from 'react';
 import { useTranslation } from 'react-i18next';
 import { useNavigate } from 'react-router-dom';
 import { Pause, Play, X } from 'lucide-react';
-import { invoke } from '@tauri-apps/api/core';
-import { confirm } from '@tauri-apps/plugin-dialog';
-import { useSettings, AuditoryStimulusSet } from '../contexts/SettingsContext';
+import { invoke, convertFileSrc } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
+import { confirm, message } from '@tauri-apps/plugin-dialog';
+import { useSettings, AuditoryStimulusSet, GridLayout, KEY_BINDINGS } from '../contexts/SettingsContext';
 import { useGameStatus } from '../contexts/GameStatusContext';
 import { usePause } from '../contexts/PauseContext';
 import { BrainCircuit, Timer} from 'lucide-react';
@@ -13,9 +14,12 @@ import Button from '../components/ui/Button';
 import Grid from '../components/Grid';
 import GameControls, { FeedbackState } from '../components/GameControls';
 import GameHeader from '../components/GameHeader';
+import StimulusGlyph from '../components/StimulusGlyph';
+import ArithmeticPanel from '../components/ArithmeticPanel';
 import Card from '../components/ui/Card';
 import Stat from '../components/ui/Stat';
-import { GameSessionSummary, calculateAccuracy } from '../utils/stats'; // Import for type safety
+import { DEFAULT_MODALITIES, GameEvent, GameSessionSummary, Modality } from '../utils/stats'; // Import for type safety
+import { AppError, errorMessage, isAppError } from '../utils/errors';
 import './GamePage.css';
 
 // --- Data Structures mirroring Rust backend ---
@@ -23,28 +27,50 @@ import { UserSettings } from '../contexts/SettingsContext';
 
 // --- Data Structures mirroring Rust backend ---
 interface FrontendStimulus {
-  visual_stimulus: { position: number };
-  audio_stimulus: { letter: string };
+  visual_stimulus: { position: number } | null;
+  audio_stimulus: { letter: string } | null;
+  stimuli: Partial<Record<Modality, string>>; // Every modality's stimulus
+  n_back: number; // Varies per turn in variable-N mode
+}
+
+interface ModalityStats {
+  accuracy: number;
+  falseAlarmRate: number;
+  lureFalseAlarmRate: number;
 }
 
 interface GameState {
+  phase: 'Idle' | 'Running' | 'Paused' | 'Aborted' | 'Completed';
   isRunning: boolean;
+  isPaused: boolean;
   settings: UserSettings;
   currentTurnIndex: number;
   currentStimulus: FrontendStimulus | null;
-  visualAccuracy: number;
-  visualFalseAlarmRate: number;
-  audioAccuracy: number;
-  audioFalseAlarmRate: number;
-  isVisualMatch: boolean;
-  isAudioMatch: boolean;
+  modalityStats: Partial<Record<Modality, ModalityStats>>;
+  upcomingMatches: Modality[];
 }
 
-interface UserResponse {
-  visual_match: boolean;
-  audio_match: boolean;
+// Color, shape and image are drawn in the active cell; without positions that is the only cell
+const GLYPH_MODALITIES: Modality[] = ['Color', 'Shape', 'Image'];
+const SINGLE_CELL: GridLayout = { Square: { size: 1 } };
+// Long enough for any answer, which lies between -9 and 81
+const MAX_ANSWER_LENGTH = 3;
+
+type Adjustment = 'Keep' | 'RaiseNLevel' | 'LowerNLevel' | 'SpeedUp' | 'SlowDown';
+
+interface Recommendation {
+  adjustment: Adjustment;
+  settings: UserSettings;
 }
 
+// Confirmation dialog text for each recommended change
+const DIFFICULTY_PROMPTS: Partial<Record<Adjustment, { message: string; okLabel: string }>> = {
+  RaiseNLevel: { message: 'game.increaseDifficultyPrompt', okLabel: 'game.increase' },
+  LowerNLevel: { message: 'game.decreaseDifficultyPrompt', okLabel: 'game.decrease' },
+  SpeedUp: { message: 'game.speedUpPrompt', okLabel: 'game.speedUp' },
+  SlowDown: { message: 'game.slowDownPrompt', okLabel: 'game.slowDown' },
+};
+
 const GamePage: React.FC = () => {
   const { t } = useTranslation();
   const navigate = useNavigate();
@@ -55,37 +81,42 @@ const GamePage: React.FC = () => {
   const [isLoading, setIsLoading] = useState(false);
   const [isPaused, setIsPaused] = useState(false);
   const [isTransitioning, setIsTransitioning] = useState(false);
-  const userResponseRef = useRef<UserResponse>({ visual_match: false, audio_match: false });
-  const previousMatchStatusRef = useRef({ isVisualMatch: false, isAudioMatch: false });
-  const gameLoopTimerRef = useRef<number | null>(null);
   
-  // State for immediate feedback
-  const [hasRespondedVisual, setHasRespondedVisual] = useState(false);
-  const [hasRespondedAudio, setHasRespondedAudio] = useState(false);
-  const [positionFeedback, setPositionFeedback] = useState<FeedbackState>(null);
-  const [audioFeedback, setAudioFeedback] = useState<FeedbackState>(null);
-  const [positionMissed, setPositionMissed] = useState(false);
-  const [audioMissed, setAudioMissed] = useState(false);
+  // State for immediate feedback, per modality
+  const [responded, setResponded] = useState<Partial<Record<Modality, boolean>>>({});
+  const [feedback, setFeedback] = useState<Partial<Record<Modality, FeedbackState>>>({});
+  const [missed, setMissed] = useState<Partial<Record<Modality, boolean>>>({});
+  // The arithmetic answer typed so far on this turn
+  const [answer, setAnswer] = useState('');
   const audioCache = useRef<Record<string, HTMLAudioElement>>({});
+  const customSoundsRef = useRef<Record<string, string>>({});
+  // Whether this session has already warned that its checkpoint could not be saved
+  const checkpointWarnedRef = useRef(false);
 
   // --- Keyboard Listener Effect ---
   useEffect(() => {
     const handleKeyDown = (event: KeyboardEvent) => {
       if (!gameState || !gameState.isRunning || isPaused) return;
 
-      if (contextSettings.positionKeys.includes(event.key)) {
-        handlePositionMatch();
-      }
-      if (contextSettings.audioKeys.includes(event.key)) {
-        handleAudioMatch();
+      const modalities = gameState.settings.modalities ?? DEFAULT_MODALITIES;
+      if (modalities.includes('Arithmetic') && handleAnswerKey(event.key)) {
+        // Keep Enter from also clicking a focused match button
+        event.preventDefault();
+        return;
       }
+      modalities.forEach(modality => {
+        const binding = KEY_BINDINGS[modality];
+        if (binding && contextSettings[binding].includes(event.key)) {
+          handleMatch(modality);
+        }
+      });
     };
 
     window.addEventListener('keydown', handleKeyDown);
     return () => {
       window.removeEventListener('keydown', handleKeyDown);
     };
-  }, [gameState, isPaused, contextSettings.positionKeys, contextSettings.audioKeys]);
+  }, [gameState, isPaused, contextSettings, responded, answer]);
 
   // --- Pause Listener Effect ---
   useEffect(() => {
@@ -103,15 +134,33 @@ const GamePage: React.FC = () => {
     };
   }, [gameState?.isRunning, isPaused, setPauseListener]);
 
+  // --- Custom Sound Lookup ---
+  // Custom auditory sets play the sound files registered with them
+  useEffect(() => {
+    const stimulusSet = contextSettings.auditory_stimulus_set;
+    if (typeof stimulusSet === 'string') return;
+    invoke<{ id: string; stimuli: { token: string; sound: string }[] }[]>('list_stimulus_sets')
+      .then(sets => {
+        const set = sets.find(s => s.id === stimulusSet.Custom);
+        customSoundsRef.current = Object.fromEntries(
+          (set?.stimuli ?? []).map(s => [s.token, convertFileSrc(s.sound)])
+        );
+      })
+      .catch(e => console.error('Failed to load custom stimulus set:', e));
+  }, [contextSettings.auditory_stimulus_set]);
+
   // --- Audio Playback Effect ---
   useEffect(() => {
     if (gameState?.isRunning && !isPaused) {
-      const letter = gameState.currentStimulus?.audio_stimulus.letter;
+      const letter = gameState.currentStimulus?.audio_stimulus?.letter;
       if (letter) {
-        const isTianGanDiZhi = contextSettings.auditory_stimulus_set === AuditoryStimulusSet.TianGanDiZhi;
-        const audioSrc = isTianGanDiZhi
-          ? `/sounds/tiangandizhi/${letter}.mp3`
-          : `/sounds/letter_${letter}.mp3`;
+        const stimulusSet = contextSettings.auditory_stimulus_set;
+        const audioSrc = typeof stimulusSet !== 'string'
+          ? customSoundsRef.current[letter]
+          : stimulusSet === AuditoryStimulusSet.TianGanDiZhi
+            ? `/sounds/tiangandizhi/${letter}.mp3`
+            : `/sounds/letter_${letter}.mp3`;
+        if (!audioSrc) return;
 
         let audio = audioCache.current[audioSrc];
         if (!audio) {
@@ -126,128 +175,108 @@ const GamePage: React.FC = () => {
 
   // --- Missed Feedback Effect ---
   useEffect(() => {
-    let posTimer: number;
-    let audTimer: number;
+    if (Object.keys(missed).length === 0) return;
     const animationDuration = gameState ? gameState.settings.speed_ms / 2 : 500;
+    const timer = setTimeout(() => setMissed({}), animationDuration);
+    return () => clearTimeout(timer);
+  }, [missed, gameState]);
 
-    if (positionMissed) {
-      posTimer = setTimeout(() => setPositionMissed(false), animationDuration);
-    }
-    if (audioMissed) {
-      audTimer = setTimeout(() => setAudioMissed(false), animationDuration);
-    }
-
-    return () => {
-      clearTimeout(posTimer);
-      clearTimeout(audTimer);
-    };
-  }, [positionMissed, audioMissed, gameState]);
-
-  // --- Game Loop Effect ---
-  useEffect(() => {
-    if (!gameState || !gameState.isRunning || isPaused) {
-      return;
-    }
-
-    previousMatchStatusRef.current = {
-      isVisualMatch: gameState.isVisualMatch,
-      isAudioMatch: gameState.isAudioMatch,
-    };
-
-    const gameSpeed = gameState.settings.speed_ms;
+  // --- Session End ---
+  const handleSessionFinished = useCallback((session: GameSessionSummary) => {
+    // Game is over. Update state and set transitioning flag to prevent UI flash.
+    setGameState(s => s ? { ...s, isRunning: false } : null);
+    setIsGameRunning(false);
+    setIsTransitioning(true);
 
-    gameLoopTimerRef.current = window.setTimeout(async () => {
+    // Use a timer so the final feedback is still visible before navigating away.
+    setTimeout(async () => {
       try {
-        if (gameState.currentTurnIndex > 0) {
-          if (previousMatchStatusRef.current.isVisualMatch && !userResponseRef.current.visual_match) {
-            setPositionMissed(true);
-          }
-          if (previousMatchStatusRef.current.isAudioMatch && !userResponseRef.current.audio_match) {
-            setAudioMissed(true);
+        // Offer the backend's difficulty recommendation for the next session
+        if (contextSettings.autoAdjustNLevel) {
+          const { adjustment, settings: next } = await invoke<Recommendation>('recommend_next_settings');
+          const prompt = DIFFICULTY_PROMPTS[adjustment];
+          if (prompt) {
+            const confirmed = await confirm(t(prompt.message) + '\n\n' + t('game.disableSuggestionInSettings'), {
+              title: t('game.difficultySuggestionTitle'),
+              okLabel: t(prompt.okLabel),
+              cancelLabel: t('game.cancel'),
+            });
+            if (confirmed) {
+              setSettings(prev => ({ ...prev, n_level: next.n_level, speed_ms: next.speed_ms }));
+            }
           }
         }
 
-        await invoke('submit_user_input', { userResponse: userResponseRef.current });
-        userResponseRef.current = { visual_match: false, audio_match: false };
-
-        const newState = await invoke<GameState>('get_game_state');
-
-        if (newState.isRunning) {
-          setGameState(newState);
-          setHasRespondedVisual(false);
-          setHasRespondedAudio(false);
-          setPositionFeedback(null);
-          setAudioFeedback(null);
-        } else {
-          // Game is over. Update state and set transitioning flag to prevent UI flash.
-          setGameState(s => s ? { ...s, isRunning: false } : null);
-          setIsGameRunning(false);
-          setIsTransitioning(true);
-          
-          // Use a new timer that is NOT tracked by the effect's cleanup ref.
-          // This ensures the navigation is not cancelled by the re-render.
-          setTimeout(async () => {
-            try {
-              // Add a small delay to ensure the session is persisted before fetching.
-              await new Promise(resolve => setTimeout(resolve, 100));
-              const history = await invoke<GameSessionSummary[]>('get_game_history');
-              if (history.length > 0) {
-                const latestSession = [...history].sort((a, b) => new Date(b.timestamp).getTime() - new Date(a.timestamp).getTime())[0];
-                
-                // Check for auto-adjustment
-                if (contextSettings.autoAdjustNLevel) {
-                  const visualAccuracy = calculateAccuracy(latestSession.visual_stats);
-                  const audioAccuracy = calculateAccuracy(latestSession.audio_stats);
-                  const overallAccuracy = (visualAccuracy + audioAccuracy) / 2;
-
-                  if (overallAccuracy > contextSettings.highAccuracyThreshold) {
-                    const confirmed = await confirm(t('game.increaseDifficultyPrompt') + '\n\n' + t('game.disableSuggestionInSettings'), {
-                      title: t('game.difficultySuggestionTitle'),
-                      okLabel: t('game.increase'),
-                      cancelLabel: t('game.cancel'),
-                    });
-                    if (confirmed) {
-                      setSettings(prev => ({ ...prev, n_level: Math.min(9, prev.n_level + 1) }));
-                    }
-                  } else if (overallAccuracy < contextSettings.lowAccuracyThreshold) {
-                    const confirmed = await confirm(t('game.decreaseDifficultyPrompt') + '\n\n' + t('game.disableSuggestionInSettings'), {
-                      title: t('game.difficultySuggestionTitle'),
-                      okLabel: t('game.decrease'),
-                      cancelLabel: t('game.cancel'),
-                    });
-                    if (confirmed) {
-                      setSettings(prev => ({ ...prev, n_level: Math.max(1, prev.n_level - 1) }));
-                    }
-                  }
-                }
-
-                navigate(`/results/${latestSession.id}`, { state: { fromGame: true } });
-              } else {
-                navigate('/'); // Fallback if history is empty
-              }
-            } catch (e) {
-              console.error("Failed to fetch history after game:", e);
-              navigate('/'); // Fallback on error
-            }
-          }, 500);
+        navigate(`/results/${session.id}`, { state: { fromGame: true } });
+      } catch (e) {
+        console.error("Failed to finish game:", e);
+        navigate('/'); // Fallback on error
+      }
+    }, 500);
+  }, [contextSettings, navigate, setIsGameRunning, setSettings, t]);
+
+  // --- Session Clock Events ---
+  // The backend owns the turn schedule; this page only renders what it reports.
+  useEffect(() => {
+    const unlisteners = [
+      listen<GameState>('stimulus_onset', ({ payload }) => {
+        setGameState(payload);
+        resetTurnFeedback();
+      }),
+      listen<GameEvent>('turn_closed', ({ payload }) => {
+        const missedNow = payload.matches.filter(modality => !payload.user_response.matched.includes(modality));
+        if (missedNow.length > 0) {
+          setMissed(Object.fromEntries(missedNow.map(modality => [modality, true])));
         }
-      } catch (error) {
-        console.error("Failed to advance turn:", error);
-        setGameState(s => s ? { ...s, isRunning: false } : null);
+      }),
+      listen<GameSessionSummary>('session_finished', ({ payload }) => handleSessionFinished(payload)),
+      listen<AppError>('session_save_failed', async ({ payload }) => {
         setIsGameRunning(false);
-      }
-    }, gameSpeed);
+        await message(`${t('game.saveFailed')}\n\n${errorMessage(payload, t)}`, {
+          title: t('game.saveFailedTitle'),
+          kind: 'error',
+        });
+        navigate('/');
+      }),
+      listen<AppError>('checkpoint_failed', async ({ payload }) => {
+        // A failing disk fails every turn; one warning per session is enough
+        if (checkpointWarnedRef.current) return;
+        checkpointWarnedRef.current = true;
+        await message(`${t('game.checkpointFailed')}\n\n${errorMessage(payload, t)}`, {
+          title: t('game.checkpointFailedTitle'),
+          kind: 'warning',
+        });
+      }),
+    ];
 
     return () => {
-      if (gameLoopTimerRef.current) {
-        clearTimeout(gameLoopTimerRef.current);
-      }
+      unlisteners.forEach(unlisten => unlisten.then(fn => fn()));
     };
-  }, [gameState, navigate, isPaused]);
+  }, [handleSessionFinished, navigate, setIsGameRunning, t]);
+
+  // --- Interrupted Session ---
+  // Pick up a session the app was closed in the middle of. It comes back paused.
+  useEffect(() => {
+    invoke<GameState | null>('resume_session')
+      .then(restored => {
+        if (!restored) return;
+        setGameState(restored);
+        setIsPaused(restored.isPaused);
+        setIsGameRunning(true);
+      })
+      .catch(e => console.error('Failed to resume session:', e));
+  }, [setIsGameRunning]);
+
+  // --- Pause Sync Effect ---
+  useEffect(() => {
+    if (!gameState?.isRunning) return;
+    invoke(isPaused ? 'pause_game' : 'resume_game').catch(console.error);
+  }, [isPaused, gameState?.isRunning]);
 
   const handleStartGame = useCallback(async () => {
     setIsLoading(true);
     setIsTransitioning(false); // Reset transitioning state
+    checkpointWarnedRef.current = false;
     try {
       await invoke('start_game');
       const newState = await invoke<GameState>('get_game_state');
@@ -255,34 +284,64 @@ const GamePage: React.FC = () => {
       if (newState.isRunning) {
         setIsGameRunning(true);
       }
-      userResponseRef.current = { visual_match: false, audio_match: false };
-      setHasRespondedVisual(false);
-      setHasRespondedAudio(false);
-      setPositionFeedback(null);
-      setAudioFeedback(null);
+      setIsPaused(false);
+      resetTurnFeedback();
     } catch (error) {
       console.error("Failed to start game:", error);
+      await message(errorMessage(error, t), { title: t('game.startFailed'), kind: 'error' });
     } finally {
       setIsLoading(false);
     }
-  }, []);
-
-  const handlePositionMatch = () => {
-    if (!gameState?.isRunning || hasRespondedVisual) return;
-    
-    const isCorrect = gameState.isVisualMatch;
-    setPositionFeedback(isCorrect ? 'correct' : 'incorrect');
-    setHasRespondedVisual(true);
-    userResponseRef.current.visual_match = true;
+  }, [t]);
+
+  const resetTurnFeedback = () => {
+    setResponded({});
+    setFeedback({});
+    setAnswer('');
+  };
+
+  // A keypress can land just after the last turn closed; only other failures are worth logging
+  const logResponseError = (error: unknown) => {
+    if (!isAppError(error) || error.kind !== 'NotRunning') console.error(error);
+  };
+
+  const handleMatch = (modality: Modality) => {
+    if (!gameState?.isRunning || responded[modality]) return;
+
+    const isCorrect = gameState.upcomingMatches.includes(modality);
+    setFeedback(prev => ({ ...prev, [modality]: isCorrect ? 'correct' : 'incorrect' }));
+    setResponded(prev => ({ ...prev, [modality]: true }));
+    // The backend timestamps the press against the stimulus onset
+    invoke('register_response', { modality }).catch(logResponseError);
   };
 
-  const handleAudioMatch = () => {
-    if (!gameState?.isRunning || hasRespondedAudio) return;
+  // Digits and a leading minus type the arithmetic answer and Enter submits it.
+  // Returns whether the key belonged to the answer.
+  const handleAnswerKey = (key: string): boolean => {
+    if (/^[0-9]$/.test(key) || (key === '-' && answer === '')) {
+      if (!responded.Arithmetic && answer.length < MAX_ANSWER_LENGTH) setAnswer(answer + key);
+      return true;
+    }
+    if (key === 'Backspace') {
+      if (!responded.Arithmetic) setAnswer(answer.slice(0, -1));
+      return true;
+    }
+    if (key === 'Enter') {
+      submitAnswer();
+      return true;
+    }
+    return false;
+  };
+
+  const submitAnswer = () => {
+    const value = Number.parseInt(answer, 10);
+    if (!gameState?.isRunning || responded.Arithmetic || Number.isNaN(value)) return;
 
-    const isCorrect = gameState.isAudioMatch;
-    setAudioFeedback(isCorrect ? 'correct' : 'incorrect');
-    setHasRespondedAudio(true);
-    userResponseRef.current.audio_match = true;
+    setResponded(prev => ({ ...prev, Arithmetic: true }));
+    // Only the backend knows the operands, so the feedback waits for its verdict
+    invoke<boolean>('register_answer', { answer: value })
+      .then(correct => setFeedback(prev => ({ ...prev, Arithmetic: correct ? 'correct' : 'incorrect' })))
+      .catch(logResponseError);
   };
 
   const handlePauseToggle = () => {
@@ -290,7 +349,8 @@ const GamePage: React.FC = () => {
   };
 
   const handleQuitGame = () => {
-    // Stop the timer by setting isRunning to false
+    // Abort the session in the backend; it is saved as a partial session if the settings say so
+    invoke('abort_game').catch(console.error);
     setGameState(s => s ? { ...s, isRunning: false } : null);
     setIsGameRunning(false);
     // Navigate back to the home page
@@ -320,14 +380,17 @@ const GamePage: React.FC = () => {
     }
 
     // Active Game View
+    const modalities = gameState.settings.modalities ?? DEFAULT_MODALITIES;
+    const stimuli = gameState.currentStimulus?.stimuli ?? {};
+    const hasPositions = modalities.includes('Position');
+    const hasGlyph = modalities.some(modality => GLYPH_MODALITIES.includes(modality));
     return (
       <>
         <GameHeader
-          nLevel={gameState.settings.n_level}
+          nLevel={gameState.currentStimulus?.n_back ?? gameState.settings.n_level}
           turn={gameState.currentTurnIndex}
           totalTurns={gameState.settings.session_length}
-          visualAccuracy={gameState.visualAccuracy}
-          audioAccuracy={gameState.audioAccuracy}
+          accuracies={modalities.map(modality => [modality, gameState.modalityStats[modality]?.accuracy ?? 0])}
         />
         <div className="game-actions">
           <Button onClick={handlePauseToggle} variant="secondary" className="action-btn">
@@ -339,19 +402,31 @@ const GamePage: React.FC = () => {
             {t('game.quit')}
           </Button>
         </div>
-        <Grid
-          key={gameState.currentTurnIndex}
-          activeIndex={gameState.currentStimulus?.visual_stimulus.position ?? null}
-        />
+        {(hasPositions || hasGlyph) && (
+          <Grid
+            key={gameState.currentTurnIndex}
+            activeIndex={hasPositions
+              ? gameState.currentStimulus?.visual_stimulus?.position ?? null
+              : gameState.currentStimulus ? 0 : null}
+            layout={hasPositions ? gameState.settings.grid_layout : SINGLE_CELL}
+          >
+            {hasGlyph && <StimulusGlyph stimuli={stimuli} size="60%" />}
+          </Grid>
+        )}
+        {modalities.includes('Arithmetic') && (
+          <ArithmeticPanel
+            token={stimuli.Arithmetic}
+            answer={answer}
+            feedback={feedback.Arithmetic}
+            missed={missed.Arithmetic}
+          />
+        )}
         <GameControls
-          onPositionMatch={handlePositionMatch}
-          onAudioMatch={handleAudioMatch}
-          positionDisabled={hasRespondedVisual}
-          audioDisabled={hasRespondedAudio}
-          positionFeedback={positionFeedback}
-          audioFeedback={audioFeedback}
-          positionMissed={positionMissed}
-          audioMissed={audioMissed}
+          modalities={modalities.filter(modality => modality !== 'Arithmetic')}
+          onMatch={handleMatch}
+          responded={responded}
+          feedback={feedback}
+          missed={missed}
           animationDuration={gameState.settings.speed_ms / 2}
         />
       </>
@@ -1,7 +1,9 @@
 import React, { useState, useEffect, useMemo } from 'react';
 import { useParams, useLocation, Link } from 'react-router-dom';
 import { useTranslation, Trans } from 'react-i18next';
+import { TFunction } from 'i18next';
 import { invoke } from '@tauri-apps/api/core';
+import { confirm } from '@tauri-apps/plugin-dialog';
 import {
   BrainCircuit,
   Clock,
@@ -15,75 +17,90 @@ import {
   BookOpen,
   Play,
   ArrowLeft,
+  Trash2,
+  StickyNote,
+  Save,
+  TrendingUp,
 } from 'lucide-react';
 import Card from '../components/ui/Card';
 import Button from '../components/ui/Button';
 import ColorGridLegend from '../components/ColorGridLegend';
-import { getCalculatedStats } from '../utils/stats';
-import { useSettings, AuditoryStimulusSet, UserSettings as AppUserSettings } from '../contexts/SettingsContext';
+import ModalityIcon from '../components/ModalityIcon';
+import StimulusGlyph from '../components/StimulusGlyph';
+import { getCalculatedStats, statsFor, DEFAULT_MODALITIES, GameEvent, Modality, GameSessionSummary, GameSessionDetails as BaseSessionDetails } from '../utils/stats';
+import { useSettings, AuditoryStimulusSet, CustomAuditoryStimulusSet, UserSettings as AppUserSettings } from '../contexts/SettingsContext';
 import { useNavigate } from 'react-router-dom';
 import './HistoryDetailPage.css';
 
-// Local type definitions to override any caching issues
-interface AccuracyStats {
-  true_positives: number;
-  true_negatives: number;
-  false_positives: number;
-  false_negatives: number;
-}
-
-interface GameEvent {
-  turn_index: number;
-  stimulus: {
-    visual: number;
-    audio: string;
-  };
-  is_visual_match: boolean;
-  is_audio_match: boolean;
-  user_response: {
-    visual_match: boolean;
-    audio_match: boolean;
-  };
-}
-
-interface GameSessionDetails {
-  id: string;
-  timestamp: string;
+interface GameSessionDetails extends Omit<BaseSessionDetails, 'settings'> {
   settings: AppUserSettings;
-  visual_stats: AccuracyStats;
-  audio_stats: AccuracyStats;
-  event_history: GameEvent[];
 }
 
-
 const tianGanDiZhiMap: Record<string, string> = {
   jia: '甲', yi: '乙', bing: '丙', ding: '丁', wu: '戊', ji: '己', geng: '庚', xin: '辛', ren: '壬', gui: '癸',
   zi: '子', chou: '丑', yin: '寅', mao: '卯', chen: '辰', si: '巳', wu_branch: '午', wei: '未', shen: '申', you: '酉', xu: '戌', hai: '亥',
 };
 
-const renderEvent = (event: GameEvent, stimulusSet: AuditoryStimulusSet) => {
-  const visualMatchClass = event.is_visual_match ? 'match' : '';
-  const audioMatchClass = event.is_audio_match ? 'match' : '';
-  const visualUserClickClass = event.user_response.visual_match ? 'user-click-visual' : '';
-  const audioUserClickClass = event.user_response.audio_match ? 'user-click-audio' : '';
+// Typeset the backend's ASCII operations
+const ARITHMETIC_OPERATIONS: Record<string, string> = { '-': '−', '*': '×' };
 
-  return (
-    <div key={event.turn_index} className="event-item">
-      <div className="event-index">{event.turn_index + 1}</div>
-      <div className={`event-stimulus visual-stimulus ${visualMatchClass}`}>
-        <div className={`grid-cell-detail active-cell-detail cell-${event.stimulus.visual}`}>
-          <span className={visualUserClickClass}>{event.stimulus.visual}</span>
+// Position and audio keep the dual N-back labels
+const ModalityHeading: React.FC<{ modality: Modality; t: TFunction; size: number }> = ({ modality, t, size }) => {
+  if (modality === 'Position') return <><Eye size={size} /> {t('history.visual')}</>;
+  if (modality === 'Audio') return <><Ear size={size} /> {t('history.audio')}</>;
+  return <><ModalityIcon modality={modality} size={size} /> {t(`modalities.${modality.toLowerCase()}` as any)}</>;
+};
+
+const renderStimulus = (
+  event: GameEvent,
+  modality: Modality,
+  stimulusSet: AuditoryStimulusSet | CustomAuditoryStimulusSet,
+) => {
+  const token = event.stimulus[modality] ?? '';
+  const matchClass = event.matches.includes(modality) ? 'match' : '';
+  const responded = event.user_response.matched.includes(modality);
+
+  switch (modality) {
+    case 'Position':
+      return (
+        <div key={modality} className={`event-stimulus visual-stimulus ${matchClass}`}>
+          <div className={`grid-cell-detail active-cell-detail cell-${token}`}>
+            <span className={responded ? 'user-click-visual' : ''}>{token}</span>
+          </div>
         </div>
-      </div>
-      <div className={`event-stimulus audio-stimulus ${audioMatchClass} ${audioUserClickClass}`}>
-        {stimulusSet === AuditoryStimulusSet.TianGanDiZhi
-          ? tianGanDiZhiMap[event.stimulus.audio.toLowerCase()] || event.stimulus.audio
-          : event.stimulus.audio}
-      </div>
-    </div>
-  );
+      );
+    case 'Audio':
+    case 'Arithmetic':
+      return (
+        <div key={modality} className={`event-stimulus audio-stimulus ${matchClass} ${responded ? 'user-click-audio' : ''}`}>
+          {modality === 'Arithmetic'
+            ? token.replace(/[-*]/, op => ARITHMETIC_OPERATIONS[op])
+            : stimulusSet === AuditoryStimulusSet.TianGanDiZhi
+              ? tianGanDiZhiMap[token.toLowerCase()] || token
+              : token}
+        </div>
+      );
+    default:
+      return (
+        <div key={modality} className={`event-stimulus ${matchClass} ${responded ? 'user-click-glyph' : ''}`}>
+          <StimulusGlyph stimuli={{ [modality]: token }} size={36} />
+        </div>
+      );
+  }
 };
 
+const renderEvent = (
+  event: GameEvent,
+  modalities: Modality[],
+  stimulusSet: AuditoryStimulusSet | CustomAuditoryStimulusSet,
+  columns: React.CSSProperties,
+) => (
+  <div key={event.turn_index} className="event-item" style={columns}>
+    <div className="event-index">{event.turn_index + 1}</div>
+    {modalities.map(modality => renderStimulus(event, modality, stimulusSet))}
+  </div>
+);
+
 const HistoryDetailPage: React.FC = () => {
   const { sessionId } = useParams<{ sessionId: string }>();
   const { t } = useTranslation();
@@ -93,15 +110,14 @@ const HistoryDetailPage: React.FC = () => {
   const [session, setSession] = useState<GameSessionDetails | null>(null);
   const [isLoading, setIsLoading] = useState(true);
   const [error, setError] = useState<string | null>(null);
+  const [notes, setNotes] = useState('');
+  const [tagInput, setTagInput] = useState('');
 
-  const visualStats = useMemo(() => {
-    if (!session) return null;
-    return getCalculatedStats(session.visual_stats);
-  }, [session]);
+  const modalities = session?.settings.modalities ?? DEFAULT_MODALITIES;
 
-  const audioStats = useMemo(() => {
+  const modalityStats = useMemo(() => {
     if (!session) return null;
-    return getCalculatedStats(session.audio_stats);
+    return modalities.map(modality => [modality, getCalculatedStats(statsFor(session, modality))] as const);
   }, [session]);
 
   useEffect(() => {
@@ -113,6 +129,8 @@ const HistoryDetailPage: React.FC = () => {
         const result = await invoke<GameSessionDetails>('get_session_details', { sessionId });
         if (result) {
           setSession(result);
+          setNotes(result.notes);
+          setTagInput(result.tags.join(', '));
         } else {
           setError(t('historyDetail.notFound'));
         }
@@ -135,11 +153,12 @@ const HistoryDetailPage: React.FC = () => {
     return <div>{error}</div>;
   }
 
-  if (!session || !visualStats || !audioStats) {
+  if (!session || !modalityStats) {
     return <div>{t('historyDetail.noData')}</div>;
   }
 
   const isPostGameView = location.state?.fromGame === true;
+  const sequenceColumns = { gridTemplateColumns: `50px repeat(${modalities.length}, 1fr)` };
 
   const handlePlayAgain = async () => {
     if (!session) return;
@@ -150,6 +169,7 @@ const HistoryDetailPage: React.FC = () => {
       speed_ms: session.settings.speed_ms,
       session_length: session.settings.session_length,
       auditory_stimulus_set: session.settings.auditory_stimulus_set,
+      modalities: session.settings.modalities,
     });
     // Persist the settings
     await saveSettings();
@@ -157,6 +177,24 @@ const HistoryDetailPage: React.FC = () => {
     navigate('/');
   };
 
+  const handleSaveAnnotations = async () => {
+    // Accept full-width commas too, as typed with a Chinese input method
+    const tags = tagInput.split(/[,，]/);
+    const summary = await invoke<GameSessionSummary>('annotate_session', { sessionId: session.id, notes, tags });
+    setSession({ ...session, notes: summary.notes, tags: summary.tags });
+    setTagInput(summary.tags.join(', '));
+  };
+
+  const handleDelete = async () => {
+    const confirmed = await confirm(t('historyDetail.deleteConfirmation'), {
+      title: t('historyDetail.delete'),
+    });
+    if (confirmed) {
+      await invoke('delete_game_session', { sessionId: session.id });
+      navigate('/history');
+    }
+  };
+
   return (
     <div className="history-detail-container">
       <div className="history-detail-header">
@@ -171,6 +209,10 @@ const HistoryDetailPage: React.FC = () => {
           <Play size={16} className="btn-icon" />
           {t('historyDetail.playAgainSameSettings')}
         </Button>
+        <Button variant="danger" onClick={handleDelete}>
+          <Trash2 size={16} className="btn-icon" />
+          {t('historyDetail.delete')}
+        </Button>
       </div>
 
       {isPostGameView && (
@@ -189,31 +231,34 @@ const HistoryDetailPage: React.FC = () => {
       <Card className="detail-summary-card">
         <div className="summary-item"><BrainCircuit size={18} /><strong>{t('history.nLevel')}:</strong> {session.settings.n_level}</div>
         <div className="summary-item"><Clock size={18} /><strong>{t('history.speed')}:</strong> {session.settings.speed_ms}ms</div>
-        <div className="summary-item"><ListChecks size={18} /><strong>{t('history.sessionLength')}:</strong> {session.settings.session_length}</div>
+        {session.staircase_threshold !== null && (
+          <div className="summary-item"><TrendingUp size={18} /><strong>{t('historyDetail.staircaseThreshold')}:</strong> {session.staircase_threshold.toFixed(2)}</div>
+        )}
+        <div className="summary-item"><ListChecks size={18} /><strong>{t('history.sessionLength')}:</strong> {session.settings.session_length}{session.outcome === 'Aborted' && ` (${t('history.abortedAfter', { turns: session.turns_played })})`}</div>
         <div className="summary-item">
           <FileAudio size={18} />
           <strong>{t('settings.coreTraining.auditoryStimulusSet')}:</strong>
-          &nbsp;{t(`settings.coreTraining.auditoryStimulusSets.${session.settings.auditory_stimulus_set.toLowerCase()}` as any, {
-            defaultValue: session.settings.auditory_stimulus_set
-          })}
+          &nbsp;{typeof session.settings.auditory_stimulus_set === 'string'
+            ? t(`settings.coreTraining.auditoryStimulusSets.${session.settings.auditory_stimulus_set.toLowerCase()}` as any, {
+                defaultValue: session.settings.auditory_stimulus_set
+              })
+            : session.settings.auditory_stimulus_set.Custom}
         </div>
       </Card>
 
       <Card className="stats-card">
-        <div className="stats-column">
-          <h3 className="stats-title"><Eye size={20} /> {t('history.visual')}</h3>
-          <div className="stat-item"><span>{t('historyDetail.stats.hitRate')}:</span> <span>{visualStats.hitRate.toFixed(1)}%</span></div>
-          <div className="stat-item"><span>{t('historyDetail.stats.missRate')}:</span> <span>{visualStats.missRate.toFixed(1)}%</span></div>
-          <div className="stat-item"><span>{t('historyDetail.stats.faRate')}:</span> <span>{visualStats.falseAlarmRate.toFixed(1)}%</span></div>
-          <div className="stat-item"><span>{t('historyDetail.stats.crRate')}:</span> <span>{visualStats.correctRejectionRate.toFixed(1)}%</span></div>
-        </div>
-        <div className="stats-column">
-          <h3 className="stats-title"><Ear size={20} /> {t('history.audio')}</h3>
-          <div className="stat-item"><span>{t('historyDetail.stats.hitRate')}:</span> <span>{audioStats.hitRate.toFixed(1)}%</span></div>
-          <div className="stat-item"><span>{t('historyDetail.stats.missRate')}:</span> <span>{audioStats.missRate.toFixed(1)}%</span></div>
-          <div className="stat-item"><span>{t('historyDetail.stats.faRate')}:</span> <span>{audioStats.falseAlarmRate.toFixed(1)}%</span></div>
-          <div className="stat-item"><span>{t('historyDetail.stats.crRate')}:</span> <span>{audioStats.correctRejectionRate.toFixed(1)}%</span></div>
-        </div>
+        {modalityStats.map(([modality, stats]) => (
+          <div key={modality} className="stats-column">
+            <h3 className="stats-title"><ModalityHeading modality={modality} t={t} size={20} /></h3>
+            <div className="stat-item"><span>{t('historyDetail.stats.hitRate')}:</span> <span>{stats.hitRate.toFixed(1)}%</span></div>
+            <div className="stat-item"><span>{t('historyDetail.stats.missRate')}:</span> <span>{stats.missRate.toFixed(1)}%</span></div>
+            <div className="stat-item"><span>{t('historyDetail.stats.faRate')}:</span> <span>{stats.falseAlarmRate.toFixed(1)}%</span></div>
+            <div className="stat-item"><span>{t('historyDetail.stats.crRate')}:</span> <span>{stats.correctRejectionRate.toFixed(1)}%</span></div>
+            {session.sdt[modality] && (
+              <div className="stat-item"><span>{t('historyDetail.stats.dPrime')}:</span> <span>{session.sdt[modality]?.d_prime.toFixed(2)}</span></div>
+            )}
+          </div>
+        ))}
       </Card>
 
       <Card className="legends-card">
@@ -238,15 +283,38 @@ const HistoryDetailPage: React.FC = () => {
 
       <h2 className="page-subtitle"><BookOpen size={22} /> {t('historyDetail.sequence')}</h2>
       <Card className="sequence-card">
-        <div className="sequence-header">
+        <div className="sequence-header" style={sequenceColumns}>
           <div>#</div>
-          <div><Eye size={18} /> {t('history.visual')}</div>
-          <div><Ear size={18} /> {t('history.audio')}</div>
+          {modalities.map(modality => (
+            <div key={modality}><ModalityHeading modality={modality} t={t} size={18} /></div>
+          ))}
         </div>
         <div className="sequence-grid">
-          {session.event_history.map((event) => renderEvent(event, session.settings.auditory_stimulus_set))}
+          {session.event_history.map((event) =>
+            renderEvent(event, modalities, session.settings.auditory_stimulus_set, sequenceColumns))}
         </div>
       </Card>
+
+      <Card className="annotations-card">
+        <h2 className="card-title"><StickyNote size={20} />{t('historyDetail.annotations')}</h2>
+        <textarea
+          className="notes-input"
+          placeholder={t('historyDetail.notesPlaceholder')}
+          value={notes}
+          onChange={(e) => setNotes(e.target.value)}
+        />
+        <input
+          type="text"
+          className="select-input"
+          placeholder={t('historyDetail.tagsPlaceholder')}
+          value={tagInput}
+          onChange={(e) => setTagInput(e.target.value)}
+        />
+        <Button type="button" variant="secondary" onClick={handleSaveAnnotations}>
+          <Save size={16} className="btn-icon" />
+          {t('historyDetail.saveAnnotations')}
+        </Button>
+      </Card>
     </div>
   );
 };
@@ -24,9 +24,10 @@ import {
   BrainCircuit,
   Eye,
   Ear,
+  Tag,
 } from 'lucide-react';
 import Card from '../components/ui/Card';
-import { GameSessionSummary, transformHistoryData } from '../utils/stats';
+import { GameSessionSummary, HistoryQuery, HistoryQueryResult, transformHistoryData } from '../utils/stats';
 import { useSettings } from '../contexts/SettingsContext';
 import './HistoryPage.css';
 
@@ -62,14 +63,23 @@ const HistoryPage: React.FC = () => {
   const { t } = useTranslation();
   const [history, setHistory] = useState<GameSessionSummary[]>([]);
   const [isLoading, setIsLoading] = useState(true);
+  const [tags, setTags] = useState<string[]>([]);
+  const [selectedTag, setSelectedTag] = useState('');
   const themeColors = useThemeColors();
 
+  useEffect(() => {
+    invoke<string[]>('list_session_tags')
+      .then(setTags)
+      .catch(error => console.error("Failed to fetch session tags:", error));
+  }, []);
+
   useEffect(() => {
     const fetchHistory = async () => {
       setIsLoading(true);
       try {
-        const fetchedHistory = await invoke<GameSessionSummary[]>('get_game_history');
-        setHistory(fetchedHistory);
+        const query: HistoryQuery = { tag: selectedTag || null, order: 'OldestFirst' };
+        const result = await invoke<HistoryQueryResult>('query_history', { query });
+        setHistory(result.summaries);
       } catch (error) {
         console.error("Failed to fetch game history:", error);
       } finally {
@@ -77,13 +87,10 @@ const HistoryPage: React.FC = () => {
       }
     };
     fetchHistory();
-  }, []);
+  }, [selectedTag]);
 
-  const sortedHistory = useMemo(() => {
-    return [...history].sort((a, b) => new Date(a.timestamp).getTime() - new Date(b.timestamp).getTime());
-  }, [history]);
-
-  const chartData = useMemo(() => transformHistoryData(sortedHistory), [sortedHistory]);
+  // The history is fetched oldest first, the order the charts plot it in
+  const chartData = useMemo(() => transformHistoryData(history), [history]);
 
   const nLevelChangePoints = useMemo(() => {
     return chartData.reduce((acc, session, index, arr) => {
@@ -138,6 +145,23 @@ const HistoryPage: React.FC = () => {
     <div className="history-container">
       <h1 className="page-title">{t('history.title')}</h1>
 
+      {tags.length > 0 && (
+        <div className="tag-filter">
+          <Tag size={18} />
+          <select
+            id="tag-filter"
+            className="select-input"
+            value={selectedTag}
+            onChange={(e) => setSelectedTag(e.target.value)}
+          >
+            <option value="">{t('history.allTags')}</option>
+            {tags.map(tag => (
+              <option key={tag} value={tag}>{tag}</option>
+            ))}
+          </select>
+        </div>
+      )}
+
       <div className="charts-grid">
         <div className="chart-wrapper">
           <div className="chart-header">
@@ -1,21 +1,30 @@
 import React, { useState, useEffect, useRef } from 'react';
 import { useTranslation } from 'react-i18next';
 import { invoke } from '@tauri-apps/api/core';
-import { save, confirm } from '@tauri-apps/plugin-dialog';
-import { writeTextFile } from '@tauri-apps/plugin-fs';
-import { useSettings, AuditoryStimulusSet } from '../contexts/SettingsContext';
+import { save, open, confirm, message } from '@tauri-apps/plugin-dialog';
+import { readTextFile, writeTextFile } from '@tauri-apps/plugin-fs';
+import { useSettings, AuditoryStimulusSet, CustomAuditoryStimulusSet, DifficultyPolicy, DifficultyPolicyName, DEFAULT_STAIRCASE, KEY_BINDINGS, KeyBindingSetting } from '../contexts/SettingsContext';
 import Card from '../components/ui/Card';
 import Button from '../components/ui/Button';
 import Switch from '../components/ui/Switch';
 import SettingItem from '../components/SettingItem';
 import KeybindingSettings from '../components/KeybindingSettings';
+import ProfileSettings from '../components/ProfileSettings';
+import BackupSettings from '../components/BackupSettings';
+import ModalityIcon from '../components/ModalityIcon';
+import { DEFAULT_MODALITIES, MODALITIES, Modality } from '../utils/stats';
+import { errorMessage } from '../utils/errors';
 import Modal from '../components/ui/Modal'; // Import the new Modal component
 import {
   Sliders,
+  Users,
   Monitor,
   Keyboard,
   Database,
+  ArrowRightLeft,
+  ArchiveRestore,
   Download,
+  Upload,
   Trash2,
   Code,
   BrainCircuit,
@@ -26,10 +35,11 @@ import {
   Moon,
   Beaker,
   ZapOff,
-  Grid,
-  Volume2,
   Bot,
   FileAudio,
+  Save,
+  TrendingUp,
+  Layers,
 } from 'lucide-react';
 import './SettingsPage.css';
 
@@ -44,14 +54,46 @@ const MIN_SESSION_BASE = 20;
 const SESSION_LENGTH_FACTOR = 5;
 const MAX_SESSION_LENGTH = 100;
 
+// Policies offered in the settings, with the parameters the parameterised ones start from
+const DIFFICULTY_POLICIES: Record<DifficultyPolicyName, DifficultyPolicy> = {
+  Jaeggi: 'Jaeggi',
+  BrainWorkshop: 'BrainWorkshop',
+  DPrimeStaircase: { DPrimeStaircase: { raise_at: 2.0, lower_below: 1.0 } },
+  SpeedRamp: { SpeedRamp: { step_ms: 250, fastest_ms: MIN_SPEED_NORMAL, slowest_ms: MAX_SPEED } },
+};
+
+// --- Data Structures mirroring Rust backend ---
+type StorageBackend = 'Sled' | 'Sqlite';
+
+interface StorageBackends {
+  active: StorageBackend;
+  compiled: StorageBackend[];
+}
+
 const SettingsPage: React.FC = () => {
   const { t } = useTranslation();
   const { settings, setSettings, saveSettings, resetSettings, isLoading } = useSettings();
   const [isGeneratingHistory, setIsGeneratingHistory] = useState(false);
-  const [isListening, setIsListening] = useState<string | null>(null);
+  const [isListening, setIsListening] = useState<KeyBindingSetting | null>(null);
+  const [trialRange, setTrialRange] = useState({ from: '', to: '' });
+  const [customStimulusSets, setCustomStimulusSets] = useState<{ id: string; name: string }[]>([]);
+  const [backends, setBackends] = useState<StorageBackends | null>(null);
+
+  useEffect(() => {
+    invoke<{ id: string; name: string }[]>('list_stimulus_sets')
+      .then(setCustomStimulusSets)
+      .catch(err => console.error('Failed to load custom stimulus sets:', err));
+    invoke<StorageBackends>('get_storage_backends')
+      .then(setBackends)
+      .catch(err => console.error('Failed to load storage backends:', err));
+  }, []);
   const isInitialMount = useRef(true);
 
-  const { n_level, speed_ms, session_length, auditory_stimulus_set, theme, language, followSystemTheme, allowFastSpeed, reduceMotion, positionKeys, audioKeys, autoAdjustNLevel, highAccuracyThreshold, lowAccuracyThreshold } = settings;
+  const modalities = settings.modalities ?? DEFAULT_MODALITIES;
+  const { n_level, speed_ms, session_length, auditory_stimulus_set, aborted_sessions, theme, language, followSystemTheme, allowFastSpeed, reduceMotion, autoAdjustNLevel, difficulty_policy, staircase } = settings;
+  const policyName: DifficultyPolicyName = typeof difficulty_policy === 'object'
+    ? Object.keys(difficulty_policy)[0] as DifficultyPolicyName
+    : difficulty_policy ?? 'Jaeggi';
 
   const minSpeed = allowFastSpeed ? MIN_SPEED_FAST : MIN_SPEED_NORMAL;
   const minSessionLength = Math.max(MIN_SESSION_BASE, SESSION_LENGTH_FACTOR * n_level);
@@ -90,17 +132,10 @@ const SettingsPage: React.FC = () => {
       const newKey = e.key;
 
       setSettings(prev => {
-        const currentKeys = isListening === 'position' ? prev.positionKeys : prev.audioKeys;
-        if (currentKeys.includes(newKey)) {
-          setIsListening(null);
+        if (prev[isListening].includes(newKey)) {
           return prev;
         }
-
-        if (isListening === 'position') {
-          return { ...prev, positionKeys: [...prev.positionKeys, newKey] };
-        } else {
-          return { ...prev, audioKeys: [...prev.audioKeys, newKey] };
-        }
+        return { ...prev, [isListening]: [...prev[isListening], newKey] };
       });
 
       setIsListening(null);
@@ -150,24 +185,28 @@ const SettingsPage: React.FC = () => {
     });
   };
 
-  const handleAddKey = (type: 'position' | 'audio') => {
-    setIsListening(type);
+  const handleAddKey = (binding: KeyBindingSetting) => {
+    setIsListening(binding);
   };
 
-  const handleRemoveKey = (type: 'position' | 'audio', index: number) => {
+  const handleRemoveKey = (binding: KeyBindingSetting, index: number) => {
     setSettings(prev => {
-      if (type === 'position') {
-        const newKeys = [...prev.positionKeys];
-        newKeys.splice(index, 1);
-        return { ...prev, positionKeys: newKeys };
-      } else {
-        const newKeys = [...prev.audioKeys];
-        newKeys.splice(index, 1);
-        return { ...prev, audioKeys: newKeys };
-      }
+      const newKeys = [...prev[binding]];
+      newKeys.splice(index, 1);
+      return { ...prev, [binding]: newKeys };
     });
   };
 
+  // Keeps the backend's modality order; the last selected modality cannot be turned off
+  const handleToggleModality = (modality: Modality) => {
+    const selected = modalities.includes(modality)
+      ? modalities.filter(m => m !== modality)
+      : MODALITIES.filter(m => m === modality || modalities.includes(m));
+    if (selected.length > 0) {
+      handleSettingChange('modalities', selected);
+    }
+  };
+
   const handleExport = async () => {
     try {
       const csvData = await invoke<string>('export_history_as_csv');
@@ -185,7 +224,73 @@ const SettingsPage: React.FC = () => {
       }
     } catch (error) {
       console.error("Failed to export history:", error);
-      // Optionally show an error message to the user
+      await message(errorMessage(error, t), { title: t('settings.dataManagement.exportFailed'), kind: 'error' });
+    }
+  };
+
+  const handleExportTrials = async () => {
+    try {
+      // Date inputs are local calendar days; the range covers both of them in full
+      const csvData = await invoke<string>('export_trials_as_csv', {
+        from: trialRange.from ? new Date(`${trialRange.from}T00:00:00`).toISOString() : null,
+        to: trialRange.to ? new Date(`${trialRange.to}T23:59:59.999`).toISOString() : null,
+      });
+      const filePath = await save({
+        title: 'Save Trial Data',
+        defaultPath: 'nback-trials.csv',
+        filters: [{
+          name: 'CSV',
+          extensions: ['csv']
+        }],
+      });
+
+      if (filePath) {
+        await writeTextFile(filePath, csvData);
+      }
+    } catch (error) {
+      console.error("Failed to export trials:", error);
+      await message(errorMessage(error, t), { title: t('settings.dataManagement.exportFailed'), kind: 'error' });
+    }
+  };
+
+  const handleExportArchive = async () => {
+    try {
+      const archive = await invoke<string>('export_history_as_json');
+      const filePath = await save({
+        title: 'Save Game History',
+        defaultPath: 'nback-history.json',
+        filters: [{
+          name: 'JSON',
+          extensions: ['json']
+        }],
+      });
+
+      if (filePath) {
+        await writeTextFile(filePath, archive);
+      }
+    } catch (error) {
+      console.error("Failed to export history archive:", error);
+      await message(errorMessage(error, t), { title: t('settings.dataManagement.exportFailed'), kind: 'error' });
+    }
+  };
+
+  const handleImport = async () => {
+    const filePath = await open({
+      filters: [{
+        name: 'JSON',
+        extensions: ['json']
+      }],
+    });
+    if (!filePath) return;
+
+    try {
+      const archive = await readTextFile(filePath);
+      const report = await invoke<{ imported: number; duplicates: number }>('import_history', { archive });
+      await message(t('settings.dataManagement.importResult', report), {
+        title: t('settings.dataManagement.import'),
+      });
+    } catch (error) {
+      await message(errorMessage(error, t), { title: t('settings.dataManagement.importFailed'), kind: 'error' });
     }
   };
 
@@ -199,6 +304,22 @@ const SettingsPage: React.FC = () => {
     }
   };
 
+  const handleMigrate = async (to: StorageBackend) => {
+    const confirmed = await confirm(t('settings.dataManagement.migrateConfirmation', { backend: to }), {
+      title: t('settings.dataManagement.migrateTitle'),
+    });
+    if (!confirmed) return;
+    try {
+      const report = await invoke<{ profiles: number; sessions: number }>('migrate_storage', { to });
+      setBackends(prev => prev && { ...prev, active: to });
+      await message(t('settings.dataManagement.migrateResult', { ...report, backend: to }), {
+        title: t('settings.dataManagement.migrateTitle'),
+      });
+    } catch (error) {
+      await message(errorMessage(error, t), { title: t('settings.dataManagement.migrateFailed'), kind: 'error' });
+    }
+  };
+
   const handleGenerateHistory = async () => {
     setIsGeneratingHistory(true);
     try {
@@ -231,6 +352,14 @@ const SettingsPage: React.FC = () => {
 
       <h1 className="page-title">{t('settings.title')}</h1>
       <div>
+        <Card className="settings-card">
+          <h2 className="card-title">
+            <Users size={20} />
+            {t('settings.profiles.title')}
+          </h2>
+          <ProfileSettings />
+        </Card>
+
         <Card className="settings-card">
           <h2 className="card-title">
             <Sliders size={20} />
@@ -294,18 +423,67 @@ const SettingsPage: React.FC = () => {
               <select
                 id="auditory-stimulus-set"
                 className="select-input"
-                value={auditory_stimulus_set}
-                onChange={(e) => handleSettingChange('auditory_stimulus_set', e.target.value as AuditoryStimulusSet)}
+                value={typeof auditory_stimulus_set === 'string' ? auditory_stimulus_set : `custom:${auditory_stimulus_set.Custom}`}
+                onChange={(e) => {
+                  const value = e.target.value;
+                  const set: AuditoryStimulusSet | CustomAuditoryStimulusSet = value.startsWith('custom:')
+                    ? { Custom: value.slice('custom:'.length) }
+                    : value as AuditoryStimulusSet;
+                  handleSettingChange('auditory_stimulus_set', set);
+                }}
               >
                 <option value={AuditoryStimulusSet.AllLetters}>{t('settings.coreTraining.auditoryStimulusSets.allLetters')}</option>
                 <option value={AuditoryStimulusSet.NonConfusingLetters}>{t('settings.coreTraining.auditoryStimulusSets.nonConfusingLetters')}</option>
                 <option value={AuditoryStimulusSet.TianGanDiZhi}>{t('settings.coreTraining.auditoryStimulusSets.tiangandizhi')}</option>
+                {customStimulusSets.map(set => (
+                  <option key={set.id} value={`custom:${set.id}`}>{set.name}</option>
+                ))}
               </select>
-              <p className="setting-description">
-                {t(`settings.coreTraining.auditoryStimulusSetDescriptions.${auditory_stimulus_set}`)}
-              </p>
+              {typeof auditory_stimulus_set === 'string' && (
+                <p className="setting-description">
+                  {t(`settings.coreTraining.auditoryStimulusSetDescriptions.${auditory_stimulus_set}`)}
+                </p>
+              )}
+            </>
+          </SettingItem>
+          <SettingItem
+            icon={<Layers size={18} />}
+            label={t('settings.coreTraining.modalities')}
+          >
+            <>
+              <div className="modality-picker">
+                {MODALITIES.map(modality => {
+                  const isSelected = modalities.includes(modality);
+                  return (
+                    <Button
+                      key={modality}
+                      type="button"
+                      variant={isSelected ? 'primary' : 'secondary'}
+                      aria-pressed={isSelected}
+                      disabled={isSelected && modalities.length === 1}
+                      onClick={() => handleToggleModality(modality)}
+                    >
+                      <ModalityIcon modality={modality} size={16} className="btn-icon" />
+                      {t(`modalities.${modality.toLowerCase()}` as any)}
+                    </Button>
+                  );
+                })}
+              </div>
+              <p className="setting-description">{t('settings.coreTraining.modalitiesDescription')}</p>
             </>
           </SettingItem>
+          <SettingItem
+            isRow
+            icon={<Save size={18} />}
+            label={t('settings.coreTraining.keepAbortedSessions')}
+          >
+            <Switch
+              id="keep-aborted-sessions-switcher"
+              label=""
+              checked={aborted_sessions === 'KeepPartial'}
+              onChange={(e) => handleSettingChange('aborted_sessions', e.target.checked ? 'KeepPartial' : 'Discard')}
+            />
+          </SettingItem>
         </Card>
 
         <Card className="settings-card">
@@ -327,34 +505,40 @@ const SettingsPage: React.FC = () => {
           </SettingItem>
           <SettingItem
             icon={<BrainCircuit size={18} />}
-            label={t('settings.intelligentAdjustment.highAccuracyThreshold', { value: highAccuracyThreshold })}
+            label={t('settings.intelligentAdjustment.policy')}
           >
-            <input
-              type="range"
-              id="high-accuracy-threshold"
-              min={50}
-              max={100}
-              value={highAccuracyThreshold}
-              onChange={(e) => handleSettingChange('highAccuracyThreshold', Number(e.target.value))}
-              className="slider"
-              disabled={!autoAdjustNLevel}
-            />
+            <>
+              <select
+                id="difficulty-policy"
+                className="select-input"
+                value={policyName}
+                onChange={(e) => handleSettingChange('difficulty_policy', DIFFICULTY_POLICIES[e.target.value as DifficultyPolicyName])}
+                disabled={!autoAdjustNLevel}
+              >
+                {(Object.keys(DIFFICULTY_POLICIES) as DifficultyPolicyName[]).map(name => (
+                  <option key={name} value={name}>{t(`settings.intelligentAdjustment.policies.${name}`)}</option>
+                ))}
+              </select>
+              <p className="setting-description">
+                {t(`settings.intelligentAdjustment.policyDescriptions.${policyName}`)}
+              </p>
+            </>
           </SettingItem>
           <SettingItem
-            icon={<BrainCircuit size={18} />}
-            label={t('settings.intelligentAdjustment.lowAccuracyThreshold', { value: lowAccuracyThreshold })}
+            isRow
+            icon={<TrendingUp size={18} />}
+            label={t('settings.intelligentAdjustment.staircase')}
           >
-            <input
-              type="range"
-              id="low-accuracy-threshold"
-              min={0}
-              max={50}
-              value={lowAccuracyThreshold}
-              onChange={(e) => handleSettingChange('lowAccuracyThreshold', Number(e.target.value))}
-              className="slider"
-              disabled={!autoAdjustNLevel}
+            <Switch
+              id="staircase-switcher"
+              label=""
+              checked={!!staircase}
+              onChange={(e) => handleSettingChange('staircase', e.target.checked ? DEFAULT_STAIRCASE : null)}
             />
           </SettingItem>
+          {staircase && (
+            <p className="setting-description">{t('settings.intelligentAdjustment.staircaseDescription')}</p>
+          )}
         </Card>
 
         <Card className="settings-card">
@@ -433,20 +617,21 @@ const SettingsPage: React.FC = () => {
             <Keyboard size={20} />
             {t('settings.keybindings.title')}
           </h2>
-          <KeybindingSettings
-            icon={<Grid size={18} />}
-            title={t('settings.keybindings.position')}
-            keys={positionKeys}
-            onAdd={() => handleAddKey('position')}
-            onRemove={(index) => handleRemoveKey('position', index)}
-          />
-          <KeybindingSettings
-            icon={<Volume2 size={18} />}
-            title={t('settings.keybindings.audio')}
-            keys={audioKeys}
-            onAdd={() => handleAddKey('audio')}
-            onRemove={(index) => handleRemoveKey('audio', index)}
-          />
+          {MODALITIES.map(modality => {
+            const binding = KEY_BINDINGS[modality];
+            if (!binding) return null;
+            return (
+              <KeybindingSettings
+                key={modality}
+                icon={<ModalityIcon modality={modality} size={18} />}
+                title={t(`settings.keybindings.${modality.toLowerCase()}` as any)}
+                keys={settings[binding]}
+                onAdd={() => handleAddKey(binding)}
+                onRemove={(index) => handleRemoveKey(binding, index)}
+              />
+            );
+          })}
+          <p className="setting-description">{t('settings.keybindings.arithmetic')}</p>
         </Card>
 
         <Card className="settings-card">
@@ -459,6 +644,40 @@ const SettingsPage: React.FC = () => {
               <Download size={16} className="btn-icon" />
               {t('settings.dataManagement.export')}
             </Button>
+            <div className="trial-export">
+              <input
+                type="date"
+                className="select-input"
+                aria-label={t('settings.dataManagement.trialsFrom')}
+                value={trialRange.from}
+                onChange={(e) => setTrialRange(prev => ({ ...prev, from: e.target.value }))}
+              />
+              <input
+                type="date"
+                className="select-input"
+                aria-label={t('settings.dataManagement.trialsTo')}
+                value={trialRange.to}
+                onChange={(e) => setTrialRange(prev => ({ ...prev, to: e.target.value }))}
+              />
+              <Button type="button" variant="secondary" onClick={handleExportTrials}>
+                <Download size={16} className="btn-icon" />
+                {t('settings.dataManagement.exportTrials')}
+              </Button>
+            </div>
+            <Button type="button" variant="secondary" onClick={handleExportArchive}>
+              <Download size={16} className="btn-icon" />
+              {t('settings.dataManagement.exportArchive')}
+            </Button>
+            <Button type="button" variant="secondary" onClick={handleImport}>
+              <Upload size={16} className="btn-icon" />
+              {t('settings.dataManagement.import')}
+            </Button>
+            {backends?.compiled.filter(backend => backend !== backends.active).map(backend => (
+              <Button key={backend} type="button" variant="secondary" onClick={() => handleMigrate(backend)}>
+                <ArrowRightLeft size={16} className="btn-icon" />
+                {t('settings.dataManagement.migrate', { from: backends.active, backend })}
+              </Button>
+            ))}
             <Button type="button" variant="danger" onClick={handleReset}>
               <Trash2 size={16} className="btn-icon" />
               {t('settings.dataManagement.reset')}
@@ -466,6 +685,14 @@ const SettingsPage: React.FC = () => {
           </div>
         </Card>
 
+        <Card className="settings-card">
+          <h2 className="card-title">
+            <ArchiveRestore size={20} />
+            {t('settings.backups.title')}
+          </h2>
+          <BackupSettings />
+        </Card>
+
         <Card className="settings-card">
           <h2 className="card-title">
             <Code size={20} />
@@ -0,0 +1,21 @@
+import { TFunction } from 'i18next';
+
+// --- Data Structures mirroring Rust backend ---
+// Every command rejects with one of these
+export type AppError =
+  | { kind: 'Storage'; message: string }
+  | { kind: 'Serialization'; message: string }
+  | { kind: 'InvalidSettings'; message: string }
+  | { kind: 'NotRunning' }
+  | { kind: 'NotFound'; message: string }
+  | { kind: 'Invalid'; message: string };
+
+export const isAppError = (error: unknown): error is AppError =>
+  typeof error === 'object' && error !== null && 'kind' in error;
+
+// A sentence for the kind of error, followed by the backend's details
+export const errorMessage = (error: unknown, t: TFunction): string => {
+  if (!isAppError(error)) return String(error);
+  const summary = t(`errors.${error.kind}`);
+  return 'message' in error ? `${summary}\n\n${error.message}` : summary;
+};
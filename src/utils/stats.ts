@@ -1,3 +1,5 @@
+import { AuditoryStimulusSet, CustomAuditoryStimulusSet, GridLayout } from '../contexts/SettingsContext';
+
 // --- Data Structures mirroring Rust backend ---
 export interface AccuracyStats {
   true_positives: number;
@@ -10,28 +12,90 @@ export interface UserSettings {
   n_level: number;
   speed_ms: number;
   session_length: number;
+  grid_layout?: GridLayout;
+  modalities?: Modality[];
 }
 
+// Sessions saved before grid layouts existed used a 3x3 grid
+export const positionCount = (layout: GridLayout = { Square: { size: 3 } }): number => {
+  if ('Square' in layout) return layout.Square.size * layout.Square.size;
+  if ('Rect' in layout) return layout.Rect.rows * layout.Rect.cols;
+  return layout.Ring.positions;
+};
+
 export interface GameSessionSummary {
   id: string;
   timestamp: string; // ISO 8601 string
   settings: UserSettings;
-  visual_stats: AccuracyStats;
-  audio_stats: AccuracyStats;
+  outcome: 'Completed' | 'Aborted';
+  turns_played: number; // Shorter than the session length for aborted sessions
+  stats: Partial<Record<Modality, AccuracyStats>>;
+  // Keyed by the N each turn asked for
+  stats_by_n_back: Record<number, Partial<Record<Modality, AccuracyStats>>>;
+  reaction_times: Partial<Record<Modality, ReactionTimeStats>>;
+  // Only present for modalities with both match and non-match turns
+  sdt: Partial<Record<Modality, SdtMetrics>>;
+  // Mean level at the staircase's reversals, if it ran and reversed at all
+  staircase_threshold: number | null;
+  notes: string;
+  tags: string[];
+}
+
+// Every field is optional; the backend defaults to the whole history, newest first
+export interface HistoryQuery {
+  from?: string | null; // ISO 8601 string, inclusive
+  to?: string | null; // ISO 8601 string, inclusive
+  n_level?: number | null;
+  stimulus_set?: AuditoryStimulusSet | CustomAuditoryStimulusSet | null;
+  tag?: string | null;
+  order?: 'NewestFirst' | 'OldestFirst';
+  offset?: number;
+  limit?: number | null;
+}
+
+export interface HistoryQueryResult {
+  summaries: GameSessionSummary[];
+  total: number; // Matching sessions across all pages
+}
+
+export interface SdtMetrics {
+  d_prime: number;
+  criterion: number;
+  beta: number;
+  a_prime: number;
+  b_double_prime: number;
+}
+
+export interface ReactionTimeStats {
+  count: number;
+  mean_ms: number;
+  median_ms: number;
 }
 
+export type Modality = 'Position' | 'Audio' | 'Color' | 'Shape' | 'Image' | 'Arithmetic';
+
+// In the backend's order, which is also the order the game and history pages list them in
+export const MODALITIES: Modality[] = ['Position', 'Audio', 'Color', 'Shape', 'Image', 'Arithmetic'];
+
+// Sessions and settings saved before the modality picker existed were dual N-back
+export const DEFAULT_MODALITIES: Modality[] = ['Position', 'Audio'];
+
 export interface GameEvent {
   turn_index: number;
-  stimulus: {
-    visual: number;
-    audio: string;
-  };
-  is_visual_match: boolean;
-  is_audio_match: boolean;
+  stimulus: Partial<Record<Modality, string>>;
+  n_back: number;
+  matches: Modality[];
+  lures: Modality[];
   user_response: {
-    visual_match: boolean;
-    audio_match: boolean;
+    matched: Modality[];
   };
+  reaction_times_ms: Partial<Record<Modality, number>>;
+  pauses: PauseInterval[];
+}
+
+export interface PauseInterval {
+  started_at: string; // ISO 8601 string
+  duration_ms: number;
 }
 
 export interface GameSessionDetails extends GameSessionSummary {
@@ -40,6 +104,16 @@ export interface GameSessionDetails extends GameSessionSummary {
 
 
 // --- Helper Functions ---
+const EMPTY_STATS: AccuracyStats = {
+  true_positives: 0,
+  true_negatives: 0,
+  false_positives: 0,
+  false_negatives: 0,
+};
+
+export const statsFor = (session: GameSessionSummary, modality: Modality): AccuracyStats =>
+  session.stats[modality] ?? EMPTY_STATS;
+
 const calculateRate = (numerator: number, denominator: number): number => {
   if (denominator === 0) {
     // If denominator is 0, it implies there were no opportunities for this event.
@@ -105,9 +179,10 @@ export const transformHistoryData = (sessions: GameSessionSummary[]) => {
     nLevel: session.settings.n_level,
     speed: session.settings.speed_ms,
     sessionLength: session.settings.session_length,
-    visualAccuracy: calculateAccuracy(session.visual_stats),
-    audioAccuracy: calculateAccuracy(session.audio_stats),
-    visualFalseAlarmRate: calculateFalseAlarmRate(session.visual_stats),
-    audioFalseAlarmRate: calculateFalseAlarmRate(session.audio_stats),
+    positionCount: positionCount(session.settings.grid_layout),
+    visualAccuracy: calculateAccuracy(statsFor(session, 'Position')),
+    audioAccuracy: calculateAccuracy(statsFor(session, 'Audio')),
+    visualFalseAlarmRate: calculateFalseAlarmRate(statsFor(session, 'Position')),
+    audioFalseAlarmRate: calculateFalseAlarmRate(statsFor(session, 'Audio')),
   }));
 };